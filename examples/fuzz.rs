@@ -0,0 +1,82 @@
+// 用随机生成的程序交叉检验各个编译阶段
+//
+//   cargo run --release --example fuzz -- [--seed N] [--count N] [--save DIR] [--reduce] [--print]
+//
+// 从 `--seed` 开始依次生成 `--count` 个程序，在所有优化等级和寄存器分配方式下运行并比较结果。
+// 出问题的程序写到 `--save` 指定的目录里，文件名是它的种子；加上 `--reduce` 时还会把化简后的程序
+// 写到同一目录下的 `种子.min.sy`。`--print` 只输出种子对应的程序。
+
+use std::path::PathBuf;
+use std::time::Instant;
+use sysy_compiler::fuzz::cross_check;
+use sysy_compiler::fuzz::generator::{generate, Config};
+use sysy_compiler::fuzz::reduce::reduce;
+
+/// 每次运行最多执行的指令条数
+const MAX_STEPS: u64 = 100_000_000;
+
+fn main(){
+    let mut seed = 0;
+    let mut count = 100;
+    let mut save = None;
+    let mut reduced = false;
+    let mut print = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next(){
+        let mut value = |name: &str| args.next().unwrap_or_else(|| usage(&format!("`{}` expects a value", name)));
+        match arg.as_str(){
+            "--seed" => seed = value("--seed").parse().unwrap_or_else(|_| usage("invalid seed")),
+            "--count" => count = value("--count").parse().unwrap_or_else(|_| usage("invalid count")),
+            "--save" => save = Some(PathBuf::from(value("--save"))),
+            "--reduce" => reduced = true,
+            "--print" => print = true,
+            _ => usage(&format!("unknown argument `{}`", arg)),
+        }
+    }
+    let config = Config::default();
+    if print{
+        print!("{}", generate(seed, &config));
+        return;
+    }
+    if reduced && save.is_none(){
+        usage("`--reduce` needs `--save`");
+    }
+    if let Some(dir) = &save{
+        std::fs::create_dir_all(dir).unwrap_or_else(|e| usage(&format!("cannot create `{}`: {}", dir.display(), e)));
+    }
+    // panic 由 `cross_check` 报告，不需要再打印一遍
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let start = Instant::now();
+    let mut failed = 0;
+    for seed in seed..seed + count{
+        let source = generate(seed, &config);
+        let failure = match cross_check(&source, &[], Some(MAX_STEPS)){
+            Ok(_) => continue,
+            Err(failure) => failure,
+        };
+        failed += 1;
+        println!("seed {}: {}", seed, failure);
+        if let Some(dir) = &save{
+            let path = dir.join(format!("{}.sy", seed));
+            std::fs::write(&path, &source).unwrap_or_else(|e| usage(&format!("cannot write `{}`: {}", path.display(), e)));
+            if !reduced{
+                continue;
+            }
+            if let Some(min) = reduce(&source, |text| failure.reproduces(text, &[], Some(MAX_STEPS))){
+                let path = dir.join(format!("{}.min.sy", seed));
+                std::fs::write(&path, min).unwrap_or_else(|e| usage(&format!("cannot write `{}`: {}", path.display(), e)));
+            }
+        }
+    }
+    println!("fuzz: {} programs, {} failed in {:.2}s", count, failed, start.elapsed().as_secs_f64());
+    if failed > 0{
+        std::process::exit(1);
+    }
+}
+
+fn usage(message: &str) -> !{
+    eprintln!("error: {}", message);
+    eprintln!("usage: fuzz [--seed N] [--count N] [--save DIR] [--reduce] [--print]");
+    std::process::exit(2);
+}
@@ -0,0 +1,61 @@
+// 把出问题的 SysY 程序化简到最小
+//
+//   cargo run --release --example reduce -- FILE [--input FILE] [--max-steps N]
+//
+// 先在所有阶段交叉检验 FILE，找到第一个问题（panic、拒绝编译、运行错误或结果不一致），
+// 再在语法树上删减、化简程序，只保留仍然有同样问题的版本，最后把最小的程序输出到标准输出。
+
+use std::time::Instant;
+use sysy_compiler::fuzz::cross_check;
+use sysy_compiler::fuzz::reduce::reduce;
+
+fn main(){
+    let mut file = None;
+    let mut input = Vec::new();
+    let mut max_steps: u64 = 10_000_000;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next(){
+        let mut value = |name: &str| args.next().unwrap_or_else(|| usage(&format!("`{}` expects a value", name)));
+        match arg.as_str(){
+            "--input" => {
+                let path = value("--input");
+                input = std::fs::read(&path).unwrap_or_else(|e| usage(&format!("cannot read `{}`: {}", path, e)));
+            }
+            "--max-steps" => max_steps = value("--max-steps").parse().unwrap_or_else(|_| usage("invalid step count")),
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg),
+            _ => usage(&format!("unknown argument `{}`", arg)),
+        }
+    }
+    let file = file.unwrap_or_else(|| usage("no input file"));
+    let source = std::fs::read_to_string(&file).unwrap_or_else(|e| usage(&format!("cannot read `{}`: {}", file, e)));
+    // panic 作为问题报告，不需要打印出来
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let start = Instant::now();
+    let failure = match cross_check(&source, &input, Some(max_steps)){
+        Ok(_) => {
+            eprintln!("reduce: all stages agree on `{}`, nothing to reduce", file);
+            std::process::exit(1);
+        }
+        Err(failure) => failure,
+    };
+    eprintln!("reduce: {}", failure);
+    let mut runs = 0;
+    let reduced = reduce(&source, |text| {
+        runs += 1;
+        failure.reproduces(text, &input, Some(max_steps))
+    });
+    let Some(reduced) = reduced else {
+        eprintln!("reduce: `{}` does not parse, or the failure does not reproduce after reprinting it", file);
+        std::process::exit(1);
+    };
+    print!("{}", reduced);
+    eprintln!("reduce: {} lines to {} lines after {} runs in {:.2}s",
+        source.lines().count(), reduced.lines().count(), runs, start.elapsed().as_secs_f64());
+}
+
+fn usage(message: &str) -> !{
+    eprintln!("error: {}", message);
+    eprintln!("usage: reduce FILE [--input FILE] [--max-steps N]");
+    std::process::exit(2);
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use sysy_compiler::{Emit, OptLevel, RegAllocKind};
+
+pub const USAGE: &str = "\
+Usage: SysYCompiler [OPTIONS] <INPUT>
+
+Compile a SysY source file. Use `-` as INPUT to read from stdin, except with
+-run-koopa and -run-riscv, where stdin is the input of the program being run.
+
+Options:
+  -koopa                 same as --emit=koopa
+  -riscv, -S             same as --emit=asm
+  -perf                  same as --emit=asm -O2
+  -run-koopa             interpret the optimized Koopa IR with stdin and
+                         stdout, exiting with the return value of `main`;
+                         produces no artifacts unless some are requested
+  -run-riscv             like -run-koopa, but simulate the generated RV32IM
+                         assembly; both report the executed instruction
+                         count on stderr
+  --emit=<KIND>[=<PATH>][,...]
+                         artifacts to produce, may be given several times;
+                         KIND is one of ast, koopa, koopa-opt, cfg, dom, loops,
+                         asm, regalloc
+  -O0, -O1, -O2          optimization level (default -O0); -O2 also inlines
+                         larger functions and runs the scalar passes twice
+  --regalloc=<ALLOC>     register allocator, `linear` (default) for linear
+                         scan or `graph` for graph coloring
+  --inline-threshold=<N> inline functions of at most N instructions at -O1
+                         and above (default 40, 160 at -O2, 0 disables
+                         inlining)
+  -o <PATH>              output file, `-` for stdout (default); with several
+                         artifacts the extension of PATH is replaced per kind
+  -h, --help             print this help
+";
+
+/// 输入或输出的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stream{
+    Stdio,
+    Path(PathBuf),
+}
+impl Stream{
+    fn from_arg(arg: &str) -> Stream{
+        if arg == "-"{
+            Stream::Stdio
+        } else {
+            Stream::Path(PathBuf::from(arg))
+        }
+    }
+}
+impl fmt::Display for Stream{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            Stream::Stdio => write!(f, "-"),
+            Stream::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// 编译之后直接运行程序的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Run{
+    /// 解释执行优化后的 Koopa IR
+    Koopa,
+    /// 模拟执行生成的汇编
+    Riscv,
+}
+
+/// 解析后的一次调用
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command{
+    Help,
+    Compile(Invocation),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Invocation{
+    pub input: Stream,
+    /// 每个产物以及它的输出位置，按命令行给出的顺序排列
+    pub outputs: Vec<(Emit, Stream)>,
+    pub opt_level: OptLevel,
+    pub reg_alloc: RegAllocKind,
+    pub inline_threshold: Option<usize>,
+    pub run: Option<Run>,
+}
+impl Invocation{
+    pub fn emits(&self) -> Vec<Emit>{
+        self.outputs.iter().map(|(emit, _)| *emit).collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError{
+    MissingInput,
+    MultipleInputs(String, String),
+    MissingValue(String),
+    UnknownFlag(String),
+    UnknownEmit(String),
+    UnknownRegAlloc(String),
+    InvalidNumber(String, String),
+    /// 源代码和被运行的程序不能同时读标准输入
+    RunFromStdin,
+}
+impl fmt::Display for CliError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            CliError::MissingInput => write!(f, "no input file"),
+            CliError::MultipleInputs(a, b) => write!(f, "more than one input file: `{}` and `{}`", a, b),
+            CliError::MissingValue(flag) => write!(f, "`{}` requires a value", flag),
+            CliError::UnknownFlag(flag) => write!(f, "unknown option `{}`", flag),
+            CliError::UnknownEmit(kind) => {
+                let kinds: Vec<&str> = Emit::ALL.iter().map(|e| e.name()).collect();
+                write!(f, "unknown emit kind `{}`, expected one of {}", kind, kinds.join(", "))
+            }
+            CliError::UnknownRegAlloc(name) => {
+                let names: Vec<&str> = RegAllocKind::ALL.iter().map(|k| k.name()).collect();
+                write!(f, "unknown register allocator `{}`, expected one of {}", name, names.join(", "))
+            }
+            CliError::InvalidNumber(flag, value) => write!(f, "invalid value `{}` for `{}`, expected a number", value, flag),
+            CliError::RunFromStdin => write!(f, "cannot read the source from stdin with `-run-koopa` or `-run-riscv`, \
+                the program being run reads stdin"),
+        }
+    }
+}
+
+/// 解析命令行参数（不含程序名）
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, CliError>{
+    let mut args = args.into_iter();
+    let mut input: Option<String> = None;
+    let mut output: Option<String> = None;
+    let mut emits: Vec<(Emit, Option<String>)> = Vec::new();
+    let mut opt_level: Option<OptLevel> = None;
+    let mut reg_alloc = RegAllocKind::default();
+    let mut inline_threshold: Option<usize> = None;
+    let mut perf = false;
+    let mut run = None;
+    while let Some(arg) = args.next(){
+        match arg.as_str(){
+            "-h" | "--help" => return Ok(Command::Help),
+            "-koopa" => emits.push((Emit::Koopa, None)),
+            "-riscv" | "-S" => emits.push((Emit::Asm, None)),
+            "-perf" => {
+                emits.push((Emit::Asm, None));
+                perf = true;
+            }
+            "-run-koopa" => run = Some(Run::Koopa),
+            "-run-riscv" => run = Some(Run::Riscv),
+            "-O0" => opt_level = Some(OptLevel::O0),
+            "-O1" => opt_level = Some(OptLevel::O1),
+            "-O2" => opt_level = Some(OptLevel::O2),
+            "-o" => output = Some(args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?),
+            "--emit" => {
+                let value = args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                parse_emit(&value, &mut emits)?;
+            }
+            _ if arg.starts_with("--emit=") => parse_emit(&arg["--emit=".len()..], &mut emits)?,
+            "--regalloc" => {
+                let value = args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                reg_alloc = parse_reg_alloc(&value)?;
+            }
+            _ if arg.starts_with("--regalloc=") => reg_alloc = parse_reg_alloc(&arg["--regalloc=".len()..])?,
+            "--inline-threshold" => {
+                let value = args.next().ok_or_else(|| CliError::MissingValue(arg.clone()))?;
+                inline_threshold = Some(parse_number("--inline-threshold", &value)?);
+            }
+            _ if arg.starts_with("--inline-threshold=") => {
+                inline_threshold = Some(parse_number("--inline-threshold", &arg["--inline-threshold=".len()..])?);
+            }
+            _ if arg.starts_with('-') && arg != "-" => return Err(CliError::UnknownFlag(arg)),
+            _ => {
+                if let Some(first) = input{
+                    return Err(CliError::MultipleInputs(first, arg));
+                }
+                input = Some(arg);
+            }
+        }
+    }
+    let input = Stream::from_arg(&input.ok_or(CliError::MissingInput)?);
+    if input == Stream::Stdio && run.is_some(){
+        return Err(CliError::RunFromStdin);
+    }
+    if emits.is_empty() && run.is_none(){
+        emits.push((Emit::Asm, None));
+    }
+    // `-riscv --emit=asm` 这样重复的请求只输出一次
+    let mut unique = Vec::new();
+    for emit in emits{
+        if !unique.contains(&emit){
+            unique.push(emit);
+        }
+    }
+    let emits = unique;
+    let opt_level = opt_level.unwrap_or(if perf {OptLevel::O2} else {OptLevel::O0});
+    let implicit = emits.iter().filter(|(_, path)| path.is_none()).count();
+    let outputs = emits.into_iter().map(|(emit, path)|{
+        let stream = match (path, &output){
+            (Some(path), _) => Stream::from_arg(&path),
+            (None, None) => Stream::Stdio,
+            (None, Some(output)) if implicit == 1 || output == "-" => Stream::from_arg(output),
+            (None, Some(output)) => Stream::Path(Path::new(output).with_extension(emit.extension())),
+        };
+        (emit, stream)
+    }).collect();
+    Ok(Command::Compile(Invocation{input, outputs, opt_level, reg_alloc, inline_threshold, run}))
+}
+
+fn parse_reg_alloc(value: &str) -> Result<RegAllocKind, CliError>{
+    RegAllocKind::from_name(value).ok_or_else(|| CliError::UnknownRegAlloc(value.to_string()))
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<usize, CliError>{
+    value.parse().map_err(|_| CliError::InvalidNumber(flag.to_string(), value.to_string()))
+}
+
+/// `kind[=path]` 用逗号分隔
+fn parse_emit(value: &str, emits: &mut Vec<(Emit, Option<String>)>) -> Result<(), CliError>{
+    for item in value.split(','){
+        let (kind, path) = match item.split_once('='){
+            Some((kind, path)) => (kind, Some(path.to_string())),
+            None => (item, None),
+        };
+        let emit = Emit::from_name(kind).ok_or_else(|| CliError::UnknownEmit(kind.to_string()))?;
+        emits.push((emit, path));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn parse(args: &str) -> Result<Command, CliError>{
+        parse_args(args.split_whitespace().map(String::from))
+    }
+
+    fn invocation(args: &str) -> Invocation{
+        match parse(args){
+            Ok(Command::Compile(invocation)) => invocation,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn legacy_modes(){
+        let inv = invocation("-koopa hello.c -o hello.koopa");
+        assert_eq!(inv.outputs, vec![(Emit::Koopa, Stream::Path("hello.koopa".into()))]);
+        assert_eq!(inv.opt_level, OptLevel::O0);
+        let inv = invocation("-perf hello.c -o hello.S");
+        assert_eq!(inv.outputs, vec![(Emit::Asm, Stream::Path("hello.S".into()))]);
+        assert_eq!(inv.opt_level, OptLevel::O2);
+        assert_eq!(inv.reg_alloc, RegAllocKind::LinearScan);
+    }
+
+    #[test]
+    fn register_allocator(){
+        let inv = invocation("--regalloc=graph --emit=asm,regalloc a.c");
+        assert_eq!(inv.reg_alloc, RegAllocKind::GraphColoring);
+        assert_eq!(inv.emits(), vec![Emit::Asm, Emit::RegAlloc]);
+        assert_eq!(invocation("--regalloc linear a.c").reg_alloc, RegAllocKind::LinearScan);
+        assert_eq!(parse("--regalloc=greedy a.c"), Err(CliError::UnknownRegAlloc("greedy".to_string())));
+    }
+
+    #[test]
+    fn inline_threshold(){
+        assert_eq!(invocation("a.c").inline_threshold, None);
+        assert_eq!(invocation("-O1 --inline-threshold=100 a.c").inline_threshold, Some(100));
+        assert_eq!(invocation("--inline-threshold 0 a.c").inline_threshold, Some(0));
+        assert_eq!(parse("--inline-threshold=big a.c"),
+                   Err(CliError::InvalidNumber("--inline-threshold".to_string(), "big".to_string())));
+    }
+
+    #[test]
+    fn several_emits(){
+        let inv = invocation("--emit=koopa,koopa-opt --emit cfg=g.dot -S -O1 a.c -o out/a.s");
+        assert_eq!(inv.input, Stream::Path("a.c".into()));
+        assert_eq!(inv.opt_level, OptLevel::O1);
+        assert_eq!(inv.outputs, vec![
+            (Emit::Koopa, Stream::Path("out/a.koopa".into())),
+            (Emit::KoopaOpt, Stream::Path("out/a.opt.koopa".into())),
+            (Emit::Cfg, Stream::Path("g.dot".into())),
+            (Emit::Asm, Stream::Path("out/a.S".into())),
+        ]);
+        let inv = invocation("--emit=ast,asm -");
+        assert_eq!(inv.input, Stream::Stdio);
+        assert_eq!(inv.outputs, vec![(Emit::Ast, Stream::Stdio), (Emit::Asm, Stream::Stdio)]);
+    }
+
+    #[test]
+    fn run_koopa(){
+        let inv = invocation("-run-koopa -O1 a.c");
+        assert_eq!(inv.run, Some(Run::Koopa));
+        assert!(inv.outputs.is_empty());
+        assert_eq!(invocation("-run-koopa -koopa a.c").emits(), vec![Emit::Koopa]);
+        assert_eq!(invocation("a.c").run, None);
+        assert_eq!(invocation("-perf -run-riscv a.c").run, Some(Run::Riscv));
+        assert_eq!(parse("-run-koopa -"), Err(CliError::RunFromStdin));
+    }
+
+    #[test]
+    fn errors(){
+        assert_eq!(parse("-koopa"), Err(CliError::MissingInput));
+        assert_eq!(parse("a.c -o"), Err(CliError::MissingValue("-o".to_string())));
+        assert_eq!(parse("-x a.c"), Err(CliError::UnknownFlag("-x".to_string())));
+        assert_eq!(parse("-O3 a.c"), Err(CliError::UnknownFlag("-O3".to_string())));
+        assert_eq!(parse("--emit=llvm a.c"), Err(CliError::UnknownEmit("llvm".to_string())));
+        assert_eq!(parse("a.c b.c"), Err(CliError::MultipleInputs("a.c".to_string(), "b.c".to_string())));
+        assert_eq!(parse("a.c --help"), Ok(Command::Help));
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+use crate::code_generator::machine::{AluOp, MachineInst, Reg};
+
+/// 汇编文本中的一行。后端直接生成 `MachineProgram`，这里用来读回输出的汇编
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line{
+    Inst(MachineInst),
+    Label(String),
+    /// `.text`、`.word 1` 这样的伪指令
+    Directive(String),
+    /// 注释和空行，原样保留
+    Comment(String),
+    /// 认不出的指令，原样保留
+    Other(String),
+}
+impl fmt::Display for Line{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            Line::Inst(inst) => write!(f, "\t{}", inst),
+            Line::Label(label) => write!(f, "{}:", label),
+            Line::Directive(text) => write!(f, "\t{}", text),
+            Line::Comment(text) | Line::Other(text) => f.write_str(text),
+        }
+    }
+}
+
+/// 把后端生成的汇编文本解析成一行一行的结构
+pub fn parse(asm: &str) -> Vec<Line>{
+    asm.lines().map(|line|{
+        let text = line.trim();
+        if text.is_empty() || text.starts_with('#'){
+            Line::Comment(line.to_string())
+        } else if let Some(label) = text.strip_suffix(':'){
+            Line::Label(label.to_string())
+        } else if text.starts_with('.'){
+            Line::Directive(text.to_string())
+        } else {
+            parse_inst(text).map_or_else(|| Line::Other(line.to_string()), Line::Inst)
+        }
+    }).collect()
+}
+
+fn parse_inst(text: &str) -> Option<MachineInst>{
+    let (name, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let args: Vec<&str> = rest.split(',').map(str::trim).filter(|arg| !arg.is_empty()).collect();
+    let reg = |i: usize| args.get(i).and_then(|arg| Reg::parse(arg));
+    let imm = |i: usize| args.get(i).and_then(|arg| arg.parse::<i32>().ok());
+    let label = |i: usize| args.get(i).map(|arg| arg.to_string());
+    // `offset(base)`
+    let mem = |i: usize| -> Option<(i32, Reg)>{
+        let (offset, base) = args.get(i)?.strip_suffix(')')?.split_once('(')?;
+        Some((offset.trim().parse().ok()?, Reg::parse(base.trim())?))
+    };
+    let inst = match (name, args.len()){
+        ("li", 2) => MachineInst::Li(reg(0)?, imm(1)?),
+        ("lui", 2) => MachineInst::Lui(reg(0)?, imm(1)?),
+        ("la", 2) => MachineInst::La(reg(0)?, label(1)?),
+        ("mv", 2) => MachineInst::Mv(reg(0)?, reg(1)?),
+        ("seqz", 2) => MachineInst::Seqz(reg(0)?, reg(1)?),
+        ("snez", 2) => MachineInst::Snez(reg(0)?, reg(1)?),
+        ("lw", 2) => {
+            let (offset, base) = mem(1)?;
+            MachineInst::Lw(reg(0)?, offset, base)
+        }
+        ("sw", 2) => {
+            let (offset, base) = mem(1)?;
+            MachineInst::Sw(reg(0)?, offset, base)
+        }
+        ("bnez", 2) => MachineInst::Bnez(reg(0)?, label(1)?),
+        ("beqz", 2) => MachineInst::Beqz(reg(0)?, label(1)?),
+        ("j", 1) => MachineInst::J(label(0)?),
+        ("call", 1) => MachineInst::Call(label(0)?),
+        ("ret", 0) => MachineInst::Ret,
+        (_, 3) => {
+            if let Some(op) = AluOp::ALL.iter().find(|op| op.name() == name){
+                MachineInst::Op(*op, reg(0)?, reg(1)?, reg(2)?)
+            } else {
+                let op = AluOp::ALL.iter().find(|op| op.has_imm() && op.imm_name() == name)?;
+                MachineInst::OpImm(*op, reg(0)?, reg(1)?, imm(2)?)
+            }
+        }
+        _ => return None,
+    };
+    Some(inst)
+}
+
+/// 输出成汇编文本
+pub fn print(lines: &[Line]) -> String{
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
@@ -4,8 +4,6 @@ use crate::optim::reg_alloc;
 use koopa::ir::types::{ TypeKind, Type };
 use crate::optim::check_used;
 use koopa::ir::values::{Binary, Return, BinaryOp, Alloc, Store, Load, Branch, Jump, Call, GetElemPtr, Aggregate, GetPtr};
-use lazy_static::lazy_static;
-use std::sync::Mutex;
 use std::cell::{Ref, RefCell};
 use std::cmp::min;
 use std::collections::{HashMap, HashSet, VecDeque};
@@ -13,12 +11,21 @@ use koopa::ir::entities::{Value, Program, FunctionData, ValueKind, ValueData};
 use koopa::ir::Function;
 use koopa::ir::ValueKind::Integer;
 use rand::Rng;
-use crate::{ActiveAnalysis, IntervalAnalysis};
+use crate::optim::cfg::{ActiveAnalysis, IntervalAnalysis};
 use crate::code_generator::code_generator::StorePos::Stack;
-use crate::optim::reg_alloc::RegAlloc;
+use crate::optim::reg_alloc::{is_callee_saved, reg_name, AllocStats, RegAlloc, RegAllocKind};
+use crate::optim::graph_coloring::GraphColoring;
+use crate::optim::split::{LiveRangeSplit, Split};
+use koopa::ir::BasicBlock;
+use crate::code_generator::machine::{AluOp, Data, MachineBasicBlock, MachineFunction, MachineGlobal, MachineInst, MachineProgram, Reg};
+use crate::code_generator::peephole::peephole;
 
 pub trait GenerateAsm{
-    fn generate(&self) -> String;
+    fn generate(&self, ctx: &mut CodegenContext) -> String;
+}
+/// 把 Koopa IR 翻译成机器指令
+pub trait LowerToMachine{
+    fn lower(&self, ctx: &mut CodegenContext) -> MachineProgram;
 }
 pub enum RegType{
     T(i32),
@@ -27,13 +34,13 @@ pub enum RegType{
 // todo 这里的allocator不是allocator，他只是一个item，之后可以在外面套一个真正的global allocator
 pub trait RegAlloctor{
     fn store_type_bound(&mut self, value: Value, store_type: StoreType);
-    fn get_offset_reg(&mut self, offset: i32) -> (String, i32);
+    fn get_offset_reg(&mut self, offset: i32) -> (Vec<MachineInst>, i32);
     fn alloc_tmp_reg(&mut self) -> Option<i32>;
     fn free_reg(&mut self, idx: i32) -> Option<i32>;
     fn alloc_stack_space(&mut self, value: Value, size: i32);
-    fn get_space(&mut self, value: Value) -> (StorePos, String);
-    fn return_reg(&mut self, value: Value) -> String;
-    fn borrow_reg(&mut self, value: &Value) -> (String, String);
+    fn get_space(&mut self, value: Value) -> (StorePos, Vec<MachineInst>);
+    fn return_reg(&mut self, value: Value) -> Vec<MachineInst>;
+    fn borrow_reg(&mut self, value: &Value) -> (Reg, Vec<MachineInst>);
     fn bound_stack_space(&mut self, value: &Value, offset: i32);
 }
 pub fn check_stmt_used(func_data: &FunctionData, value: &Value, map: &Ref<HashMap<Value,
@@ -54,13 +61,19 @@ pub enum StoreType{
     Point //the dimension of array
 }
 pub enum StorePos{
-    Reg(String),
-    Stack(String)
+    Reg(Reg),
+    Stack(Reg)
 }
 struct GlobalRegAlloctor{
     tmp_reg_pool: Vec<i32>,
     stack_allocation: HashMap<Value, i32>,
     reg_allocation: HashMap<Value, Option<i32>>,
+    /// 溢出的值在循环里拆分出的寄存器
+    splits: HashMap<Value, Split>,
+    /// 在这些块的跳转前读回、在这些块开头写回的值
+    reload_at: HashMap<BasicBlock, Vec<Value>>,
+    spill_at: HashMap<BasicBlock, Vec<Value>>,
+    current_bb: Option<BasicBlock>,
     store_type: HashMap<Value, StoreType>,
     borrowed_reg: HashMap<Value, VecDeque<(RegType, i32)>>,
     have_borrowed: VecDeque<i32>,
@@ -77,19 +90,75 @@ impl GlobalRegAlloctor{
         for i in (0..12) {
             queue.push_back(i);
         }
-        GlobalRegAlloctor{ tmp_reg_pool: v, reg_allocation: HashMap::new(), stack_allocation:
+        GlobalRegAlloctor{ tmp_reg_pool: v, reg_allocation: HashMap::new(), splits: HashMap::new(),
+            reload_at: HashMap::new(), spill_at: HashMap::new(), current_bb: None, stack_allocation:
         HashMap::new(), store_type: HashMap::new() ,borrowed_reg: HashMap::new(), offset: 0 ,
             start_offset: 0, have_borrowed: queue}
     }
-    fn fresh(&mut self, reg_allocation: HashMap<Value, Option<i32>>){
+    fn fresh(&mut self, reg_allocation: HashMap<Value, Option<i32>>, splits: Vec<(Value, Split)>){
         // self.reg_pool is no need to fresh, because at the end of a function, all reg is free
         self.reg_allocation = reg_allocation;
+        self.reload_at.clear();
+        self.spill_at.clear();
+        for (value, split) in &splits{
+            for &bb in &split.reload{
+                self.reload_at.entry(bb).or_default().push(*value);
+            }
+            for &bb in &split.spill{
+                self.spill_at.entry(bb).or_default().push(*value);
+            }
+        }
+        self.splits = splits.into_iter().collect();
+        self.current_bb = None;
         self.stack_allocation.clear();
         self.store_type.clear();
         self.offset = 0;
         self.start_offset = 0;
     }
 }
+impl GlobalRegAlloctor{
+    /// 值在当前基本块里所在的寄存器，溢出的值只有在拆分出的循环里才有寄存器
+    fn reg_of(&self, value: Value) -> Option<i32>{
+        if let Some(reg) = self.reg_allocation.get(&value).unwrap(){
+            return Some(*reg);
+        }
+        let split = self.splits.get(&value)?;
+        let bb = self.current_bb?;
+        if split.blocks.contains(&bb){
+            Some(split.reg)
+        } else {
+            None
+        }
+    }
+    /// 溢出的值在栈上的位置，第一次用到时分配
+    fn stack_slot(&mut self, value: Value) -> i32{
+        if !self.stack_allocation.contains_key(&value){
+            self.alloc_stack_space(value, 4);
+        }
+        self.stack_allocation[&value]
+    }
+    /// 拆分点上在栈和寄存器之间搬运值，`load` 为真时读进寄存器，否则写回栈上
+    fn move_split(&mut self, value: Value, load: bool) -> Vec<MachineInst>{
+        let reg = alloc_reg(self.splits[&value].reg);
+        let offset = self.stack_slot(value);
+        let (mut now, tmp) = self.get_offset_reg(offset);
+        now.push(MachineInst::Op(AluOp::Add, Reg::t(tmp), Reg::SP, Reg::t(tmp)));
+        now.push(if load {MachineInst::Lw(reg, 0, Reg::t(tmp))} else {MachineInst::Sw(reg, 0, Reg::t(tmp))});
+        self.free_reg(tmp);
+        now
+    }
+    /// 进入 `bb` 时把在循环里改过的拆分值写回栈上
+    fn enter_block(&mut self, bb: BasicBlock) -> Vec<MachineInst>{
+        self.current_bb = Some(bb);
+        let values = self.spill_at.get(&bb).cloned().unwrap_or_default();
+        values.into_iter().flat_map(|value| self.move_split(value, false)).collect()
+    }
+    /// 从 `bb` 跳进循环前把拆分值读进寄存器
+    fn leave_block(&mut self, bb: BasicBlock) -> Vec<MachineInst>{
+        let values = self.reload_at.get(&bb).cloned().unwrap_or_default();
+        values.into_iter().flat_map(|value| self.move_split(value, true)).collect()
+    }
+}
 impl RegAlloctor for GlobalRegAlloctor{
     fn bound_stack_space(&mut self, value: &Value, offset: i32) {
         self.stack_allocation.insert(value.clone(), offset);
@@ -99,8 +168,8 @@ impl RegAlloctor for GlobalRegAlloctor{
     }
     // given a offset, return a string that store this offset
     // to avoid the situation offset not in (-2047, 2048)
-    fn get_offset_reg(&mut self, offset: i32) -> (String, i32){
-        let mut s = "".to_string();
+    fn get_offset_reg(&mut self, offset: i32) -> (Vec<MachineInst>, i32){
+        let mut s = Vec::new();
         let reg;
         if offset >= (1 << 12){
             let div = offset / (1 << 12);
@@ -108,25 +177,25 @@ impl RegAlloctor for GlobalRegAlloctor{
             if quo < (1 << 11) {
                 let g = self.alloc_tmp_reg().unwrap();
                 reg = g;
-                s += &format!("\tlui t{}, {}\n", g, div);
-                s += &format!("\taddi t{}, t{}, {}\n", g, g, quo);
+                s.push(MachineInst::Lui(Reg::t(g), div));
+                s.push(MachineInst::OpImm(AluOp::Add, Reg::t(g), Reg::t(g), quo));
             } else {
                 let g = self.alloc_tmp_reg().unwrap();
                 reg = g;
-                s += &format!("\tlui t{}, {}\n", g, div + 1);
-                s += &format!("\taddi t{}, t{}, {}\n", g, g, quo - (1 << 12));
+                s.push(MachineInst::Lui(Reg::t(g), div + 1));
+                s.push(MachineInst::OpImm(AluOp::Add, Reg::t(g), Reg::t(g), quo - (1 << 12)));
             }
         } else if offset >= (1 << 11){
             let g = self.alloc_tmp_reg().unwrap();
             reg = g;
-            s += &format!("\tlui t{}, {}\n", g, 1);
-            s += &format!("\taddi t{}, t{}, {}\n", g, g, offset - (1 << 12));
+            s.push(MachineInst::Lui(Reg::t(g), 1));
+            s.push(MachineInst::OpImm(AluOp::Add, Reg::t(g), Reg::t(g), offset - (1 << 12)));
         } else {
             let g = self.alloc_tmp_reg().unwrap();
             reg = g;
-            s += &format!("\tli t{}, {}\n", g,  offset);
+            s.push(MachineInst::Li(Reg::t(g), offset));
         }
-        (s.to_string(), reg)
+        (s, reg)
     }
     fn alloc_tmp_reg(&mut self) -> Option<i32> {
         if !self.tmp_reg_pool.is_empty(){
@@ -162,32 +231,31 @@ impl RegAlloctor for GlobalRegAlloctor{
     ///     1. if temp reg not full, alloc a temp reg for it
     ///     2. else alloc a memory for any S_n reg, then move value to S_n, after borrowing,
     ///        store value back to memory, and recover S_n
-    fn get_space(&mut self, value: Value) -> (StorePos, String){
-        if let Some(reg) = self.reg_allocation.get(&value).unwrap(){
-            (StorePos::Reg(format!("s{}", *reg)), "".to_string())
+    fn get_space(&mut self, value: Value) -> (StorePos, Vec<MachineInst>){
+        if let Some(reg) = self.reg_of(value){
+            (StorePos::Reg(alloc_reg(reg)), Vec::new())
         } else if let Some(offset) = self.stack_allocation.get(&value){
-            let (load_string, reg_idx) = self.get_offset_reg(*offset);
-            let (idx, store_string) = self.borrow_reg(&value);
-            let now = store_string + &load_string + &format!("\tadd t{}, sp, t{}\n", reg_idx,
-                                                             reg_idx) +
-                &format!("\tlw {}, 0(t{})\n", idx, reg_idx);
+            let (load, reg_idx) = self.get_offset_reg(*offset);
+            let (idx, mut now) = self.borrow_reg(&value);
+            now.extend(load);
+            now.push(MachineInst::Op(AluOp::Add, Reg::t(reg_idx), Reg::SP, Reg::t(reg_idx)));
+            now.push(MachineInst::Lw(idx, 0, Reg::t(reg_idx)));
             self.free_reg(reg_idx);
-            (StorePos::Stack(format!("{}", idx)), now)
+            (StorePos::Stack(idx), now)
         } else if let None = self.reg_allocation.get(&value).unwrap(){ //这是为了解决第一次存储reg spill的数据
             self.alloc_stack_space(value, 4);
             self.get_space(value) // this branch will choose Some(offset)
         } else {
-            println!("{:#?}", value);
-            unreachable!()
+            unreachable!("{:#?} has no storage", value)
         }
         // todo:如果stack_allocation中没有offset?
     }
     /// before borrow reg, make sure before value is stored correctly, return the store code, and
     /// the reg idx
-    fn borrow_reg(&mut self, value: &Value) -> (String, String){
+    fn borrow_reg(&mut self, value: &Value) -> (Reg, Vec<MachineInst>){
         let idx = self.alloc_tmp_reg().unwrap();
-        let borrowed_reg = format!("t{}", idx);
-        let now = "".to_string();
+        let borrowed_reg = Reg::t(idx);
+        let now = Vec::new();
         if let Some(queue) = self.borrowed_reg.get_mut(value){
             queue.push_front((RegType::T(idx), 0));
         } else {
@@ -212,14 +280,14 @@ impl RegAlloctor for GlobalRegAlloctor{
     }
     /// after return reg, make sure last stored reg value is wrote back to reg, return the load code
     /// if the value doesn't borrow reg, do nothing
-    fn return_reg(&mut self, value: Value) -> String{
+    fn return_reg(&mut self, value: Value) -> Vec<MachineInst>{
         // self.free_reg()
         if let Some(deque) = self.borrowed_reg.get_mut(&value){
             if let Some((RegType::T(idx), offset)) = deque.pop_front(){
                 let store_offset  = self.stack_allocation.get(&value).unwrap();
-                let (store_before, store_idx) = self.get_offset_reg(*store_offset);
-                let now = store_before + &format!("\tadd t{}, sp, t{}\n\tsw t{}, 0(t{})\n",
-                                                          store_idx, store_idx, idx, store_idx);
+                let (mut now, store_idx) = self.get_offset_reg(*store_offset);
+                now.push(MachineInst::Op(AluOp::Add, Reg::t(store_idx), Reg::SP, Reg::t(store_idx)));
+                now.push(MachineInst::Sw(Reg::t(idx), 0, Reg::t(store_idx)));
                 self.free_reg(idx);
                 self.free_reg(store_idx);
                 now
@@ -228,7 +296,7 @@ impl RegAlloctor for GlobalRegAlloctor{
             }
         } else {
             //todo: 为什么这里要""?
-            "".to_string()
+            Vec::new()
         }
         // if let Some(deque) = self.borrowed_reg.get_mut(&value){
         //     if let Some((reg, offset)) = deque.pop_front(){
@@ -247,48 +315,79 @@ impl RegAlloctor for GlobalRegAlloctor{
         // }
     }
 }
-lazy_static!{
-    static ref global_reg_allocator: Mutex<RefCell<GlobalRegAlloctor>> = Mutex::new(RefCell::new
-        (GlobalRegAlloctor::new(0, 6)));
-    static ref global_function_name: Mutex<HashMap<Function, String>> = Mutex::new(HashMap::new());
-    static ref now_sp_size: Mutex<RefCell<i32>> = Mutex::new(RefCell::new(0));
-    static ref global_function_type: Mutex<HashMap<Function, String>> = Mutex::new
-    (HashMap::new());
-    static ref global_varable: Mutex<HashMap<Value, String>> = Mutex::new
-    (HashMap::new());
-    static ref global_variable_type: Mutex<HashMap<Value, (String, i32)>> = Mutex::new
-    (HashMap::new());
+/// 生成汇编时需要的全部状态，每次编译单独创建一份
+pub struct CodegenContext{
+    reg_allocator: GlobalRegAlloctor,
+    function_name: HashMap<Function, String>,
+    function_type: HashMap<Function, String>,
+    global_varable: HashMap<Value, String>,
+    global_variable_type: HashMap<Value, (String, i32)>,
+    now_sp_size: i32,
+    reg_alloc: RegAllocKind,
+    /// 输出汇编前是否对每个函数做窥孔优化
+    pub peephole: bool,
+    /// 生成汇编后，每个函数的寄存器分配统计
+    pub alloc_stats: Vec<AllocStats>,
+}
+impl CodegenContext{
+    pub fn new() -> CodegenContext{
+        CodegenContext::with_reg_alloc(RegAllocKind::default())
+    }
+    pub fn with_reg_alloc(reg_alloc: RegAllocKind) -> CodegenContext{
+        CodegenContext{
+            reg_allocator: GlobalRegAlloctor::new(0, 6),
+            function_name: HashMap::new(),
+            function_type: HashMap::new(),
+            global_varable: HashMap::new(),
+            global_variable_type: HashMap::new(),
+            now_sp_size: 0,
+            reg_alloc,
+            peephole: false,
+            alloc_stats: Vec::new(),
+        }
+    }
 }
-fn gen_agg_init(agg: &Aggregate, values: HashMap<Value, ValueData>) -> String{
-    let mut s = "".to_string();
+impl Default for CodegenContext{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+fn gen_agg_init(agg: &Aggregate, values: HashMap<Value, ValueData>) -> Vec<Data>{
+    let mut s = Vec::new();
     for value in agg.elems(){
         if let ValueKind::Integer(ii) = values.get(value).unwrap().kind(){
             if ii.value() == 0{
-                s += &format!("\t.zero 4\n");
+                s.push(Data::Zero(4));
             } else {
-                s += &format!("\t.word {}\n", ii.value());
+                s.push(Data::Word(ii.value()));
             }
         } else if let ValueKind::Aggregate(agg1) = values.get(value).unwrap().kind(){
-            s += &gen_agg_init(&agg1, values.clone());
+            s.extend(gen_agg_init(&agg1, values.clone()));
         } else {
-            println!("{}", values.get(value).unwrap().ty());
-            unreachable!();
+            unreachable!("unsupported aggregate element of type {}", values.get(value).unwrap().ty());
         }
     }
     s
 }
 impl GenerateAsm for Program{
-    fn generate(&self) -> String {
-        let mut s = "".to_string();
-        let mut m = global_function_name.lock().unwrap();
-        let mut t = global_function_type.lock().unwrap();
+    fn generate(&self, ctx: &mut CodegenContext) -> String {
+        let mut program = self.lower(ctx);
+        if ctx.peephole{
+            program.functions.iter_mut().for_each(peephole);
+        }
+        program.to_string()
+    }
+}
+impl LowerToMachine for Program{
+    fn lower(&self, ctx: &mut CodegenContext) -> MachineProgram{
+        let mut s = MachineProgram::default();
         //todo: global var的初始化的值表示了左值的下表，不是一个真值
         let values = self.borrow_values();
-        let vvalue = values.iter();
-        s += "\t.data\n";
-        for (val,val_data) in vvalue{
-            let mut var = global_varable.lock().unwrap();
-            let mut var_type = global_variable_type.lock().unwrap();
+        // 按定义的顺序输出，这样汇编是确定的
+        for val in self.inst_layout(){
+            let val_data = &values[val];
+            let var = &mut ctx.global_varable;
+            let var_type = &mut ctx.global_variable_type;
             if let Some(name) = val_data.name(){
                 if let TypeKind::Pointer(p) = val_data.ty().kind(){
                     if let TypeKind::Array(ty, _) = p.kind(){
@@ -300,24 +399,23 @@ impl GenerateAsm for Program{
                     var_type.insert(val.clone(), (val_data.ty().to_string(), val_data.ty().size() as i32));
                 }
                 var.insert(val.clone(), name.to_string());
-                s += &format!("\t.global {}\n",name[1..].to_string());
-                s += &format!("{}:\n",name[1..].to_string());
+                let mut global = MachineGlobal{name: name[1..].to_string(), init: Vec::new()};
                 if let ValueKind::GlobalAlloc(g) = val_data.kind(){
                     let init_value = values.get(&g.init());
                     if let Some(i) = init_value{
                         if let ValueKind::Integer(ii) = i.kind(){
                             if ii.value() == 0{
-                                s += &format!("\t.zero 4\n");
+                                global.init.push(Data::Zero(4));
                             } else {
-                                s += &format!("\t.word {}\n", ii.value());
+                                global.init.push(Data::Word(ii.value()));
                             }
                         } else if let ValueKind::Aggregate(agg) = i.kind(){
-                            s += &gen_agg_init(agg, values.clone());
+                            global.init = gen_agg_init(agg, values.clone());
                         } else if let ValueKind::ZeroInit(_) = i.kind(){
                             if let TypeKind::Array(arr_type, arr_size) = i.ty().kind(){
-                                s += &format!("\t.zero {}\n", arr_type.size() * *arr_size);
+                                global.init.push(Data::Zero(arr_type.size() * *arr_size));
                             } else {
-                                s += &format!("\t.zero {}\n", i.ty().size());
+                                global.init.push(Data::Zero(i.ty().size()));
                             }
                         } else {
                             eprintln!("{:#?}", i.kind());
@@ -329,39 +427,38 @@ impl GenerateAsm for Program{
                 } else {
                     unreachable!()
                 }
+                s.globals.push(global);
             }
         }
         let interval = self.get_interval();
-        let mut alloc_result = self.reg_alloc(interval);
+        let mut alloc_result = match ctx.reg_alloc{
+            RegAllocKind::LinearScan => self.reg_alloc(interval),
+            RegAllocKind::GraphColoring => self.graph_coloring(interval),
+        };
+        let mut split_result = self.split_live_ranges(&alloc_result);
         for &func in self.func_layout(){
-            m.insert(func.clone(), self.func(func).name().to_string());
+            ctx.function_name.insert(func.clone(), self.func(func).name().to_string());
             if let TypeKind::Function(_, a) = self.func(func).ty().kind(){
                 if let TypeKind::Unit = a.kind(){
-                    t.insert(func.clone(), "void".to_string());
+                    ctx.function_type.insert(func.clone(), "void".to_string());
                 } else if let TypeKind::Int32 = a.kind(){
-                    t.insert(func.clone(), "i32".to_string());
+                    ctx.function_type.insert(func.clone(), "i32".to_string());
                 }
             }
         }
-        std::mem::drop(m);
-        std::mem::drop(t);
         for &func in self.func_layout(){
             let tmp = &self.func(func).name().to_string()[1..];
             if is_lib(tmp){
                 continue;
             }
             {
-                let mut m = global_reg_allocator.lock().unwrap();
-                let g = m.borrow_mut().get_mut();
                 let alloc = alloc_result.remove(&func).unwrap();
-
-                g.fresh(alloc);
+                let splits = split_result.remove(&func).unwrap_or_default();
+                ctx.alloc_stats.push(AllocStats::new(self.func(func), &alloc, &splits));
+                let g = &mut ctx.reg_allocator;
+                g.fresh(alloc, splits);
             }
-            let mut head = "\t.text\n".to_string();
-            let mut func_def = "".to_string();
-            head += &format!("\t.global {}\n", tmp);
-            func_def += &self.func(func).generate(&values);
-            s += &(head + &func_def);
+            s.functions.push(self.func(func).lower(ctx, &values));
         }
         s
     }
@@ -376,24 +473,34 @@ fn is_lib(true_name: &str) -> bool{
         false
     }
 }
+/// 栈帧大小、超过 8 个的实参占用的字数和需要保存的 s 寄存器。
+/// 栈帧从 sp 往上依次是传出的实参、保存的 s 寄存器、局部变量和溢出的值，最高处是 ra
 enum Caller{
     Caller((i32, i32, HashSet<i32>)),
     Nocall((i32, i32, HashSet<i32>))
 }
-fn calculate_and_allocate_space(this: &FunctionData, reg_allocator: &HashMap<Value, Option<i32>>) ->
+fn calculate_and_allocate_space(this: &FunctionData, ctx: &mut CodegenContext) ->
                                                                                            Caller{
     let mut bits:i32 = 0;
     let mut arg_count_max = 0;
     let mut caller: bool = false;
     let mut vec = HashSet::with_capacity(12);
-    reg_allocator.iter().fold((&mut bits, &mut vec), |(sum, vec), (val, opt)|{
+    // 只有被调用者保存的寄存器需要在序言里保存，a 寄存器由调用者负责
+    ctx.reg_allocator.reg_allocation.iter().fold((&mut bits, &mut vec), |(sum, vec), (val, opt)|{
         if let Some(idx) = opt{
-            vec.insert(*idx);
+            if is_callee_saved(*idx){
+                vec.insert(*idx);
+            }
         } else {
             *sum += 4;
         }
         (sum, vec)
     });
+    for split in ctx.reg_allocator.splits.values(){
+        if is_callee_saved(split.reg){
+            vec.insert(split.reg);
+        }
+    }
     for (_, node) in this.layout().bbs(){
         for &inst in node.insts().keys(){
             let value_data = this.dfg().value(inst);
@@ -462,85 +569,201 @@ fn calculate_and_allocate_space(this: &FunctionData, reg_allocator: &HashMap<Val
     //     }
     // }
     if caller{
-        let mut m = now_sp_size.lock().unwrap();
         let sp = ((bits + 4 + (arg_count_max * 4) as i32 + 15) / 16) as i32 * 16;
-        *m.get_mut() = sp;
-        Caller::Caller((sp, arg_count_max as i32 + vec.len() as i32, vec))
+        ctx.now_sp_size = sp;
+        Caller::Caller((sp, arg_count_max, vec))
     } else {
-        let mut m = now_sp_size.lock().unwrap();
         let sp = ((bits + (arg_count_max * 4) as i32 + 15) / 16) as i32 * 16;
-        *m.get_mut() = sp;
-        Caller::Nocall((sp, arg_count_max as i32 + vec.len() as i32, vec))
+        ctx.now_sp_size = sp;
+        Caller::Nocall((sp, arg_count_max, vec))
     }
 }
-fn save_and_recover_reg(set: &HashSet<i32>) -> (String, String){
-    let mut s = ("".to_string(), "".to_string());
-    let mut sp = 0;
-    set.iter().fold((&mut s.0, &mut s.1), |(save, recover), idx|{
-        *save += &format!("\tsw s{} ,{}(sp)\n", idx, sp);
-        *recover = format!("\tlw s{}, {}(sp)\n", idx, sp) + recover;
+/// 在传出实参区之上（从 `base` 开始）保存和恢复 s 寄存器
+fn save_and_recover_reg(set: &HashSet<i32>, base: i32) -> (Vec<MachineInst>, Vec<MachineInst>){
+    let mut s = (Vec::new(), Vec::new());
+    let mut sp = base;
+    let mut set: Vec<&i32> = set.iter().collect();
+    set.sort();
+    set.into_iter().fold((&mut s.0, &mut s.1), |(save, recover), idx|{
+        save.push(MachineInst::Sw(Reg::s(*idx), sp, Reg::SP));
+        recover.insert(0, MachineInst::Lw(Reg::s(*idx), sp, Reg::SP));
         sp += 4;
         (save, recover)
     });
     s
 }
-trait GenerateAsmFunc{
-    fn generate(&self, global_var: &Ref<HashMap<Value, ValueData>>) -> String;
+/// 寄存器分配结果里的编号对应的寄存器
+fn alloc_reg(idx: i32) -> Reg{
+    Reg::parse(&reg_name(idx)).unwrap()
+}
+/// 能直接写成 I 型指令的 `op` 与常数右操作数，返回运算和立即数
+fn imm_operand(op: BinaryOp, c: i32) -> Option<(AluOp, i32)>{
+    let fits = |c: i32| (-2048..2048).contains(&c);
+    match op{
+        BinaryOp::Add if fits(c) => Some((AluOp::Add, c)),
+        BinaryOp::Sub if c != i32::MIN && fits(-c) => Some((AluOp::Add, -c)),
+        BinaryOp::And if fits(c) => Some((AluOp::And, c)),
+        BinaryOp::Or if fits(c) => Some((AluOp::Or, c)),
+        BinaryOp::Xor if fits(c) => Some((AluOp::Xor, c)),
+        BinaryOp::Shl => Some((AluOp::Sll, c & 31)),
+        BinaryOp::Shr => Some((AluOp::Srl, c & 31)),
+        BinaryOp::Sar => Some((AluOp::Sra, c & 31)),
+        _ => None,
+    }
+}
+
+/// 可以用乘法代替的除数：绝对值至少为 2 且不是 2 的幂（2 的幂在 IR 上已经换成移位）
+fn magic_divisor(op: BinaryOp, d: i32) -> Option<i32>{
+    let applies = matches!(op, BinaryOp::Div | BinaryOp::Mod) && d.unsigned_abs() >= 2 && !d.unsigned_abs().is_power_of_two();
+    applies.then_some(d)
+}
+
+/// 有符号除以常数 `d` 用的乘数 M 与移位量 s：`n / d = (mulh(n, M) ± n) >> s`，再对负数加一。
+/// 算法见 Hacker's Delight 10-4
+fn magic(d: i32) -> (i32, u32){
+    let two31: u32 = 1 << 31;
+    let ad = d.unsigned_abs();
+    let t = two31 + ((d as u32) >> 31);
+    let anc = t - 1 - t % ad;
+    let mut p = 31;
+    let (mut q1, mut r1) = (two31 / anc, two31 - two31 / anc * anc);
+    let (mut q2, mut r2) = (two31 / ad, two31 - two31 / ad * ad);
+    loop{
+        p += 1;
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= anc{
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(anc);
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= ad{
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(ad);
+        }
+        let delta = ad - r2;
+        if !(q1 < delta || (q1 == delta && r1 == 0)){
+            break;
+        }
+    }
+    let m = q2.wrapping_add(1) as i32;
+    (if d < 0 {m.wrapping_neg()} else {m}, p - 32)
 }
-impl GenerateAsmFunc for FunctionData{
-    fn generate(&self, global_var: &Ref<HashMap<Value, ValueData>>) -> String{
-        let mut s = "".to_string();
-        s += &format!("{}:\n", &self.name().to_string()[1..]);
+
+/// `dst = n / d` 或 `dst = n % d`，`tmp` 是可以改写的临时寄存器
+fn div_by_constant(g: &mut GlobalRegAlloctor, op: BinaryOp, dst: Reg, n: Reg, tmp: Reg, d: i32) -> Vec<MachineInst>{
+    let (m, shift) = magic(d);
+    let q_idx = g.alloc_tmp_reg().unwrap();
+    let q = Reg::t(q_idx);
+    let mut s = vec![MachineInst::Li(tmp, m), MachineInst::Op(AluOp::Mulh, q, n, tmp)];
+    if d > 0 && m < 0{
+        s.push(MachineInst::Op(AluOp::Add, q, q, n));
+    } else if d < 0 && m > 0{
+        s.push(MachineInst::Op(AluOp::Sub, q, q, n));
+    }
+    if shift > 0{
+        s.push(MachineInst::OpImm(AluOp::Sra, q, q, shift as i32));
+    }
+    // 商为负时向零取整
+    s.push(MachineInst::OpImm(AluOp::Srl, tmp, q, 31));
+    if op == BinaryOp::Div{
+        s.push(MachineInst::Op(AluOp::Add, dst, q, tmp));
+    } else {
+        s.push(MachineInst::Op(AluOp::Add, q, q, tmp));
+        s.push(MachineInst::Li(tmp, d));
+        s.push(MachineInst::Op(AluOp::Mul, q, q, tmp));
+        s.push(MachineInst::Op(AluOp::Sub, dst, n, q));
+    }
+    g.free_reg(q_idx);
+    s
+}
+
+/// 同时完成一组寄存器复制 (目标, 来源)，目标互不相同；
+/// 复制成环时借一个临时寄存器保存被覆盖的值
+fn parallel_move(g: &mut GlobalRegAlloctor, moves: Vec<(Reg, Reg)>) -> Vec<MachineInst>{
+    let mut s = Vec::new();
+    let mut pending: Vec<(Reg, Reg)> = moves.into_iter().filter(|(dst, src)| dst != src).collect();
+    let mut tmps = Vec::new();
+    while !pending.is_empty(){
+        let ready = pending.iter().position(|(dst, _)| pending.iter().all(|(_, src)| src != dst));
+        if let Some(i) = ready{
+            let (dst, src) = pending.remove(i);
+            s.push(MachineInst::Mv(dst, src));
+        } else {
+            let dst = pending[0].0;
+            let tmp = g.alloc_tmp_reg().unwrap();
+            s.push(MachineInst::Mv(Reg::t(tmp), dst));
+            for (_, src) in pending.iter_mut(){
+                if *src == dst{
+                    *src = Reg::t(tmp);
+                }
+            }
+            tmps.push(tmp);
+        }
+    }
+    for tmp in tmps{
+        g.free_reg(tmp);
+    }
+    s
+}
+trait LowerFunction{
+    fn lower(&self, ctx: &mut CodegenContext, global_var: &Ref<HashMap<Value, ValueData>>) -> MachineFunction;
+}
+impl LowerFunction for FunctionData{
+    fn lower(&self, ctx: &mut CodegenContext, global_var: &Ref<HashMap<Value, ValueData>>) -> MachineFunction{
+        let mut func = MachineFunction{name: self.name()[1..].to_string(), blocks: Vec::new()};
+        // 序言放在入口块的开头
+        let mut s = MachineBasicBlock::new(None);
         let caller;
         {
-            let mut k = global_reg_allocator.lock().unwrap();
-            let mut m = k.get_mut();
-            caller = calculate_and_allocate_space(self, &m.reg_allocation);
+            caller = calculate_and_allocate_space(self, ctx);
         }
         let sp_len;
         let save_and_recover;
-        if let Caller::Caller((sp, offset, set)) = &caller{
-            let mut k = global_reg_allocator.lock().unwrap();
-            let mut m = k.get_mut();
-            save_and_recover = save_and_recover_reg(set);
+        if let Caller::Caller((sp, args, set)) = &caller{
+            let m = &mut ctx.reg_allocator;
+            save_and_recover = save_and_recover_reg(set, args * 4);
             sp_len = sp;
             let (ss, reg) = m.get_offset_reg(*sp);
-            s += &(ss + &format!("\tsub sp, sp, t{}\n", reg));
+            s.extend(ss);
+            s.push(MachineInst::Op(AluOp::Sub, Reg::SP, Reg::SP, Reg::t(reg)));
             m.free_reg(reg);
             let (ss, reg) = m.get_offset_reg(sp - 4);
-            s += &(ss + &format!("\tadd t{}, sp, t{}\n",reg, reg) +&format!("\tsw ra, 0(t{})\n", reg));
+            s.extend(ss);
+            s.push(MachineInst::Op(AluOp::Add, Reg::t(reg), Reg::SP, Reg::t(reg)));
+            s.push(MachineInst::Sw(Reg::RA, 0, Reg::t(reg)));
             m.free_reg(reg);
-            m.offset = offset * 4;
-            m.start_offset = offset * 4;
+            m.offset = (args + set.len() as i32) * 4;
+            m.start_offset = m.offset;
             // m.free_reg(mid_reg);
-        } else if let Caller::Nocall((sp, offset, set)) = &caller{
-            save_and_recover = save_and_recover_reg(set);
-            let mut k = global_reg_allocator.lock().unwrap();
-            let mut m = k.get_mut();
+        } else if let Caller::Nocall((sp, args, set)) = &caller{
+            save_and_recover = save_and_recover_reg(set, args * 4);
+            let m = &mut ctx.reg_allocator;
             sp_len = sp;
             let (ss, reg) = m.get_offset_reg(*sp);
-            s += &(ss + &format!("\tsub sp, sp, t{}\n", reg));
+            s.extend(ss);
+            s.push(MachineInst::Op(AluOp::Sub, Reg::SP, Reg::SP, Reg::t(reg)));
             m.free_reg(reg);
-            m.offset = offset * 4;
-            m.start_offset = offset * 4;
+            m.offset = (args + set.len() as i32) * 4;
+            m.start_offset = m.offset;
         } else {
             unreachable!()
         }
         //todo: save the s_n reg
-        s += &save_and_recover.0;
+        s.extend(save_and_recover.0.clone());
         for (&bb, node) in self.layout().bbs(){
             if let Some(data) = self.dfg().bbs().get(&bb){
                 if let Some(a) = &data.name(){
                     let k = a.to_string();
                     let len = k.len();
                     let kk = k[1..len].to_string();
-                    if a == "%entry"{
-                        s += ""
-                    } else {
-                        s += &format!("{}:\n", kk);
+                    if a != "%entry"{
+                        func.blocks.push(std::mem::replace(&mut s, MachineBasicBlock::new(Some(kk))));
                     }
                 }
             }
+            s.extend(ctx.reg_allocator.enter_block(bb));
             for &inst in node.insts().keys(){
                 let value_data = self.dfg().value(inst);
                 match value_data.kind(){
@@ -548,9 +771,9 @@ impl GenerateAsmFunc for FunctionData{
                         // println!("{:#?}", ret);
                         // println!("{:#?}", self.dfg().value(ret.value().unwrap()));
                         // println!("=============================================");
-                        s += "# return gen\n";
-                        self.return_gen(&mut s, ret);
-                        s += "# return end\n\n"
+                        s.push(MachineInst::Comment("return gen".to_string()));
+                        self.return_gen(ctx, &mut s, ret);
+                        s.push(MachineInst::Comment("return end".to_string()));
                     }
                     ValueKind::Binary(bin) => {
                         // println!("{:#?}", bin);
@@ -560,55 +783,56 @@ impl GenerateAsmFunc for FunctionData{
                         // let l = bin.lhs();
                         // let r = bin.rhs();
                         // self.dfg().value(l).kind()
-                        s += "# bin gen\n";
+                        s.push(MachineInst::Comment("bin gen".to_string()));
                         if check_stmt_used(self, &inst, global_var){
-                            self.bin_gen(&mut s, bin, inst);
+                            self.bin_gen(ctx, &mut s, bin, inst);
                         }
-                        s += "# bin gen end\n\n";
+                        s.push(MachineInst::Comment("bin gen end".to_string()));
                     }
                     ValueKind::Store(store) => {
-                        s += "# store gen \n";
+                        s.push(MachineInst::Comment("store gen".to_string()));
                         if check_stmt_used(self, &inst, global_var){
-                            self.store_gen(&mut s, store, inst, global_var);
+                            self.store_gen(ctx, &mut s, store, inst, global_var);
                         }
-                        s += "# store gen end\n\n";
+                        s.push(MachineInst::Comment("store gen end".to_string()));
                     }
                     ValueKind::Load(load) => {
-                        s += "# load gen \n";
-                        self.load_gen(&mut s, load, inst);
-                        s += "# load gen end\n\n";
+                        s.push(MachineInst::Comment("load gen".to_string()));
+                        self.load_gen(ctx, &mut s, load, inst);
+                        s.push(MachineInst::Comment("load gen end".to_string()));
                     }
                     ValueKind::Alloc(alloc) => {
-                        s += "# alloc gen\n";
+                        s.push(MachineInst::Comment("alloc gen".to_string()));
                         if check_stmt_used(self, &inst, global_var){
-                            self.alloc_gen(&mut s, alloc, inst);
+                            self.alloc_gen(ctx, &mut s, alloc, inst);
                         }
-                        s += "# alloc gen end\n\n";
+                        s.push(MachineInst::Comment("alloc gen end".to_string()));
                     }
                     ValueKind::Branch(branch) => {
-                        s += "# branch gen\n";
-                        self.branch_gen(&mut s, branch, inst);
-                        s += "# branch gen end\n\n";
+                        s.push(MachineInst::Comment("branch gen".to_string()));
+                        self.branch_gen(ctx, &mut s, branch, inst);
+                        s.push(MachineInst::Comment("branch gen end".to_string()));
                     }
                     ValueKind::Jump(jump) => {
-                        s += "# jump gen\n";
-                        self.jump_gen(&mut s, jump, inst);
-                        s += "# jump gen\n\n";
+                        s.push(MachineInst::Comment("jump gen".to_string()));
+                        s.extend(ctx.reg_allocator.leave_block(bb));
+                        self.jump_gen(ctx, &mut s, jump, inst);
+                        s.push(MachineInst::Comment("jump gen end".to_string()));
                     }
                     ValueKind::Call(call) => {
-                        s += "# call gen\n";
-                        self.call_gen(&mut s, call, inst);
-                        s += "# call gen end\n\n";
+                        s.push(MachineInst::Comment("call gen".to_string()));
+                        self.call_gen(ctx, &mut s, call, inst);
+                        s.push(MachineInst::Comment("call gen end".to_string()));
                     }
                     ValueKind::GetElemPtr(get_elem_ptr) => {
-                        s += "# get elem ptr gen\n";
-                        self.get_elem_ptr_gen(&mut s, get_elem_ptr, inst);
-                        s += "# get elem ptr gen end\n\n";
+                        s.push(MachineInst::Comment("get elem ptr gen".to_string()));
+                        self.get_elem_ptr_gen(ctx, &mut s, get_elem_ptr, inst);
+                        s.push(MachineInst::Comment("get elem ptr gen end".to_string()));
                     }
                     ValueKind::GetPtr(get_ptr) => {
-                        s += "# get ptr\n";
-                        self.get_ptr(&mut s, get_ptr, inst);
-                        s += "# get ptr end\n";
+                        s.push(MachineInst::Comment("get ptr".to_string()));
+                        self.get_ptr(ctx, &mut s, get_ptr, inst);
+                        s.push(MachineInst::Comment("get ptr end".to_string()));
                     }
                     _ => unreachable!(),
                 }
@@ -620,47 +844,48 @@ impl GenerateAsmFunc for FunctionData{
                     // println!("{}",a);
                     if *a == end_name{
                         //todo: recover the s_n reg
-                        s += &save_and_recover.1;
+                        s.extend(save_and_recover.1.clone());
                         if let Caller::Caller((sp, _, _)) = caller{
-                            let mut k = global_reg_allocator.lock().unwrap();
-                            let m = k.get_mut();
+                            let m = &mut ctx.reg_allocator;
                             let (ss, reg) = m.get_offset_reg(sp - 4);
-                            s += &(ss + &format!("\tadd t{}, sp, t{}\n",reg, reg) + &format!("\tlw ra, 0(t{})\n", reg));
+                            s.extend(ss);
+                            s.push(MachineInst::Op(AluOp::Add, Reg::t(reg), Reg::SP, Reg::t(reg)));
+                            s.push(MachineInst::Lw(Reg::RA, 0, Reg::t(reg)));
                             m.free_reg(reg);
                         }
-                        let mut k = global_reg_allocator.lock().unwrap();
-                        let m = k.get_mut();
+                        let m = &mut ctx.reg_allocator;
                         let (ss, reg) = m.get_offset_reg(*sp_len);
-                        s += &(ss + &format!("\tadd sp, sp, t{}\n",reg));
+                        s.extend(ss);
+                        s.push(MachineInst::Op(AluOp::Add, Reg::SP, Reg::SP, Reg::t(reg)));
                         m.free_reg(reg);
-                        s += &format!("\tret\n\n");
+                        s.push(MachineInst::Ret);
                     }
                 }
             }
         }
-        s
+        func.blocks.push(s);
+        func
     }
 }
 
 trait SplitGen {
-    fn return_gen(&self, s: &mut String, ret: &Return);
-    fn bin_gen(&self, s: &mut String, bin: &Binary, value: Value);
-    fn alloc_gen(&self, s: &mut String, alloc: &Alloc, value: Value);
-    fn load_gen(&self, s: &mut String, alloc: &Load, value: Value);
-    fn store_gen(&self, s: &mut String, alloc: &Store, value: Value, global_varable_ref:
+    fn return_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, ret: &Return);
+    fn bin_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, bin: &Binary, value: Value);
+    fn alloc_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, alloc: &Alloc, value: Value);
+    fn load_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, alloc: &Load, value: Value);
+    fn store_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, alloc: &Store, value: Value, global_varable_ref:
     &Ref<HashMap<Value, ValueData>>);
-    fn branch_gen(&self, s: &mut String, branch: &Branch, value: Value);
-    fn jump_gen(&self, s: &mut String, jump: &Jump, value: Value);
-    fn call_gen(&self, s: &mut String, call: &Call, value: Value);
-    fn get_elem_ptr_gen(&self, s: &mut String, get_elem_ptr: &GetElemPtr, value: Value);
-    fn get_ptr(&self, s: &mut String, get_ptr: &GetPtr, value: Value);
+    fn branch_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, branch: &Branch, value: Value);
+    fn jump_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, jump: &Jump, value: Value);
+    fn call_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, call: &Call, value: Value);
+    fn get_elem_ptr_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, get_elem_ptr: &GetElemPtr, value: Value);
+    fn get_ptr(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, get_ptr: &GetPtr, value: Value);
 }
 impl SplitGen for FunctionData {
-    fn get_ptr(&self, s: &mut String, get_ptr: &GetPtr, value: Value){
+    fn get_ptr(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, get_ptr: &GetPtr, value: Value){
         let src = get_ptr.src();
         let idx = get_ptr.index();
-        let mut m = global_reg_allocator.lock().unwrap();
-        let g = m.get_mut();
+        let g = &mut ctx.reg_allocator;
         let mut ty_size = 0;
         let mut src_reg;
         let mut idx_reg;
@@ -674,20 +899,20 @@ impl SplitGen for FunctionData {
         let (ptr_reg_pos, ptr_begin) = g.get_space(value);
         if let StorePos::Stack(reg_name) = src_reg_pos{
             src_reg = reg_name;
-            *s += &src_begin;
+            s.extend(src_begin);
             recover_src = true;
         } else if let StorePos::Reg(reg_name) = src_reg_pos{
             src_reg = reg_name;
         } else {
-            let mut m = global_varable.lock().unwrap();
-            let k = m.get_mut(&src).unwrap();
+            let m = &ctx.global_varable;
+            let k = m.get(&src).unwrap();
             tmp_src_reg = g.alloc_tmp_reg().unwrap();
-            src_reg = format!("t{}", tmp_src_reg);
-            *s += &format!("\tla {}, {}\n",src_reg, k[1..].to_string());
+            src_reg = Reg::t(tmp_src_reg);
+            s.push(MachineInst::La(src_reg, k[1..].to_string()));
         }
         if let StorePos::Stack(reg_name) = ptr_reg_pos{
             ptr_reg = reg_name;
-            *s += &ptr_begin;
+            s.extend(ptr_begin);
             recover_ptr = true;
         } else if let StorePos::Reg(reg_name) = ptr_reg_pos{
             ptr_reg = reg_name;
@@ -695,16 +920,14 @@ impl SplitGen for FunctionData {
             unreachable!()
         }
         // g.store_type_bound(value, StoreType::Point);
-        let var_type = global_variable_type.lock().unwrap();
+        let var_type = &ctx.global_variable_type;
         if let Some((global_var, size)) = var_type.get(&src){
             if  global_var.as_bytes()[0] == b'*'{
-                println!("this is a global point of array:{}, size is {}", global_var, size);
                 ty_size = *size;
             }
         } else {
             if let TypeKind::Pointer(p) = self.dfg().value(src).ty().kind(){
                 if let TypeKind::Array(ty, size) = p.kind(){
-                    println!("this is a point of array:{}, the size is {}, inner type is {}", self.dfg().value(src).ty().to_string(), size, ty.size());
                     ty_size = ty.size() as i32 * (*size as i32);
                 } else {
                     ty_size = 4;
@@ -713,35 +936,36 @@ impl SplitGen for FunctionData {
         }
         if let ValueKind::Integer(i) =  self.dfg().value(idx).kind(){
             tmp_idx_reg = g.alloc_tmp_reg().unwrap();
-            idx_reg = format!("t{}", tmp_idx_reg);
-            *s += &format!("\tli {}, {}\n",idx_reg, i.value());
+            idx_reg = Reg::t(tmp_idx_reg);
+            s.push(MachineInst::Li(idx_reg, i.value()));
         } else{
             let (idx_pos, begin_idx) = g.get_space(idx);
             if let StorePos::Stack(reg_name) = idx_pos{
                 idx_reg = reg_name;
                 recover_idx = true;
-                *s += &begin_idx;
+                s.extend(begin_idx);
             } else if let StorePos::Reg(reg_name) = idx_pos{
                 idx_reg = reg_name;
             } else {
                 unreachable!()
             }
         }
+        // 偏移量算到临时寄存器里，下标的值之后可能还要用
         let type_size_reg = g.alloc_tmp_reg().unwrap();
-        *s += &format!("\tli t{}, {}\n", type_size_reg, ty_size);
-        *s += &format!("\tmul {}, {}, t{}\n",idx_reg, idx_reg, type_size_reg);
+        s.push(MachineInst::Li(Reg::t(type_size_reg), ty_size));
+        s.push(MachineInst::Op(AluOp::Mul, Reg::t(type_size_reg), idx_reg, Reg::t(type_size_reg)));
+        s.push(MachineInst::Op(AluOp::Add, ptr_reg, src_reg, Reg::t(type_size_reg)));
         g.free_reg(type_size_reg);
-        *s += &format!("\tadd {}, {}, {}\n",ptr_reg, src_reg, idx_reg);
         g.store_type_bound(value, StoreType::Point);
         // g.free_reg(s0);
         if recover_src{
-            *s += &g.return_reg(src);
+            s.extend(g.return_reg(src));
         }
         if recover_ptr{
-            *s += &g.return_reg(value);
+            s.extend(g.return_reg(value));
         }
         if recover_idx{
-            *s += &g.return_reg(idx);
+            s.extend(g.return_reg(idx));
         }
         if tmp_src_reg != -1{
             g.free_reg(tmp_src_reg);
@@ -750,29 +974,26 @@ impl SplitGen for FunctionData {
             g.free_reg(tmp_idx_reg);
         }
     }
-    fn get_elem_ptr_gen(&self, s: &mut String, get_elem_ptr: &GetElemPtr, value: Value){
+    fn get_elem_ptr_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, get_elem_ptr: &GetElemPtr, value: Value){
         let src = get_elem_ptr.src();
         let idx = get_elem_ptr.index();
         let mut idx_reg;
         let mut src_reg;
         let mut tmp_src_reg = -1;
         let mut tmp_idx_reg = -1;
-        let mut m = global_reg_allocator.lock().unwrap();
-        let mut global_var = global_varable.lock().unwrap();
-        let g = m.get_mut();
+        let g = &mut ctx.reg_allocator;
+        let global_var = &ctx.global_varable;
         let mut ty_size = 0;
         let mut recover_src = false;
         g.store_type_bound(value, StoreType::Point);
-        let var_type = global_variable_type.lock().unwrap();
+        let var_type = &ctx.global_variable_type;
         if let Some((global_var, size)) = var_type.get(&src){
             if  global_var.as_bytes()[0] == b'*'{
-                println!("this is a global point of array:{}, size is {}", global_var, size);
                 ty_size = *size;
             }
         } else {
             if let TypeKind::Pointer(p) = self.dfg().value(src).ty().kind(){
                 if let TypeKind::Array(ty, size) = p.kind(){
-                    println!("this is a point of array, the size is {}, inner type is {}", size, ty.size());
                     ty_size = ty.size() as i32;
                 }
             }
@@ -780,8 +1001,8 @@ impl SplitGen for FunctionData {
         let mut recover_idx = false;
         if let ValueKind::Integer(i) =  self.dfg().value(idx).kind(){
             tmp_idx_reg = g.alloc_tmp_reg().unwrap();
-            idx_reg = format!("t{}", tmp_idx_reg);
-            *s += &format!("\tli {}, {}\n",idx_reg, i.value());
+            idx_reg = Reg::t(tmp_idx_reg);
+            s.push(MachineInst::Li(idx_reg, i.value()));
         } else{
             let (idx_pos, begin_idx) = g.get_space(idx);
             if let StorePos::Reg(reg_name) = idx_pos{
@@ -789,7 +1010,7 @@ impl SplitGen for FunctionData {
             } else if let StorePos::Stack(reg_name) = idx_pos{
                 idx_reg = reg_name;
                 recover_idx = true;
-                *s += &begin_idx;
+                s.extend(begin_idx);
             } else {
                 unreachable!()
             }
@@ -798,8 +1019,8 @@ impl SplitGen for FunctionData {
         let mut reg_out = -1;
         if let Some(k) = global_var.get(&src){
             tmp_src_reg = g.alloc_tmp_reg().unwrap();
-            src_reg = format!("t{}", tmp_src_reg);
-            *s += &format!("\tla {}, {}\n",src_reg, k[1..].to_string());
+            src_reg = Reg::t(tmp_src_reg);
+            s.push(MachineInst::La(src_reg, k[1..].to_string()));
         }else if let ValueKind::GetElemPtr(_) = &self.dfg().value(src).kind() {
             let (src_pos, begin_src) = g.get_space(src.clone());
             if let StorePos::Reg(reg_name) = src_pos {
@@ -807,7 +1028,7 @@ impl SplitGen for FunctionData {
             } else if let StorePos::Stack(reg_name) = src_pos {
                 src_reg = reg_name;
                 recover_src = true;
-                *s += &begin_src;
+                s.extend(begin_src);
             } else {
                 unreachable!()
             }
@@ -818,7 +1039,7 @@ impl SplitGen for FunctionData {
             } else if let StorePos::Stack(reg_name) = src_pos {
                 src_reg = reg_name;
                 recover_src = true;
-                *s += &begin_src;
+                s.extend(begin_src);
             } else {
                 unreachable!()
             }
@@ -834,8 +1055,9 @@ impl SplitGen for FunctionData {
             // if let StoreType::Point = g.store_type.get(&src).unwrap(){
             //     *s += &(ss + &format!("\tadd t{}, sp, t{}\n",src_reg, src_reg)+ &format!("\tlw t{}, 0(t{})\n", src_reg, src_reg));
             // } else {
-            src_reg = format!("t{}", reg);
-            *s += &(ss + &format!("\tadd {}, sp, {}\n",src_reg, src_reg));
+            src_reg = Reg::t(reg);
+            s.extend(ss);
+            s.push(MachineInst::Op(AluOp::Add, src_reg, Reg::SP, src_reg));
             // }
             // *s += &format!("\tlw t{}, {}(sp)\n",src_reg, offset);
         } else {
@@ -843,14 +1065,13 @@ impl SplitGen for FunctionData {
         }
         //todo: 没有添加对大的type_size的特殊处理
         let type_size_reg = g.alloc_tmp_reg().unwrap();
-        *s += &format!("\tli t{}, {}\n", type_size_reg, ty_size); //todo:对不同维度有不同的值，需要判断
-        *s += &format!("\tmul {}, {}, t{}\n",idx_reg, idx_reg, type_size_reg);
-        g.free_reg(type_size_reg);
+        s.push(MachineInst::Li(Reg::t(type_size_reg), ty_size)); //todo:对不同维度有不同的值，需要判断
+        s.push(MachineInst::Op(AluOp::Mul, Reg::t(type_size_reg), idx_reg, Reg::t(type_size_reg)));
         let (ptr_pos, begin_ptr) = g.get_space(value);
         let mut recover_ptr = false;
         let mut ptr_reg;
         if let StorePos::Stack(reg_name) = ptr_pos{
-            *s += &begin_ptr;
+            s.extend(begin_ptr);
             recover_ptr = true;
             ptr_reg = reg_name;
         } else if let StorePos::Reg(reg_name) = ptr_pos{
@@ -858,19 +1079,20 @@ impl SplitGen for FunctionData {
         } else {
             unreachable!()
         }
-        *s += &format!("\tadd {}, {}, {}\n",ptr_reg, src_reg, idx_reg);
+        s.push(MachineInst::Op(AluOp::Add, ptr_reg, src_reg, Reg::t(type_size_reg)));
+        g.free_reg(type_size_reg);
         if reg_out != -1{
             g.free_reg(reg_out);
         }
         // g.bound_space(value, self.dfg().value(value).ty().size() as i32);
         if recover_idx{
-            *s += &g.return_reg(idx);
+            s.extend(g.return_reg(idx));
         }
         if recover_ptr{
-            *s += &g.return_reg(value);
+            s.extend(g.return_reg(value));
         }
         if recover_src{
-            *s += &g.return_reg(src);
+            s.extend(g.return_reg(src));
         }
         if tmp_src_reg != -1{
             g.free_reg(tmp_src_reg);
@@ -880,43 +1102,23 @@ impl SplitGen for FunctionData {
         }
     }
     //todo: 解决reg的问题
-    fn call_gen(&self, s: &mut String, call: &Call, value: Value) {
+    fn call_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, call: &Call, value: Value) {
         let arg_vec = call.args();
-        let mut len = arg_vec.len() as i32;
-        let mut m = global_reg_allocator.lock().unwrap();
-        let g = m.get_mut();
-        for i   in 0..min(len, 8){
-            let idx = i as usize;
-            if let ValueKind::Integer(int) = self.dfg().value(arg_vec[idx]).kind(){
-                *s += &format!("\tli a{}, {}\n", i, int.value());
-            } else {
-                let (arg_pos, beign_arg) = g.get_space(arg_vec[idx]);
-                let mut recover_arg = false;
-                let reg_idx;
-                if let StorePos::Reg(reg_name) = arg_pos{
-                    reg_idx = reg_name;
-                } else if let StorePos::Stack(reg_name) = arg_pos{
-                    reg_idx = reg_name;
-                    *s += &beign_arg;
-                    recover_arg = true;
-                } else{
-                    unreachable!()
-                }
-                *s += &(format!("\tmv a{}, {}\n", i, reg_idx));
-                if recover_arg{
-                    *s += &g.return_reg(arg_vec[idx]);
-                }
-            }
-        }
+        let len = arg_vec.len() as i32;
+        let g = &mut ctx.reg_allocator;
+        // 实参可能已经在某个 a 寄存器里，先把超过 8 个的实参写到栈上，
+        // 再把寄存器里的实参一起搬到 a 寄存器，最后处理常量和栈上的实参
         let mut idx = 8;
-        while len - 8 > 0{
+        while idx < arg_vec.len(){
             let value = arg_vec[idx];
             if let ValueKind::Integer(i) = self.dfg().value(value).kind(){
                 let tmp = g.alloc_tmp_reg().unwrap();
                 let offset = ((idx - 8) * 4) as i32;
                 let (ss, reg) = g.get_offset_reg(offset);
-                *s += &(ss +&format!("\tadd t{}, sp, t{}\n",reg, reg)+ &(format!("\tli t{}, {}\n",tmp , i.value()) +  &format!("\tsw t{}, 0(t{})\
-                \n", tmp,  reg)));
+                s.extend(ss);
+                s.push(MachineInst::Op(AluOp::Add, Reg::t(reg), Reg::SP, Reg::t(reg)));
+                s.push(MachineInst::Li(Reg::t(tmp), i.value()));
+                s.push(MachineInst::Sw(Reg::t(tmp), 0, Reg::t(reg)));
                 g.free_reg(reg);
                 g.free_reg(tmp);
             } else {
@@ -925,7 +1127,7 @@ impl SplitGen for FunctionData {
                 let mut recover_arg = false;
                 if let StorePos::Stack(reg_name) = arg_pos{
                     reg_idx = reg_name;
-                    *s += &begin_pos;
+                    s.extend(begin_pos);
                     recover_arg = true;
                 } else if let StorePos::Reg(reg_name) = arg_pos{
                     reg_idx = reg_name;
@@ -934,18 +1136,45 @@ impl SplitGen for FunctionData {
                 }
                 let offset = ((idx - 8) * 4) as i32;
                 let (ss, reg) = g.get_offset_reg(offset);
-                *s += &(ss + &format!("\tadd t{}, sp, t{}\n",reg, reg)+ &format!("\tsw {}, 0(t{})\n", reg_idx, reg));
+                s.extend(ss);
+                s.push(MachineInst::Op(AluOp::Add, Reg::t(reg), Reg::SP, Reg::t(reg)));
+                s.push(MachineInst::Sw(reg_idx, 0, Reg::t(reg)));
                 if recover_arg{
-                    *s += &g.return_reg(value);
+                    s.extend(g.return_reg(value));
                 }
                 g.free_reg(reg);
             }
-            len = len - 1;
             idx = idx + 1;
         }
-        let m = global_function_name.lock().unwrap();
-        *s += &format!("\tcall {}\n", m.get(&call.callee()).unwrap()[1..].to_string());
-        let t = global_function_type.lock().unwrap();
+        let mut moves = Vec::new();
+        for i in 0..min(len, 8){
+            let idx = i as usize;
+            if let ValueKind::Integer(_) = self.dfg().value(arg_vec[idx]).kind(){
+                continue;
+            }
+            if let Some(reg) = g.reg_of(arg_vec[idx]){
+                moves.push((Reg::a(i), alloc_reg(reg)));
+            }
+        }
+        s.extend(parallel_move(g, moves));
+        for i in 0..min(len, 8){
+            let idx = i as usize;
+            if let ValueKind::Integer(int) = self.dfg().value(arg_vec[idx]).kind(){
+                s.push(MachineInst::Li(Reg::a(i), int.value()));
+            } else if g.reg_of(arg_vec[idx]).is_none(){
+                let (arg_pos, beign_arg) = g.get_space(arg_vec[idx]);
+                if let StorePos::Stack(reg_name) = arg_pos{
+                    s.extend(beign_arg);
+                    s.push(MachineInst::Mv(Reg::a(i), reg_name));
+                    s.extend(g.return_reg(arg_vec[idx]));
+                } else{
+                    unreachable!()
+                }
+            }
+        }
+        let m = &ctx.function_name;
+        s.push(MachineInst::Call(m.get(&call.callee()).unwrap()[1..].to_string()));
+        let t = &ctx.function_type;
         let a = t.get(&call.callee()).unwrap();
         if a == "i32" && !self.dfg().value(value).used_by().is_empty(){
             let (rst_idx, rst_begin) = g.get_space(value);
@@ -953,7 +1182,7 @@ impl SplitGen for FunctionData {
             let mut recover_rst = false;
             if let StorePos::Stack(reg_name) = rst_idx{
                 reg = reg_name;
-                *s += &rst_begin;
+                s.extend(rst_begin);
                 recover_rst = true;
             } else if let StorePos::Reg(reg_name) = rst_idx{
                 reg = reg_name;
@@ -961,36 +1190,38 @@ impl SplitGen for FunctionData {
                 unreachable!()
             }
             g.store_type_bound(value, StoreType::Value);
-            *s += &(format!("\tmv {}, a0\n", reg));
+            if reg != Reg::a(0){
+                s.push(MachineInst::Mv(reg, Reg::a(0)));
+            }
             if recover_rst{
-                *s += &g.return_reg(value);
+                s.extend(g.return_reg(value));
             }
         }
     }
-    fn return_gen(&self, s: &mut String, ret: &Return) {
+    fn return_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, ret: &Return) {
         if let Some(val) = ret.value() {
             let a = self.dfg().value(val);
             let b = a.kind();
             match b {
                 ValueKind::Integer(i) =>
-                    *s += &format!("\tli a0, {}\n", i.value()),
+                    s.push(MachineInst::Li(Reg::a(0), i.value())),
                 _ =>{
-                    let mut g = global_reg_allocator.lock().unwrap();
-                    let r = g.borrow_mut().get_mut();
+                    let r = &mut ctx.reg_allocator;
                     // let offset = r.get_space(val).unwrap();
                     // let (ss, reg) = r.get_offset_reg(offset);
                     let (idx, before) = r.get_space(val);
                     let mut recover_idx = false;
                     if let StorePos::Stack(reg_name) = idx{
-                        *s += &before;
-                        *s += &(format!("\tmv a0, {}\n", reg_name));
+                        s.extend(before);
+                        s.push(MachineInst::Mv(Reg::a(0), reg_name));
                         recover_idx = true;
                     } else if let StorePos::Reg(reg_name) = idx{
-                        *s += &(before + &(format!("\tmv a0, {}\n", reg_name)) + &r.return_reg
-                        (val));
+                        s.extend(before);
+                        s.push(MachineInst::Mv(Reg::a(0), reg_name));
+                        s.extend(r.return_reg(val));
                     }
                     if recover_idx{
-                        *s += &r.return_reg(val);
+                        s.extend(r.return_reg(val));
                     }
                     // *s += &(ss + &format!("\tadd t{}, sp, t{}\n",reg, reg)+ &format!("\tlw a0, 0(t{})\n", reg));
                     // r.free_reg(reg);
@@ -998,16 +1229,14 @@ impl SplitGen for FunctionData {
             }
         }
     }
-    fn bin_gen(&self, s: &mut String, bin: &Binary, value: Value) {
+    fn bin_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, bin: &Binary, value: Value) {
         let r_value = bin.rhs();
         let l_value = bin.lhs();
-        let mut r_s:String;
-        let mut l_s:String;
+        let mut r_s:Reg;
+        let mut l_s:Reg;
         let op = bin.op();
-        let mut bin_operation:String;
         let mut idx;
-        let mut g = global_reg_allocator.lock().unwrap();
-        let r = g.borrow_mut().get_mut();
+        let r = &mut ctx.reg_allocator;
         let size = self.dfg().value(value).ty().size() as i32;
         r.store_type_bound(value, StoreType::Value);
         let mut tmp_r:i32 = 0;
@@ -1017,13 +1246,21 @@ impl SplitGen for FunctionData {
         let mut recover_r = false;
         let mut recover_l = false;
         let mut recover_i = false;
+        // 右操作数是常数时尽量用立即数形式，除以常数用乘法代替
+        let (r_imm, divisor) = match self.dfg().value(r_value).kind(){
+            ValueKind::Integer(i) => (imm_operand(op, i.value()), magic_divisor(op, i.value())),
+            _ => (None, None),
+        };
         if let ValueKind::Integer(i) = self.dfg().value(r_value).kind(){
-            if i.value() == 0 {
-                r_s = format!("x0");
+            if r_imm.is_some(){
+                // 用立即数时不需要寄存器
+                r_s = Reg::ZERO;
+            } else if i.value() == 0 {
+                r_s = Reg::ZERO;
             } else {
                 tmp_r = r.alloc_tmp_reg().unwrap();
-                *s += &format!("\tli t{}, {}\n", tmp_r,i.value());
-                r_s = format!("t{}", tmp_r);
+                s.push(MachineInst::Li(Reg::t(tmp_r), i.value()));
+                r_s = Reg::t(tmp_r);
                 r_is_integer = true;
             }
         } else {
@@ -1035,7 +1272,7 @@ impl SplitGen for FunctionData {
                 r_s = reg_name;
             } else if let StorePos::Stack(reg_name) = idx{
                 recover_r = true;
-                *s += &before;
+                s.extend(before);
                 r_s = reg_name;
             } else {
                 unreachable!()
@@ -1046,11 +1283,11 @@ impl SplitGen for FunctionData {
         }
         if let ValueKind::Integer(i) = self.dfg().value(l_value).kind(){
             if i.value() == 0{
-                l_s = format!("x0");
+                l_s = Reg::ZERO;
             } else {
                 tmp_l = r.alloc_tmp_reg().unwrap();
-                *s += &format!("\tli t{}, {}\n", tmp_l, i.value());
-                l_s = format!("t{}", tmp_l);
+                s.push(MachineInst::Li(Reg::t(tmp_l), i.value()));
+                l_s = Reg::t(tmp_l);
                 l_is_integer = true;
             }
         } else {
@@ -1066,7 +1303,7 @@ impl SplitGen for FunctionData {
                 l_s = reg_name;
             } else if let StorePos::Stack(reg_name) = idx{
                 recover_l = true;
-                *s += &before;
+                s.extend(before);
                 l_s = reg_name;
             } else {
                 unreachable!()
@@ -1081,92 +1318,60 @@ impl SplitGen for FunctionData {
         } else if let StorePos::Stack(reg_name) = reg{
             recover_i = true;
             idx = reg_name;
-            *s += &before;
+            s.extend(before);
         } else {
             unreachable!()
         }
+        let alu = |op: AluOp| MachineInst::Op(op, idx, l_s, r_s);
         match op{
-            BinaryOp::Add => {
-                bin_operation = "add".to_string();
-                *s += &format!("\t{} {}, {}, {}\n", bin_operation, idx, l_s, r_s);
+            _ if r_imm.is_some() => {
+                let (inst, imm) = r_imm.unwrap();
+                s.push(MachineInst::OpImm(inst, idx, l_s, imm));
             },
-            BinaryOp::Sub => {
-                bin_operation = "sub".to_string();
-                *s += &format!("\t{} {}, {}, {}\n", bin_operation, idx, l_s, r_s);
+            BinaryOp::Add => s.push(alu(AluOp::Add)),
+            BinaryOp::Sub => s.push(alu(AluOp::Sub)),
+            BinaryOp::Mul => s.push(alu(AluOp::Mul)),
+            BinaryOp::Div | BinaryOp::Mod if divisor.is_some() => {
+                s.extend(div_by_constant(r, op, idx, l_s, r_s, divisor.unwrap()));
             },
-            BinaryOp::Mul => {
-                bin_operation = "mul".to_string();
-                *s += &format!("\t{} {}, {}, {}\n", bin_operation, idx, l_s, r_s);
-            },
-            BinaryOp::Div => {
-                bin_operation = "div".to_string();
-                *s += &format!("\t{} {}, {}, {}\n", bin_operation, idx, l_s, r_s);
-            },
-            BinaryOp::Mod => {
-                bin_operation = "rem".to_string();
-                *s += &format!("\t{} {}, {}, {}\n", bin_operation, idx, l_s, r_s);
-            },
-            BinaryOp::Eq => {
-                let tmp = r.alloc_tmp_reg().unwrap();
-                *s += &format!("\txor t{}, {}, {}\n", tmp, l_s, r_s);
-                *s += &format!("\tseqz {}, t{}\n", idx, tmp);
-                r.free_reg(tmp);
-            },
-            BinaryOp::And => {
-                bin_operation = "and".to_string();
-                *s += &format!("\t{} {}, {}, {}\n", bin_operation, idx, l_s, r_s);
-            },
-            BinaryOp::Or => {
-                bin_operation = "or".to_string();
-                *s += &format!("\t{} {}, {}, {}\n", bin_operation, idx, l_s, r_s);
-            },
-            BinaryOp::NotEq => {
+            BinaryOp::Div => s.push(alu(AluOp::Div)),
+            BinaryOp::Mod => s.push(alu(AluOp::Rem)),
+            BinaryOp::Xor => s.push(alu(AluOp::Xor)),
+            BinaryOp::Shl => s.push(alu(AluOp::Sll)),
+            BinaryOp::Shr => s.push(alu(AluOp::Srl)),
+            BinaryOp::Sar => s.push(alu(AluOp::Sra)),
+            BinaryOp::And => s.push(alu(AluOp::And)),
+            BinaryOp::Or => s.push(alu(AluOp::Or)),
+            BinaryOp::Lt => s.push(alu(AluOp::Slt)),
+            BinaryOp::Gt => s.push(MachineInst::Op(AluOp::Slt, idx, r_s, l_s)),
+            BinaryOp::Eq | BinaryOp::NotEq => {
                 let tmp = r.alloc_tmp_reg().unwrap();
-                *s += &format!("\txor t{}, {}, {}\n", tmp, l_s, r_s);
-                *s += &format!("\tsnez {}, t{}\n", idx, tmp);
+                s.push(MachineInst::Op(AluOp::Xor, Reg::t(tmp), l_s, r_s));
+                s.push(if op == BinaryOp::Eq {MachineInst::Seqz(idx, Reg::t(tmp))} else {MachineInst::Snez(idx, Reg::t(tmp))});
                 r.free_reg(tmp);
             },
-            BinaryOp::Le => {
-                bin_operation = "slt".to_string();
+            // 相等或者小于（大于）
+            BinaryOp::Le | BinaryOp::Ge => {
+                let (lhs, rhs) = if op == BinaryOp::Le {(l_s, r_s)} else {(r_s, l_s)};
                 let tmp = r.alloc_tmp_reg().unwrap();
                 let tmp1 = r.alloc_tmp_reg().unwrap();
-                *s += &format!("\txor t{}, {}, {}\n", tmp, l_s, r_s);
-                *s += &format!("\tseqz {}, t{}\n", idx, tmp);
-                *s += &format!("\t{} t{}, {}, {}\n", bin_operation, tmp1, l_s, r_s);
-                *s += &format!("\tor {}, {}, t{}\n", idx, idx, tmp1);
+                s.push(MachineInst::Op(AluOp::Xor, Reg::t(tmp), l_s, r_s));
+                s.push(MachineInst::Seqz(idx, Reg::t(tmp)));
+                s.push(MachineInst::Op(AluOp::Slt, Reg::t(tmp1), lhs, rhs));
+                s.push(MachineInst::Op(AluOp::Or, idx, idx, Reg::t(tmp1)));
                 r.free_reg(tmp);
                 r.free_reg(tmp1);
             },
-            BinaryOp::Lt => {
-                bin_operation = "slt".to_string();
-                *s += &format!("\t{} {}, {}, {}\n", bin_operation, idx, l_s, r_s);
-            },
-            BinaryOp::Gt => {
-                bin_operation = "slt".to_string();
-                *s += &format!("\t{} {}, {}, {}\n", bin_operation, idx, r_s, l_s);
-            },
-            BinaryOp::Ge =>{
-                bin_operation = "slt".to_string();
-                let tmp = r.alloc_tmp_reg().unwrap();
-                let tmp1 = r.alloc_tmp_reg().unwrap();
-                *s += &format!("\txor t{}, {}, {}\n", tmp, l_s, r_s);
-                *s += &format!("\tseqz {}, t{}\n", idx, tmp);
-                *s += &format!("\t{} t{}, {}, {}\n", bin_operation, tmp1, r_s, l_s);
-                *s += &format!("\tor {}, {}, t{}\n", idx, idx, tmp1);
-                r.free_reg(tmp);
-                r.free_reg(tmp1);
-            }
-            _ => bin_operation = "".to_string()
         }
         // todo: recover the reg
         if recover_i{
-            *s += &r.return_reg(value);
+            s.extend(r.return_reg(value));
         }
         if recover_l{
-            *s += &r.return_reg(l_value);
+            s.extend(r.return_reg(l_value));
         }
         if recover_r{
-            *s += &r.return_reg(r_value);
+            s.extend(r.return_reg(r_value));
         }
 
         if l_is_integer{
@@ -1182,15 +1387,14 @@ impl SplitGen for FunctionData {
         // r.free_reg(idx);
         // r.free_reg(reg);
     }
-    fn load_gen(&self, s: &mut String, load: &Load, value: Value) {
+    fn load_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, load: &Load, value: Value) {
         let src_value = load.src();
-        let var = global_varable.lock().unwrap();
+        let var = &ctx.global_varable;
         let mut offset = 0;
         let mut reg_idx;
         let mut src_reg_idx;
         let size = self.dfg().value(value).ty().size() as i32;
-        let mut m = global_reg_allocator.lock().unwrap();
-        let g = m.get_mut();
+        let g = &mut ctx.reg_allocator;
         // g.bound_space(value, size);
         // g.store_type_bound(value, StoreType::Value);
         // offset = g.get_space(value).unwrap();
@@ -1202,7 +1406,7 @@ impl SplitGen for FunctionData {
                 reg_idx = reg_name;
             } else if let StorePos::Stack(reg_name) = reg{
                 recover_i = true;
-                *s += &before;
+                s.extend(before);
                 reg_idx = reg_name;
             } else {
                 unreachable!()
@@ -1231,8 +1435,8 @@ impl SplitGen for FunctionData {
         // }
         if let Some(name) = var.get(&src_value){
             let tmp_reg = g.alloc_tmp_reg().unwrap();
-            *s += &format!("\tla t{}, {}\n\tlw {}, 0(t{})\n",tmp_reg, name[1..].to_string(),
-                           reg_idx, tmp_reg);
+            s.push(MachineInst::La(Reg::t(tmp_reg), name[1..].to_string()));
+            s.push(MachineInst::Lw(reg_idx, 0, Reg::t(tmp_reg)));
             g.free_reg(tmp_reg);
         } else {
             let (src_reg, src_before) = g.get_space(src_value);
@@ -1240,44 +1444,44 @@ impl SplitGen for FunctionData {
                 src_reg_idx = reg_name;
             } else if let StorePos::Stack(reg_name) = src_reg{
                 recover_s = true;
-                *s += &src_before;
+                s.extend(src_before);
                 src_reg_idx = reg_name;
             } else {
                 unreachable!()
             }
             if let ValueKind::GetPtr(_) = self.dfg().value(src_value).kind(){
-                *s += &format!("\tlw {}, 0({})\n", reg_idx, src_reg_idx);
+                s.push(MachineInst::Lw(reg_idx, 0, src_reg_idx));
             } else if let ValueKind::GetElemPtr(_) = self.dfg().value(src_value).kind(){
-                *s += &format!("\tlw {}, 0({})\n", reg_idx, src_reg_idx);
-            } else {
-                *s += &format!("\tmv {}, {}\n", reg_idx, src_reg_idx);
+                s.push(MachineInst::Lw(reg_idx, 0, src_reg_idx));
+            } else if reg_idx != src_reg_idx{
+                s.push(MachineInst::Mv(reg_idx, src_reg_idx));
             }
         }
         if recover_i{
-            *s += &g.return_reg(value);
+            s.extend(g.return_reg(value));
         }
         if recover_s{
-            *s += &g.return_reg(load.src());
+            s.extend(g.return_reg(load.src()));
         }
         // g.free_reg(reg_idx);
     }
-    fn store_gen(&self, s: &mut String, store: &Store, value: Value, global_varable_ref:
+    fn store_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, store: &Store, value: Value, global_varable_ref:
     &Ref<HashMap<Value, ValueData>>) {
         let value = store.value();
         let dest = store.dest();
-        let var = global_varable.lock().unwrap();
-        let mut m = global_reg_allocator.lock().unwrap();
-        let g = m.get_mut();
-        let mut value_reg= "".to_string();
-        let mut dest_reg = "".to_string();
+        let var = &ctx.global_varable;
+        let g = &mut ctx.reg_allocator;
+        let value_reg;
+        let dest_reg;
         let mut recover_value = false;
         let mut recover_dest = false;
         if let Some(name) = var.get(&dest){
             if let ValueKind::Integer(i) = self.dfg().value(value).kind(){
                 let tmp_reg = g.alloc_tmp_reg().unwrap();
                 let reg_idx = g.alloc_tmp_reg().unwrap();
-                *s += &(format!("\tli t{}, {}\n",reg_idx, i.value()) + &format!("\tla t{}, \
-                {}\n\tsw t{}, 0(t{})\n", tmp_reg, name[1..].to_string(), reg_idx, tmp_reg));
+                s.push(MachineInst::Li(Reg::t(reg_idx), i.value()));
+                s.push(MachineInst::La(Reg::t(tmp_reg), name[1..].to_string()));
+                s.push(MachineInst::Sw(Reg::t(reg_idx), 0, Reg::t(tmp_reg)));
                 g.free_reg(tmp_reg);
                 g.free_reg(reg_idx);
             } else {
@@ -1287,14 +1491,14 @@ impl SplitGen for FunctionData {
                         value_reg = reg_name;
                     } else if let StorePos::Stack(reg_name) = src_reg_pos{
                         value_reg = reg_name;
-                        *s += &src_begin;
+                        s.extend(src_begin);
                         recover_value = true;
                     } else {
                         unreachable!()
                     }
                     let tmp = g.alloc_tmp_reg().unwrap();
-                    *s += &format!("\tla t{}, {}\n",tmp, name[1..].to_string());
-                    *s += &format!("\tsw {}, 0(t{})\n",value_reg, tmp);
+                    s.push(MachineInst::La(Reg::t(tmp), name[1..].to_string()));
+                    s.push(MachineInst::Sw(value_reg, 0, Reg::t(tmp)));
                     g.free_reg(tmp);
                 } else {
                     unreachable!()
@@ -1304,7 +1508,7 @@ impl SplitGen for FunctionData {
             if let (dest_reg_pos, dest_before) = g.get_space(dest){
                 if let StorePos::Stack(reg_name) = dest_reg_pos{
                     dest_reg = reg_name;
-                    *s += &dest_before;
+                    s.extend(dest_before);
                     recover_dest = true;
                 } else if let StorePos::Reg(reg_name) = dest_reg_pos{
                     dest_reg = reg_name;
@@ -1314,27 +1518,28 @@ impl SplitGen for FunctionData {
                 if let ValueKind::Integer(i) = self.dfg().value(value).kind(){
                     let tmp = g.alloc_tmp_reg().unwrap();
                     if let ValueKind::GetElemPtr(_) = self.dfg().value(dest).kind(){
-                        *s += &format!("\tli t{}, {}\n\tsw t{}, 0({})\n", tmp, i.value(), tmp,
-                                       dest_reg);
+                        s.push(MachineInst::Li(Reg::t(tmp), i.value()));
+                        s.push(MachineInst::Sw(Reg::t(tmp), 0, dest_reg));
                     } else if let ValueKind::GetPtr(_) = self.dfg().value(dest).kind(){
-                        *s += &format!("\tli t{}, {}\n\tsw t{}, 0({})\n", tmp, i.value(), tmp,
-                                       dest_reg);
+                        s.push(MachineInst::Li(Reg::t(tmp), i.value()));
+                        s.push(MachineInst::Sw(Reg::t(tmp), 0, dest_reg));
                     } else {
-                        *s += &format!("\tli t{}, {}\n\tmv {}, t{}\n", tmp, i.value(), dest_reg, tmp);
+                        s.push(MachineInst::Li(Reg::t(tmp), i.value()));
+                        s.push(MachineInst::Mv(dest_reg, Reg::t(tmp)));
                     }
                     g.free_reg(tmp);
                 } else {
                     //todo 传参数
                     if let ValueKind::FuncArgRef(func_arg_ref) = self.dfg().value(value).kind() {
                         if func_arg_ref.index() < 8 {
-                            *s += &(format!("\tmv {}, a{}\n", dest_reg, func_arg_ref.index()));
+                            s.push(MachineInst::Mv(dest_reg, Reg::a(func_arg_ref.index() as i32)));
                         } else {
-                            let mut k = now_sp_size.lock().unwrap();
-                            let sp_size = k.get_mut();
+                            let sp_size = &ctx.now_sp_size;
                             let src_offset = *sp_size + 4 * (func_arg_ref.index() as i32 - 8);
                             let (src_ss, src_reg) = g.get_offset_reg(src_offset);
-                            *s += &(src_ss + &format!("\tadd t{}, sp, t{}\n", src_reg, src_reg) +
-                                &format!("\tlw {}, 0(t{})\n", dest_reg, src_reg));
+                            s.extend(src_ss);
+                            s.push(MachineInst::Op(AluOp::Add, Reg::t(src_reg), Reg::SP, Reg::t(src_reg)));
+                            s.push(MachineInst::Lw(dest_reg, 0, Reg::t(src_reg)));
                             g.free_reg(src_reg);
                         }
                     } else if let (src_reg_pos, src_begin) = g.get_space(value){
@@ -1342,27 +1547,27 @@ impl SplitGen for FunctionData {
                             value_reg = reg_name;
                         } else if let StorePos::Stack(reg_name) = src_reg_pos{
                             value_reg = reg_name;
-                            *s += &src_begin;
+                            s.extend(src_begin);
                             recover_value = true;
                         } else {
                             unreachable!()
                         }
                         if let ValueKind::GetElemPtr(_) = self.dfg().value(dest).kind(){
-                            *s += &format!("\tsw {}, 0({})\n",value_reg, dest_reg);
+                            s.push(MachineInst::Sw(value_reg, 0, dest_reg));
                         } else if let ValueKind::GetPtr(_) = self.dfg().value(dest).kind(){
-                            *s += &format!("\tsw {}, 0({})\n",value_reg, dest_reg);
-                        } else {
-                            *s += &format!("\tmv {}, {}\n",dest_reg, value_reg);
+                            s.push(MachineInst::Sw(value_reg, 0, dest_reg));
+                        } else if dest_reg != value_reg{
+                            s.push(MachineInst::Mv(dest_reg, value_reg));
                         }
                     }
                 }
             }
         }
         if recover_value{
-            *s += &g.return_reg(value);
+            s.extend(g.return_reg(value));
         }
         if recover_dest{
-            *s += &g.return_reg(dest);
+            s.extend(g.return_reg(dest));
         }
         // if let Some(offset) = g.get_space(dest){
         //     if let ValueKind::Integer(i) = self.dfg().value(value).kind(){
@@ -1451,10 +1656,9 @@ impl SplitGen for FunctionData {
         //     }
         // }
     }
-    fn alloc_gen(&self, s: &mut String, alloc: &Alloc, value: Value) {
+    fn alloc_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, alloc: &Alloc, value: Value) {
         //todo: 对指针的处理
-        let mut m = global_reg_allocator.lock().unwrap();
-        let g = m.get_mut();
+        let g = &mut ctx.reg_allocator;
         let mut size = 0;
         if let TypeKind::Pointer(type_id)= self.dfg().value(value).ty().kind(){
             let i32_type = Type::get_i32();
@@ -1486,12 +1690,11 @@ impl SplitGen for FunctionData {
         //     size = self.dfg().value(value).ty().size() as i32;
         // }
     }
-    fn branch_gen(&self, s: &mut String, branch: &Branch, value: Value) {
+    fn branch_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, branch: &Branch, value: Value) {
         let cond = branch.cond();
         let then_branch = branch.true_bb();
         let else_branch = branch.false_bb();
-        let mut k = global_reg_allocator.lock().unwrap();
-        let g = k.get_mut();
+        let g = &mut ctx.reg_allocator;
         let mut reg_idx ;
         let mut recover_cond = false;
         // if let Some(offset) = g.borrow_mut().get_space(cond){
@@ -1501,14 +1704,14 @@ impl SplitGen for FunctionData {
         // }
         if let Integer(i) = self.dfg().value(cond).kind(){
             let reg_idx_i32 = g.borrow_mut().alloc_tmp_reg().unwrap();
-            reg_idx = format!("t{}", reg_idx_i32);
-            *s += &format!("\tli {}, {}\n", reg_idx, i.value());
+            reg_idx = Reg::t(reg_idx_i32);
+            s.push(MachineInst::Li(reg_idx, i.value()));
             g.borrow_mut().free_reg(reg_idx_i32);
         } else if let (reg, before) = g.get_space(cond){
             if let StorePos::Stack(reg_name) = reg{
                 reg_idx = reg_name;
                 recover_cond = true;
-                *s += &before;
+                s.extend(before);
             } else if let StorePos::Reg(reg_name) = reg{
                 reg_idx = reg_name;
             } else {
@@ -1520,7 +1723,7 @@ impl SplitGen for FunctionData {
         if let Some(then_data) = self.dfg().bbs().get(&then_branch){
             if let Some(then_name) = then_data.name(){
                 let kk = then_name.to_string()[1..then_name.len()].to_string();
-                *s += &format!("\tbnez {}, {}\n", reg_idx, kk);
+                s.push(MachineInst::Bnez(reg_idx, kk));
             }
         } else {
             unreachable!()
@@ -1528,21 +1731,70 @@ impl SplitGen for FunctionData {
         if let Some(else_data) = self.dfg().bbs().get(&else_branch){
             if let Some(else_name) = else_data.name(){
                 let kk = else_name.to_string()[1..else_name.len()].to_string();
-                *s += &format!("\tj {}\n\n", kk);
+                s.push(MachineInst::J(kk));
             }
         } else {
             unreachable!()
         }
         if recover_cond{
-            *s += &g.return_reg(cond);
+            s.extend(g.return_reg(cond));
         }
     }
-    fn jump_gen(&self, s: &mut String, jump: &Jump, value: Value) {
+    fn jump_gen(&self, ctx: &mut CodegenContext, s: &mut MachineBasicBlock, jump: &Jump, value: Value) {
         let target = jump.target();
         if let Some(bd) = self.dfg().bbs().get(&target){
             if let Some(name) = bd.name(){
                 let kk = name.to_string()[1..name.len()].to_string();
-                *s += &format!("\tj {}\n\n", kk);
+                s.push(MachineInst::J(kk));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::interpreter::riscv;
+    use crate::{compile, Emit, OptLevel, Options, RegAllocKind};
+
+    #[test]
+    fn division_by_constants_uses_magic_numbers(){
+        let mulh = |a: i32, b: i32| ((a as i64 * b as i64) >> 32) as i32;
+        let numerators = [0, 1, -1, 2, -2, 7, -7, 100, -100, 12345, -12345, i32::MAX, i32::MIN, i32::MAX - 1, i32::MIN + 1];
+        for d in (-1000..=1000).chain([641, -641, 1_000_000_007, i32::MAX, i32::MIN + 1]){
+            if magic_divisor(BinaryOp::Div, d).is_none(){
+                continue;
+            }
+            let (m, shift) = magic(d);
+            for &n in &numerators{
+                let mut q = mulh(n, m);
+                if d > 0 && m < 0{
+                    q = q.wrapping_add(n);
+                } else if d < 0 && m > 0{
+                    q = q.wrapping_sub(n);
+                }
+                q >>= shift;
+                q += ((q as u32) >> 31) as i32;
+                assert_eq!(q, n.wrapping_div(d), "{} / {}", n, d);
+            }
+        }
+    }
+
+    #[test]
+    fn stack_arguments_leave_saved_registers_alone(){
+        // mid 在 s0、s1 里保存了 main 的 a、b，传给 g9 的第 9、10 个实参不能写到它们的保存位置上
+        let source = "int g9(int a, int b, int c, int d, int e, int f, int g, int h, int i, int j) { \
+                return a + b + c + d + e + f + g + h + i * j; }\n\
+            int mid(int x) { int y = x * 7; int z = x + 5; return g9(1, 2, 3, 4, 5, 6, 7, 8, x, x * 3) + y * z; }\n\
+            int main() { int a = getint(); int b = a * 7; int c = a * a + 17; int d = c * c; int e = d * 13 + c; \
+                int m = mid(a); putint(a); putch(32); putint(b); putch(32); putint(c); putch(32); \
+                putint(d); putch(32); putint(e); putch(32); putint(m); return 0; }";
+        for opt_level in [OptLevel::O0, OptLevel::O1]{
+            for reg_alloc in RegAllocKind::ALL{
+                let options = Options{emit: vec![Emit::Asm], opt_level, reg_alloc, inline_threshold: Some(0)};
+                let output = compile(source, &options).unwrap();
+                let output = riscv::run(output.get(Emit::Asm).unwrap(), b"3", Some(1_000_000)).unwrap().output;
+                assert_eq!(String::from_utf8(output).unwrap(), "3 21 26 676 8814 231", "{:?} {:?}", opt_level, reg_alloc);
             }
         }
     }
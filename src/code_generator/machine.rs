@@ -0,0 +1,289 @@
+use std::fmt;
+
+/// RV32 的整数寄存器，保存的是 `x` 编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Reg(pub u8);
+
+const REG_NAMES: [&str; 32] = [
+    "x0", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+impl Reg{
+    pub const ZERO: Reg = Reg(0);
+    pub const RA: Reg = Reg(1);
+    pub const SP: Reg = Reg(2);
+    pub fn parse(name: &str) -> Option<Reg>{
+        match name{
+            "zero" => Some(Reg::ZERO),
+            "fp" => Some(Reg(8)),
+            _ => REG_NAMES.iter().position(|&n| n == name).map(|i| Reg(i as u8)),
+        }
+    }
+    /// `t0`-`t6`
+    pub fn t(n: i32) -> Reg{
+        match n{
+            0..=2 => Reg(5 + n as u8),
+            3..=6 => Reg(25 + n as u8),
+            _ => unreachable!("no register t{}", n),
+        }
+    }
+    /// `s0`-`s11`
+    pub fn s(n: i32) -> Reg{
+        match n{
+            0..=1 => Reg(8 + n as u8),
+            2..=11 => Reg(16 + n as u8),
+            _ => unreachable!("no register s{}", n),
+        }
+    }
+    /// `a0`-`a7`
+    pub fn a(n: i32) -> Reg{
+        match n{
+            0..=7 => Reg(10 + n as u8),
+            _ => unreachable!("no register a{}", n),
+        }
+    }
+    pub fn name(&self) -> &'static str{
+        REG_NAMES[self.0 as usize]
+    }
+    /// `t0`-`t6`：后端只在一条 IR 指令内部用它们做临时寄存器
+    pub fn is_temp(&self) -> bool{
+        matches!(self.0, 5..=7 | 28..=31)
+    }
+    /// 临时寄存器的编号，`t3` 为 3
+    pub fn temp_index(&self) -> Option<i32>{
+        match self.0{
+            5..=7 => Some(self.0 as i32 - 5),
+            28..=31 => Some(self.0 as i32 - 25),
+            _ => None,
+        }
+    }
+    /// `a0`-`a7`
+    pub fn is_arg(&self) -> bool{
+        matches!(self.0, 10..=17)
+    }
+}
+impl fmt::Display for Reg{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        f.write_str(self.name())
+    }
+}
+
+/// 三个寄存器的运算，其中一部分有立即数形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp{
+    Add, Sub, Mul, Mulh, Div, Rem, And, Or, Xor, Slt, Sltu, Sll, Srl, Sra,
+}
+impl AluOp{
+    pub const ALL: [AluOp; 14] = [AluOp::Add, AluOp::Sub, AluOp::Mul, AluOp::Mulh, AluOp::Div, AluOp::Rem, AluOp::And,
+        AluOp::Or, AluOp::Xor, AluOp::Slt, AluOp::Sltu, AluOp::Sll, AluOp::Srl, AluOp::Sra];
+    pub fn name(&self) -> &'static str{
+        match self{
+            AluOp::Add => "add",
+            AluOp::Sub => "sub",
+            AluOp::Mul => "mul",
+            AluOp::Mulh => "mulh",
+            AluOp::Div => "div",
+            AluOp::Rem => "rem",
+            AluOp::And => "and",
+            AluOp::Or => "or",
+            AluOp::Xor => "xor",
+            AluOp::Slt => "slt",
+            AluOp::Sltu => "sltu",
+            AluOp::Sll => "sll",
+            AluOp::Srl => "srl",
+            AluOp::Sra => "sra",
+        }
+    }
+    /// 立即数形式的指令名
+    pub fn imm_name(&self) -> String{
+        match self{
+            AluOp::Sltu => "sltiu".to_string(),
+            _ => format!("{}i", self.name()),
+        }
+    }
+    /// 是否有对应的 `addi` 这样的立即数指令（`sub` 没有）
+    pub fn has_imm(&self) -> bool{
+        !matches!(self, AluOp::Sub | AluOp::Mul | AluOp::Mulh | AluOp::Div | AluOp::Rem)
+    }
+    pub fn is_commutative(&self) -> bool{
+        matches!(self, AluOp::Add | AluOp::Mul | AluOp::Mulh | AluOp::And | AluOp::Or | AluOp::Xor)
+    }
+}
+
+/// 一条机器指令，只包含后端会生成的那些
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MachineInst{
+    Li(Reg, i32),
+    Lui(Reg, i32),
+    La(Reg, String),
+    Mv(Reg, Reg),
+    /// `op rd, rs1, rs2`
+    Op(AluOp, Reg, Reg, Reg),
+    /// `opi rd, rs1, imm`
+    OpImm(AluOp, Reg, Reg, i32),
+    Seqz(Reg, Reg),
+    Snez(Reg, Reg),
+    /// `lw rd, offset(base)`
+    Lw(Reg, i32, Reg),
+    /// `sw rs, offset(base)`
+    Sw(Reg, i32, Reg),
+    Bnez(Reg, String),
+    Beqz(Reg, String),
+    J(String),
+    Call(String),
+    Ret,
+    /// 输出成 `# ...` 的注释，标出每条 IR 指令生成的代码
+    Comment(String),
+}
+impl MachineInst{
+    /// 写入的寄存器。`call` 破坏的寄存器不算在内
+    pub fn def(&self) -> Option<Reg>{
+        match self{
+            MachineInst::Li(rd, _) | MachineInst::Lui(rd, _) | MachineInst::La(rd, _) | MachineInst::Mv(rd, _)
+            | MachineInst::Op(_, rd, _, _) | MachineInst::OpImm(_, rd, _, _) | MachineInst::Seqz(rd, _)
+            | MachineInst::Snez(rd, _) | MachineInst::Lw(rd, _, _) => Some(*rd),
+            _ => None,
+        }
+    }
+    pub fn def_mut(&mut self) -> Option<&mut Reg>{
+        match self{
+            MachineInst::Li(rd, _) | MachineInst::Lui(rd, _) | MachineInst::La(rd, _) | MachineInst::Mv(rd, _)
+            | MachineInst::Op(_, rd, _, _) | MachineInst::OpImm(_, rd, _, _) | MachineInst::Seqz(rd, _)
+            | MachineInst::Snez(rd, _) | MachineInst::Lw(rd, _, _) => Some(rd),
+            _ => None,
+        }
+    }
+    /// 读取的寄存器。`call` 读取全部参数寄存器，`ret` 读取 `a0`
+    pub fn uses(&self) -> Vec<Reg>{
+        match self{
+            MachineInst::Li(..) | MachineInst::Lui(..) | MachineInst::La(..) | MachineInst::J(_)
+            | MachineInst::Comment(_) => vec![],
+            MachineInst::Mv(_, rs) | MachineInst::OpImm(_, _, rs, _) | MachineInst::Seqz(_, rs) | MachineInst::Snez(_, rs)
+            | MachineInst::Lw(_, _, rs) | MachineInst::Bnez(rs, _) | MachineInst::Beqz(rs, _) => vec![*rs],
+            MachineInst::Op(_, _, rs1, rs2) | MachineInst::Sw(rs1, _, rs2) => vec![*rs1, *rs2],
+            MachineInst::Call(_) => (0..8).map(Reg::a).collect(),
+            MachineInst::Ret => vec![Reg::a(0), Reg::SP, Reg::RA],
+        }
+    }
+    /// 控制流在这条指令之后不再顺序执行下去，或者可能跳走
+    pub fn is_terminator(&self) -> bool{
+        matches!(self, MachineInst::Bnez(..) | MachineInst::Beqz(..) | MachineInst::J(_) | MachineInst::Ret)
+    }
+}
+impl fmt::Display for MachineInst{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            MachineInst::Li(rd, imm) => write!(f, "li {}, {}", rd, imm),
+            MachineInst::Lui(rd, imm) => write!(f, "lui {}, {}", rd, imm),
+            MachineInst::La(rd, symbol) => write!(f, "la {}, {}", rd, symbol),
+            MachineInst::Mv(rd, rs) => write!(f, "mv {}, {}", rd, rs),
+            MachineInst::Op(op, rd, rs1, rs2) => write!(f, "{} {}, {}, {}", op.name(), rd, rs1, rs2),
+            MachineInst::OpImm(op, rd, rs, imm) => write!(f, "{} {}, {}, {}", op.imm_name(), rd, rs, imm),
+            MachineInst::Seqz(rd, rs) => write!(f, "seqz {}, {}", rd, rs),
+            MachineInst::Snez(rd, rs) => write!(f, "snez {}, {}", rd, rs),
+            MachineInst::Lw(rd, offset, base) => write!(f, "lw {}, {}({})", rd, offset, base),
+            MachineInst::Sw(rs, offset, base) => write!(f, "sw {}, {}({})", rs, offset, base),
+            MachineInst::Bnez(rs, label) => write!(f, "bnez {}, {}", rs, label),
+            MachineInst::Beqz(rs, label) => write!(f, "beqz {}, {}", rs, label),
+            MachineInst::J(label) => write!(f, "j {}", label),
+            MachineInst::Call(func) => write!(f, "call {}", func),
+            MachineInst::Ret => write!(f, "ret"),
+            MachineInst::Comment(text) => write!(f, "# {}", text),
+        }
+    }
+}
+
+/// 一个基本块。入口块没有标号
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineBasicBlock{
+    pub label: Option<String>,
+    pub insts: Vec<MachineInst>,
+}
+impl MachineBasicBlock{
+    pub fn new(label: Option<String>) -> MachineBasicBlock{
+        MachineBasicBlock{label, insts: Vec::new()}
+    }
+    pub fn push(&mut self, inst: MachineInst){
+        self.insts.push(inst);
+    }
+    pub fn extend(&mut self, insts: impl IntoIterator<Item = MachineInst>){
+        self.insts.extend(insts);
+    }
+}
+
+/// 一个函数，块按输出的顺序排列，第一个块紧跟在函数名后面
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineFunction{
+    pub name: String,
+    pub blocks: Vec<MachineBasicBlock>,
+}
+
+/// 全局变量初值的一段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Data{
+    Word(i32),
+    /// 若干个字节的 0
+    Zero(usize),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineGlobal{
+    pub name: String,
+    pub init: Vec<Data>,
+}
+
+/// 整个程序：数据段里的全局变量和代码段里的函数
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineProgram{
+    pub globals: Vec<MachineGlobal>,
+    pub functions: Vec<MachineFunction>,
+}
+
+impl fmt::Display for MachineBasicBlock{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        if let Some(label) = &self.label{
+            writeln!(f, "{}:", label)?;
+        }
+        for inst in &self.insts{
+            match inst{
+                MachineInst::Comment(_) => writeln!(f, "{}", inst)?,
+                _ => writeln!(f, "\t{}", inst)?,
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for MachineFunction{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        writeln!(f, "\t.text\n\t.global {}\n{}:", self.name, self.name)?;
+        for block in &self.blocks{
+            write!(f, "{}", block)?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for MachineGlobal{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        writeln!(f, "\t.global {}\n{}:", self.name, self.name)?;
+        for data in &self.init{
+            match data{
+                Data::Word(value) => writeln!(f, "\t.word {}", value)?,
+                Data::Zero(size) => writeln!(f, "\t.zero {}", size)?,
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for MachineProgram{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        writeln!(f, "\t.data")?;
+        for global in &self.globals{
+            write!(f, "{}", global)?;
+        }
+        for func in &self.functions{
+            write!(f, "{}", func)?;
+        }
+        Ok(())
+    }
+}
@@ -1,2 +1,4 @@
 pub mod code_generator;
-
+pub mod machine;
+pub mod asm;
+pub mod peephole;
@@ -0,0 +1,172 @@
+use crate::code_generator::machine::{AluOp, MachineFunction, MachineInst, Reg};
+
+/// 窥孔优化：在相邻的指令之间消去后端生成的冗余序列，例如
+/// `mv a0, a0`、`sw` 之后紧跟着从同一位置 `lw`、`li` 加上 `add` 可以写成一条 `addi`、
+/// 跳到紧接着的块的 `j`，以及写入之后还没读就被覆盖或者不再使用的寄存器。
+/// 依赖后端的约定：`t0`-`t6` 只在一条 IR 指令的展开里使用，块结束时都已经没用了。
+pub fn peephole(func: &mut MachineFunction){
+    while rewrite(func){}
+}
+
+/// 对块里一条指令的改写，`None` 表示删掉
+type Edit = (usize, Option<MachineInst>);
+
+/// 扫描一遍，返回是否改动过
+fn rewrite(func: &mut MachineFunction) -> bool{
+    let mut changed = false;
+    for b in 0..func.blocks.len(){
+        let next_label = func.blocks.get(b + 1).and_then(|block| block.label.clone());
+        let insts = &mut func.blocks[b].insts;
+        let mut i = 0;
+        while i < insts.len(){
+            let mut edits = match match_at(insts, i, next_label.as_deref()){
+                Some(edits) => edits,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            // 从后往前改，删除不会影响前面的下标
+            edits.sort_by_key(|(k, _)| std::cmp::Reverse(*k));
+            for (k, inst) in edits{
+                match inst{
+                    Some(inst) => insts[k] = inst,
+                    None => {
+                        insts.remove(k);
+                    }
+                }
+            }
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn match_at(insts: &[MachineInst], i: usize, next_label: Option<&str>) -> Option<Vec<Edit>>{
+    let inst = &insts[i];
+    let next = next_inst(insts, i).map(|j| (j, &insts[j]));
+    let edits = match (inst, next){
+        (MachineInst::Comment(_), _) => return None,
+        (MachineInst::Mv(rd, rs), _) if rd == rs => vec![(i, None)],
+        (MachineInst::OpImm(AluOp::Add, rd, rs, 0), _) if rd == rs => vec![(i, None)],
+        (MachineInst::J(label), None) if next_label == Some(label.as_str()) => vec![(i, None)],
+        // 条件跳过一条无条件跳转，反过来判断
+        (MachineInst::Bnez(rs, label), Some((j, MachineInst::J(target))))
+            if next_inst(insts, j).is_none() && next_label == Some(label.as_str()) =>
+            vec![(i, Some(MachineInst::Beqz(*rs, target.clone()))), (j, None)],
+        (MachineInst::Beqz(rs, label), Some((j, MachineInst::J(target))))
+            if next_inst(insts, j).is_none() && next_label == Some(label.as_str()) =>
+            vec![(i, Some(MachineInst::Bnez(*rs, target.clone()))), (j, None)],
+        // 从刚存进去的位置读出来
+        (MachineInst::Sw(rs, offset, base), Some((j, MachineInst::Lw(rd, o, b)))) if o == offset && b == base =>
+            vec![(j, Some(MachineInst::Mv(*rd, *rs)))],
+        (MachineInst::Li(t, c), Some((j, MachineInst::Op(op, rd, rs1, rs2)))) => {
+            let folded = fold_imm(*op, *rd, *rs1, *rs2, *t, *c)?;
+            if rd != t && !dead_after(insts, j, *t){
+                return None;
+            }
+            vec![(i, None), (j, Some(folded))]
+        }
+        // `addi` 算出来的地址只用来访存一次
+        (MachineInst::OpImm(AluOp::Add, t, base, c), Some((j, MachineInst::Lw(rd, offset, b))))
+            if b == t && fits(offset.wrapping_add(*c)) && (rd == t || dead_after(insts, j, *t)) =>
+            vec![(i, None), (j, Some(MachineInst::Lw(*rd, offset + c, *base)))],
+        (MachineInst::OpImm(AluOp::Add, t, base, c), Some((j, MachineInst::Sw(rs, offset, b))))
+            if b == t && rs != t && fits(offset.wrapping_add(*c)) && dead_after(insts, j, *t) =>
+            vec![(i, None), (j, Some(MachineInst::Sw(*rs, offset + c, *base)))],
+        // 先算到临时寄存器里再 `mv` 走，不如直接算到目标寄存器里
+        (_, Some((j, MachineInst::Mv(rd, t)))) if inst.def() == Some(*t) && rd != t && dead_after(insts, j, *t) => {
+            let mut retargeted = inst.clone();
+            *retargeted.def_mut().unwrap() = *rd;
+            vec![(i, Some(retargeted)), (j, None)]
+        }
+        _ => match inst.def(){
+            Some(rd) if dead_after(insts, i, rd) => vec![(i, None)],
+            _ => return None,
+        },
+    };
+    Some(edits)
+}
+
+/// 块里的下一条指令，跳过注释
+fn next_inst(insts: &[MachineInst], i: usize) -> Option<usize>{
+    (i + 1..insts.len()).find(|&j| !matches!(insts[j], MachineInst::Comment(_)))
+}
+
+/// 第 `i` 条指令之后 `reg` 里的值是否不会再被读到。只看到块结束为止
+fn dead_after(insts: &[MachineInst], i: usize, reg: Reg) -> bool{
+    if matches!(reg, Reg::ZERO | Reg::SP | Reg::RA){
+        return false;
+    }
+    for inst in &insts[i + 1..]{
+        if inst.uses().contains(&reg){
+            return false;
+        }
+        if inst.def() == Some(reg){
+            return true;
+        }
+        // 返回之后只有返回值和被调用者保存的寄存器还有用
+        if *inst == MachineInst::Ret{
+            return reg.is_temp() || reg.is_arg();
+        }
+        // 调用会破坏临时寄存器
+        if inst.is_terminator() || matches!(inst, MachineInst::Call(_)){
+            return reg.is_temp();
+        }
+    }
+    reg.is_temp()
+}
+
+/// 能放进 12 位有符号立即数
+fn fits(imm: i32) -> bool{
+    (-2048..2048).contains(&imm)
+}
+
+/// `li t, c` 之后的 `op rd, rs1, rs2` 读了 `t`，换成带立即数的指令
+fn fold_imm(op: AluOp, rd: Reg, rs1: Reg, rs2: Reg, t: Reg, c: i32) -> Option<MachineInst>{
+    let rs = match (rs1 == t, rs2 == t){
+        (false, true) => rs1,
+        (true, false) if op.is_commutative() => rs2,
+        _ => return None,
+    };
+    match op{
+        AluOp::Sll | AluOp::Srl | AluOp::Sra => Some(MachineInst::OpImm(op, rd, rs, c & 31)),
+        _ if op.has_imm() && fits(c) => Some(MachineInst::OpImm(op, rd, rs, c)),
+        AluOp::Sub if c != i32::MIN && fits(-c) => Some(MachineInst::OpImm(AluOp::Add, rd, rs, -c)),
+        AluOp::Mul if c > 0 && (c as u32).is_power_of_two() => Some(MachineInst::OpImm(AluOp::Sll, rd, rs, c.trailing_zeros() as i32)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::code_generator::asm::{self, Line};
+    use crate::code_generator::machine::MachineBasicBlock;
+
+    /// 按标号把汇编文本切成块
+    fn function(text: &str) -> MachineFunction{
+        let mut func = MachineFunction{name: "f".to_string(), blocks: vec![MachineBasicBlock::new(None)]};
+        for line in asm::parse(text){
+            match line{
+                Line::Label(label) => func.blocks.push(MachineBasicBlock::new(Some(label))),
+                Line::Inst(inst) => func.blocks.last_mut().unwrap().push(inst),
+                Line::Comment(text) if !text.is_empty() =>
+                    func.blocks.last_mut().unwrap().push(MachineInst::Comment(text[2..].to_string())),
+                _ => {}
+            }
+        }
+        func
+    }
+
+    #[test]
+    fn redundant_sequences_are_removed(){
+        let mut func = function("\tli t0, 0\n\tsub sp, sp, t0\n\tmv s1, s1\n\tli t0, 20\n\tadd t0, sp, t0\n\tlw s0, 0(t0)\n\
+            # store gen\n\tsw s0 ,4(sp)\n\tlw a1, 4(sp)\n\tli t1, 8\n\tmul t1, a1, t1\n\tmv a0, t1\n\
+            \tbnez a0, then_1\n\tj end_1\nthen_1:\n\tli a2, 1\n\tli a2, 2\n\tj end_1\n\nend_1:\n\tret\n");
+        peephole(&mut func);
+        let expected = function("\tlw s0, 20(sp)\n# store gen\n\tsw s0, 4(sp)\n\tmv a1, s0\n\tslli a0, a1, 3\n\
+            \tbeqz a0, end_1\nthen_1:\n\tli a2, 2\nend_1:\n\tret\n");
+        assert_eq!(func, expected, "{}", func);
+    }
+}
@@ -0,0 +1,7 @@
+pub mod report;
+pub mod source_map;
+pub mod parse_error;
+
+pub use report::{Diagnostic, Diagnostics, Level, Span};
+pub use source_map::SourceMap;
+pub use parse_error::parse_error_to_diagnostic;
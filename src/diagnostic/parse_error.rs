@@ -0,0 +1,100 @@
+use lalrpop_util::lexer::Token;
+use lalrpop_util::ParseError;
+use crate::diagnostic::{Diagnostic, Span};
+
+/// 把文法里终结符的名字换成可读的写法：`"int"` 变成 `` `int` ``，正则终结符换成文字说明
+fn pretty_token_name(name: &str) -> String{
+    let trimmed = name.trim_start_matches("r#").trim_end_matches('#');
+    let inner = trimmed.strip_prefix('"').and_then(|s| s.strip_suffix('"')).unwrap_or(trimmed);
+    match inner{
+        "[_a-zA-Z][_a-zA-Z0-9]*" => "identifier".to_string(),
+        "[1-9][0-9]*" | "0[0-7]*" | "0[xX][0-9a-fA-F]+" => "integer literal".to_string(),
+        _ => format!("`{}`", inner),
+    }
+}
+
+/// 列出可以出现在这里的记号，去掉换名后重复的
+fn expected_note(expected: &[String]) -> Option<String>{
+    let mut names: Vec<String> = Vec::new();
+    for e in expected{
+        let name = pretty_token_name(e);
+        if !names.contains(&name){
+            names.push(name);
+        }
+    }
+    match names.len(){
+        0 => None,
+        1 => Some(format!("expected {}", names[0])),
+        _ => Some(format!("expected one of {}", names.join(", "))),
+    }
+}
+
+/// 把 lalrpop 解析器返回的错误转成指向源程序的诊断
+pub fn parse_error_to_diagnostic(error: ParseError<usize, Token<'_>, &str>, source: &str) -> Diagnostic{
+    match error{
+        ParseError::InvalidToken{location} => {
+            // 出错的字符可能占好几个字节
+            let len = source.get(location..).and_then(|rest| rest.chars().next()).map_or(1, char::len_utf8);
+            Diagnostic::error("invalid token".to_string())
+                .with_span(Span::new(location, location + len))
+        }
+        ParseError::UnrecognizedEOF{location, expected} => {
+            let d = Diagnostic::error("unexpected end of file".to_string())
+                .with_span(Span::new(location, location));
+            if let Some(note) = expected_note(&expected){
+                d.with_note(note)
+            } else {
+                d
+            }
+        }
+        ParseError::UnrecognizedToken{token: (l, Token(_, text), r), expected} => {
+            let d = Diagnostic::error(format!("unexpected token `{}`", text))
+                .with_span(Span::new(l, r));
+            if let Some(note) = expected_note(&expected){
+                d.with_note(note)
+            } else {
+                d
+            }
+        }
+        ParseError::ExtraToken{token: (l, Token(_, text), r)} => {
+            Diagnostic::error(format!("extra token `{}` after the end of the program", text))
+                .with_span(Span::new(l, r))
+        }
+        ParseError::User{error} => {
+            Diagnostic::error(error.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::diagnostic::SourceMap;
+
+    fn render(source: &str) -> String{
+        let error = crate::sysy::CompUnitParser::new().parse(source).unwrap_err();
+        let source_map = SourceMap::new("a.sy".to_string(), source.to_string());
+        parse_error_to_diagnostic(error, source).render(&source_map)
+    }
+
+    #[test]
+    fn unexpected_token(){
+        assert_eq!(render("int main() {\n    return 0\n}\n"), "error: unexpected token `}`\n\
+            \x20--> a.sy:3:1\n  |\n3 | }\n  | ^\n  = note: expected `;`\n");
+    }
+
+    #[test]
+    fn unexpected_eof(){
+        assert_eq!(render("int main() { return 0; }\nint"), "error: unexpected end of file\n\
+            \x20--> a.sy:2:4\n  |\n2 | int\n  |    ^\n  = note: expected identifier\n");
+    }
+
+    #[test]
+    fn invalid_token(){
+        assert_eq!(render("int main() { return 1 @ 2; }"),
+                   "error: invalid token\n --> a.sy:1:23\n  |\n1 | int main() { return 1 @ 2; }\n  |                       ^\n");
+        // 多字节的字符整个标出来
+        assert_eq!(render("int main(){ int a = 1 你; }"),
+                   "error: invalid token\n --> a.sy:1:23\n  |\n1 | int main(){ int a = 1 你; }\n  |                       ^\n");
+    }
+}
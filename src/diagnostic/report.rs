@@ -0,0 +1,135 @@
+use std::fmt::Write;
+use crate::diagnostic::SourceMap;
+
+/// 源文件中的字节区间 `[start, end)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span{
+    pub start: usize,
+    pub end: usize,
+}
+impl Span{
+    pub fn new(start: usize, end: usize) -> Span{
+        Span{start, end}
+    }
+}
+
+/// 诊断的严重程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level{
+    Error,
+    Warning,
+    Note,
+}
+impl Level{
+    fn as_str(&self) -> &'static str{
+        match self{
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Note => "note",
+        }
+    }
+}
+
+/// 一条诊断：消息、指向的源程序区间和附加的说明
+#[derive(Debug, Clone)]
+pub struct Diagnostic{
+    pub level: Level,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+}
+impl Diagnostic{
+    pub fn error(message: String) -> Diagnostic{
+        Diagnostic{level: Level::Error, message, span: None, notes: Vec::new()}
+    }
+    pub fn warning(message: String) -> Diagnostic{
+        Diagnostic{level: Level::Warning, message, span: None, notes: Vec::new()}
+    }
+    pub fn with_span(mut self, span: Span) -> Diagnostic{
+        self.span = Some(span);
+        self
+    }
+    pub fn with_note(mut self, note: String) -> Diagnostic{
+        self.notes.push(note);
+        self
+    }
+    /// 渲染成下面的格式
+    /// ```text
+    /// error: unexpected token `}`
+    ///  --> a.sy:3:5
+    ///   |
+    /// 3 |     }
+    ///   |     ^
+    ///   = note: expected `;`
+    /// ```
+    pub fn render(&self, source_map: &SourceMap) -> String{
+        let mut s = format!("{}: {}\n", self.level.as_str(), self.message);
+        let mut gutter = 1;
+        if let Some(span) = self.span{
+            let (line, col) = source_map.line_col(span.start);
+            let (end_line, end_col) = source_map.line_col(span.end);
+            let line_no = line.to_string();
+            gutter = line_no.len();
+            let pad = " ".repeat(gutter);
+            let text = source_map.line_text(line);
+            // 跨行的区间只标到第一行末尾
+            let width = if end_line == line && end_col > col{
+                end_col - col
+            } else if end_line > line{
+                (text.chars().count() + 1).saturating_sub(col).max(1)
+            } else {
+                1
+            };
+            let indent: String = text.chars().take(col - 1)
+                .map(|c| if c == '\t' {'\t'} else {' '}).collect();
+            let _ = writeln!(s, "{}--> {}:{}:{}", pad, source_map.file_name, line, col);
+            let _ = writeln!(s, "{} |", pad);
+            let _ = writeln!(s, "{} | {}", line_no, text);
+            let _ = writeln!(s, "{} | {}{}", pad, indent, "^".repeat(width));
+        } else {
+            let _ = writeln!(s, "--> {}", source_map.file_name);
+        }
+        for note in &self.notes{
+            let _ = writeln!(s, "{} = note: {}", " ".repeat(gutter), note);
+        }
+        s
+    }
+}
+
+/// 编译器某个阶段报告的一批诊断
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics{
+    pub list: Vec<Diagnostic>,
+}
+impl Diagnostics{
+    pub fn new() -> Diagnostics{
+        Diagnostics{list: Vec::new()}
+    }
+    pub fn push(&mut self, diagnostic: Diagnostic){
+        self.list.push(diagnostic);
+    }
+    pub fn has_error(&self) -> bool{
+        self.list.iter().any(|d| d.level == Level::Error)
+    }
+    pub fn is_empty(&self) -> bool{
+        self.list.is_empty()
+    }
+    pub fn render(&self, source_map: &SourceMap) -> String{
+        let mut s = "".to_string();
+        for d in &self.list{
+            s += &d.render(source_map);
+            s += "\n";
+        }
+        let errors = self.list.iter().filter(|d| d.level == Level::Error).count();
+        if errors > 0{
+            let _ = writeln!(s, "error: aborting due to {} previous error{}", errors,
+                             if errors == 1 {""} else {"s"});
+        }
+        s
+    }
+}
+impl From<Diagnostic> for Diagnostics{
+    fn from(diagnostic: Diagnostic) -> Self {
+        Diagnostics{list: vec![diagnostic]}
+    }
+}
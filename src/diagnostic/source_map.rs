@@ -0,0 +1,82 @@
+use crate::diagnostic::Span;
+
+/// 保存一个输入文件的源程序，把字节偏移换算成行号和列号
+#[derive(Debug, Clone)]
+pub struct SourceMap{
+    pub file_name: String,
+    pub source: String,
+    line_starts: Vec<usize>,
+}
+impl SourceMap{
+    pub fn new(file_name: String, source: String) -> SourceMap{
+        let mut line_starts = vec![0];
+        for (idx, c) in source.char_indices(){
+            if c == '\n'{
+                line_starts.push(idx + 1);
+            }
+        }
+        SourceMap{file_name, source, line_starts}
+    }
+    /// 字节偏移所在的行号和列号，从 1 开始，列号按字符计数
+    pub fn line_col(&self, offset: usize) -> (usize, usize){
+        let mut offset = offset.min(self.source.len());
+        // 落在多字节字符中间的偏移算作这个字符
+        while !self.source.is_char_boundary(offset){
+            offset -= 1;
+        }
+        let line = match self.line_starts.binary_search(&offset){
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let start = self.line_starts[line];
+        let col = self.source[start..offset].chars().count() + 1;
+        (line + 1, col)
+    }
+    /// 区间起点的 `文件:行:列`，调试输出时用
+    pub fn location(&self, span: Span) -> String{
+        let (line, col) = self.line_col(span.start);
+        format!("{}:{}:{}", self.file_name, line, col)
+    }
+    /// 第 `line` 行（从 1 开始）的内容，不含换行符
+    pub fn line_text(&self, line: usize) -> &str{
+        let start = self.line_starts[line - 1];
+        let end = if line < self.line_starts.len(){
+            self.line_starts[line]
+        } else {
+            self.source.len()
+        };
+        self.source[start..end].trim_end_matches(['\n', '\r'])
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::front_end::ast::*;
+
+    #[test]
+    fn nodes_carry_their_source_location(){
+        let source = "const int n = 2;\nint f(int x) {\n    return x * n;\n}\nint main() {\n    int a = f(n) + 1;\n    return a;\n}\n";
+        let ast = crate::sysy::CompUnitParser::new().parse(source).unwrap();
+        let source_map = SourceMap::new("a.sy".to_string(), source.to_string());
+        let at = |span: Span| (source_map.location(span), &source[span.start..span.end]);
+
+        let GlobalItem::Decl(decl) = &ast.items[0] else {panic!()};
+        assert_eq!(at(decl.span), ("a.sy:1:1".to_string(), "const int n = 2;"));
+        assert_eq!(at(decl.const_decl.as_ref().unwrap().const_def.span), ("a.sy:1:11".to_string(), "n = 2"));
+
+        let GlobalItem::FuncDef(f) = &ast.items[1] else {panic!()};
+        let Some(Stmt{stmt_type: StmtType::Return(Some(exp)), span}) = &f.block.block_item[0].stmt else {panic!()};
+        assert_eq!(at(*span), ("a.sy:3:5".to_string(), "return x * n;"));
+        assert_eq!(at(exp.span), ("a.sy:3:12".to_string(), "x * n"));
+
+        let GlobalItem::FuncDef(main) = &ast.items[2] else {panic!()};
+        let decl = main.block.block_item[0].decl.as_ref().unwrap();
+        let init = decl.var_decl.as_ref().unwrap().var_def.initval.as_ref().unwrap().exp.as_ref().unwrap();
+        let add = init.exp.as_ref().unwrap().land_exp.as_ref().unwrap().eq_exp.as_ref().unwrap()
+            .rel_exp.as_ref().unwrap().add_exp.as_ref().unwrap();
+        let call = add.add_operate.as_ref().unwrap().0.mul_exp.as_ref().unwrap().unary_exp.as_ref().unwrap();
+        assert!(call.func_call.is_some());
+        assert_eq!(at(call.span), ("a.sy:6:13".to_string(), "f(n)"));
+    }
+}
@@ -1,10 +1,12 @@
 pub type int = i32;
 pub type Ident = String;
+pub use crate::diagnostic::Span;
 
 
 #[derive(Debug, Clone)]
 pub struct CompUnit{
     pub items: Vec<GlobalItem>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -19,6 +21,7 @@ pub struct FuncDef {
     pub id: String,
     pub params: Option<FuncParams>,
     pub block: Block,
+    pub span: Span,
 }
 
 
@@ -31,28 +34,33 @@ pub enum  FuncType{
 pub struct FuncParams{
     pub param: FuncParam,
     pub params: Vec<FuncParam>,
+    pub span: Span,
 }
 #[derive(Debug, Clone)]
 pub struct ArrayIdx{
-    pub const_exp: Vec<ConstExp>
+    pub const_exp: Vec<ConstExp>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct FuncParam{
     pub btype: BType,
     pub ident: Ident,
-    pub array_idx: Option<ArrayIdx>
+    pub array_idx: Option<ArrayIdx>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Block{
     pub block_item: Vec<BlockItem>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct BlockItem{
     pub decl: Option<Decl>,
-    pub stmt: Option<Stmt>
+    pub stmt: Option<Stmt>,
+    pub span: Span,
 }
 
 
@@ -75,31 +83,36 @@ pub enum StmtType{
 #[derive(Debug, Clone)]
 pub struct Stmt{
     pub stmt_type: StmtType,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Exp{
-    pub exp: Option<Box<LOrExp>>
+    pub exp: Option<Box<LOrExp>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct UnaryExp{
     pub primary_exp: Option<Box<PrimaryExp>>,
     pub unary_exp: Option<(UnaryOp, Box<UnaryExp>)>,
-    pub func_call: Option<(Ident,Option<FuncRParams>)>
+    pub func_call: Option<(Ident,Option<FuncRParams>)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct FuncRParams{
     pub exp: Exp,
-    pub exp_vec: Vec<Exp>
+    pub exp_vec: Vec<Exp>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct PrimaryExp{
     pub exp: Option<Box<Exp>>,
     pub lval: Option<Lval>,
-    pub num: Option<int>
+    pub num: Option<int>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -110,7 +123,8 @@ pub enum UnaryOperator{
 }
 #[derive(Debug, Clone)]
 pub struct UnaryOp{
-    pub unary_op: UnaryOperator
+    pub unary_op: UnaryOperator,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -124,6 +138,7 @@ pub enum MulOperator{
 pub struct MulExp{
     pub unary_exp: Option<Box<UnaryExp>>,
     pub mul_operate: Option<(Box<MulExp>,MulOperator,Box<UnaryExp>)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -134,7 +149,8 @@ pub enum AddOperator{
 #[derive(Debug, Clone)]
 pub struct AddExp{
     pub mul_exp: Option<Box<MulExp>>,
-    pub add_operate: Option<(Box<AddExp>, AddOperator, Box<MulExp>)>
+    pub add_operate: Option<(Box<AddExp>, AddOperator, Box<MulExp>)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -148,7 +164,8 @@ pub enum RelOperation{
 #[derive(Debug, Clone)]
 pub struct RelExp{
     pub add_exp: Option<Box<AddExp>>,
-    pub rel_operate: Option<(Box<RelExp>, RelOperation, Box<AddExp>)>
+    pub rel_operate: Option<(Box<RelExp>, RelOperation, Box<AddExp>)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -160,32 +177,37 @@ pub enum EqOperation{
 #[derive(Debug, Clone)]
 pub struct EqExp{
     pub rel_exp: Option<Box<RelExp>>,
-    pub eq_operate: Option<(Box<EqExp>, EqOperation, Box<RelExp>)>
+    pub eq_operate: Option<(Box<EqExp>, EqOperation, Box<RelExp>)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct LAndExp{
     pub eq_exp: Option<Box<EqExp>>,
-    pub land_operate:  Option<(Box<LAndExp>, Box<EqExp>)>
+    pub land_operate:  Option<(Box<LAndExp>, Box<EqExp>)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct LOrExp{
     pub land_exp: Option<Box<LAndExp>>,
-    pub lor_operate: Option<(Box<LOrExp>, Box<LAndExp>)>
+    pub lor_operate: Option<(Box<LOrExp>, Box<LAndExp>)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Decl{
     pub const_decl: Option<Box<ConstDecl>>,
-    pub var_decl: Option<Box<VarDecl>>
+    pub var_decl: Option<Box<VarDecl>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct ConstDecl{
     pub b_type: BType,
     pub const_def: ConstDef,
-    pub const_def_vec: Option<Vec<ConstDef>>
+    pub const_def_vec: Option<Vec<ConstDef>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -197,54 +219,63 @@ pub enum BType{
 pub struct Lval{
     pub ident: Ident,
     pub array_idx: Vec<Exp>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct ConstDef{
     pub ident: Ident,
     pub array_idx: Vec<ConstExp>,
-    pub const_init_val: Option<ConstInitVal>
+    pub const_init_val: Option<ConstInitVal>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct ConstInitVal{
     pub const_exp: Option<ConstExp>,
-    pub array_init_vec: Option<Box<ConstArrayInit>>
+    pub array_init_vec: Option<Box<ConstArrayInit>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct ConstArrayInit{
     pub array_init: ConstInitVal,
-    pub array_init_vec: Vec<ConstInitVal>
+    pub array_init_vec: Vec<ConstInitVal>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct ConstExp{
-    pub exp: Exp
+    pub exp: Exp,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct VarDecl{
     pub b_type: BType,
     pub var_def: VarDef,
-    pub var_def_vec: Vec<VarDef>
+    pub var_def_vec: Vec<VarDef>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct VarDef{
     pub ident: Ident,
     pub array_init: Vec<ConstExp>,
-    pub initval: Option<InitVal>
+    pub initval: Option<InitVal>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct InitVal{
     pub exp: Option<Exp>,
     pub array_init_vec: Option<Box<VarArrayInit>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct  VarArrayInit{
     pub array_init: InitVal,
-    pub array_init_vec: Vec<InitVal>
+    pub array_init_vec: Vec<InitVal>,
+    pub span: Span,
 }
\ No newline at end of file
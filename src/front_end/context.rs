@@ -0,0 +1,173 @@
+use koopa::ir::builder::LocalBuilder;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, Value};
+use crate::diagnostic::{Diagnostic, Diagnostics, Span};
+use crate::front_end::symbol_table::SymbolTable;
+
+/// 降级到 Koopa IR 时的全部状态：正在构建的程序、符号表、当前插入位置等
+pub struct FrontendContext{
+    pub program: Program,
+    pub symbol_table: SymbolTable,
+    pub diagnostics: Diagnostics,
+    func: Option<Function>,
+    entry_bb: Option<BasicBlock>,
+    /// 当前插入指令的基本块，None 表示当前位置已经被 return/break/continue 终结
+    now_bb: Option<BasicBlock>,
+    /// 函数统一的出口块 `%end_<name>` 与保存返回值的 `@result`
+    end_bb: Option<BasicBlock>,
+    result: Option<Value>,
+    /// 最后一条插入到入口块开头的 alloc，之后的 alloc 插在它后面
+    last_alloc: Option<Value>,
+    /// 外层循环的 (条件块, 出口块)，供 break/continue 使用
+    loops: Vec<(BasicBlock, BasicBlock)>,
+    label_count: usize,
+}
+
+impl FrontendContext{
+    pub fn new() -> FrontendContext{
+        let mut program = Program::new();
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.init_lib_fun(&mut program);
+        FrontendContext{
+            program,
+            symbol_table,
+            diagnostics: Diagnostics::new(),
+            func: None,
+            entry_bb: None,
+            now_bb: None,
+            end_bb: None,
+            result: None,
+            last_alloc: None,
+            loops: Vec::new(),
+            label_count: 0,
+        }
+    }
+    pub fn error(&mut self, span: Span, message: String){
+        self.diagnostics.push(Diagnostic::error(message).with_span(span));
+    }
+    /// 为一组相关的基本块分配一个程序内唯一的编号，汇编中的标号因此不会重复
+    pub fn new_label_id(&mut self) -> usize{
+        self.label_count += 1;
+        self.label_count
+    }
+    pub fn func_data(&mut self) -> &mut FunctionData{
+        self.program.func_mut(self.func.expect("not inside a function"))
+    }
+    pub fn new_value(&mut self) -> LocalBuilder<'_>{
+        self.func_data().dfg_mut().new_value()
+    }
+    pub fn integer(&mut self, value: i32) -> Value{
+        self.new_value().integer(value)
+    }
+    pub fn value_type(&mut self, value: Value) -> Type{
+        if value.is_global(){
+            self.program.borrow_value(value).ty().clone()
+        } else {
+            self.func_data().dfg().value(value).ty().clone()
+        }
+    }
+    /// 开始构建一个函数，创建入口块；int 函数同时创建 `@result`
+    pub fn enter_function(&mut self, data: FunctionData, ret_int: bool) -> Function{
+        let name = data.name()[1..].to_string();
+        let func = self.program.new_func(data);
+        self.func = Some(func);
+        self.last_alloc = None;
+        let entry = self.new_bb("%entry".to_string());
+        self.push_bb(entry);
+        self.entry_bb = Some(entry);
+        self.end_bb = Some(self.new_bb(format!("%end_{}", name)));
+        self.result = if ret_int{
+            let result = self.alloc(Type::get_i32(), "@result");
+            let zero = self.integer(0);
+            let store = self.new_value().store(zero, result);
+            self.push_inst(store);
+            Some(result)
+        } else {
+            None
+        };
+        func
+    }
+    /// 结束当前函数：落空的控制流跳到出口块，出口块读出返回值并返回
+    pub fn exit_function(&mut self){
+        let end = self.end_bb.take().unwrap();
+        self.jump_to(end);
+        self.push_bb(end);
+        let ret_value = match self.result.take(){
+            Some(result) => {
+                let value = self.new_value().load(result);
+                self.push_inst(value);
+                Some(value)
+            }
+            None => None,
+        };
+        let ret = self.new_value().ret(ret_value);
+        self.terminate(ret);
+        self.func = None;
+        self.entry_bb = None;
+    }
+    pub fn new_bb(&mut self, name: String) -> BasicBlock{
+        self.func_data().dfg_mut().new_bb().basic_block(Some(name))
+    }
+    /// 把基本块接到函数末尾，并作为新的插入位置
+    pub fn push_bb(&mut self, bb: BasicBlock){
+        self.func_data().layout_mut().bbs_mut().push_key_back(bb).unwrap();
+        self.now_bb = Some(bb);
+    }
+    pub fn is_terminated(&self) -> bool{
+        self.now_bb.is_none()
+    }
+    pub fn push_inst(&mut self, inst: Value){
+        let bb = self.now_bb.expect("no basic block to insert into");
+        self.func_data().layout_mut().bb_mut(bb).insts_mut().push_key_back(inst).unwrap();
+    }
+    /// 插入一条终结指令，之后的语句不再可达
+    pub fn terminate(&mut self, inst: Value){
+        self.push_inst(inst);
+        self.now_bb = None;
+    }
+    /// 若当前块尚未终结，则跳转到 `target`
+    pub fn jump_to(&mut self, target: BasicBlock){
+        if !self.is_terminated(){
+            let jump = self.new_value().jump(target);
+            self.terminate(jump);
+        }
+    }
+    /// 在入口块开头分配局部变量，便于之后的分析与 mem2reg
+    pub fn alloc(&mut self, ty: Type, name: &str) -> Value{
+        let alloc = self.new_value().alloc(ty);
+        self.func_data().dfg_mut().set_value_name(alloc, Some(name.to_string()));
+        let entry = self.entry_bb.unwrap();
+        let last_alloc = self.last_alloc;
+        let insts = self.func_data().layout_mut().bb_mut(entry).insts_mut();
+        match last_alloc{
+            Some(last) => insts.cursor_mut(last).insert_key_after(alloc).unwrap(),
+            None => insts.push_key_front(alloc).unwrap(),
+        }
+        self.last_alloc = Some(alloc);
+        alloc
+    }
+    /// `return` 语句：写入返回值后跳到出口块
+    pub fn return_value(&mut self, value: Option<Value>){
+        if let (Some(value), Some(result)) = (value, self.result){
+            let store = self.new_value().store(value, result);
+            self.push_inst(store);
+        }
+        let end = self.end_bb.unwrap();
+        self.jump_to(end);
+    }
+    pub fn enter_loop(&mut self, cond_bb: BasicBlock, end_bb: BasicBlock){
+        self.loops.push((cond_bb, end_bb));
+    }
+    pub fn exit_loop(&mut self){
+        self.loops.pop();
+    }
+    pub fn loop_target(&self) -> Option<(BasicBlock, BasicBlock)>{
+        self.loops.last().copied()
+    }
+}
+
+impl Default for FrontendContext{
+    fn default() -> Self{
+        Self::new()
+    }
+}
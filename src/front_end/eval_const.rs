@@ -0,0 +1,155 @@
+use crate::front_end::ast::*;
+use crate::front_end::symbol_table::{SymbolTable, SymbolType};
+
+/// 编译期求值，表达式不是常量（引用变量、调用函数、除零）时返回 None
+pub trait EvalConst{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>;
+}
+
+impl EvalConst for ConstExp{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        self.exp.eval_const(table)
+    }
+}
+
+impl EvalConst for Exp{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        self.exp.as_ref()?.eval_const(table)
+    }
+}
+
+impl EvalConst for LOrExp{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        if let Some((lhs, rhs)) = &self.lor_operate{
+            let l = lhs.eval_const(table)?;
+            let r = rhs.eval_const(table)?;
+            Some((l != 0 || r != 0) as i32)
+        } else {
+            self.land_exp.as_ref()?.eval_const(table)
+        }
+    }
+}
+
+impl EvalConst for LAndExp{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        if let Some((lhs, rhs)) = &self.land_operate{
+            let l = lhs.eval_const(table)?;
+            let r = rhs.eval_const(table)?;
+            Some((l != 0 && r != 0) as i32)
+        } else {
+            self.eq_exp.as_ref()?.eval_const(table)
+        }
+    }
+}
+
+impl EvalConst for EqExp{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        if let Some((lhs, op, rhs)) = &self.eq_operate{
+            let l = lhs.eval_const(table)?;
+            let r = rhs.eval_const(table)?;
+            Some(match op{
+                EqOperation::Eq => (l == r) as i32,
+                EqOperation::NEq => (l != r) as i32,
+            })
+        } else {
+            self.rel_exp.as_ref()?.eval_const(table)
+        }
+    }
+}
+
+impl EvalConst for RelExp{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        if let Some((lhs, op, rhs)) = &self.rel_operate{
+            let l = lhs.eval_const(table)?;
+            let r = rhs.eval_const(table)?;
+            Some(match op{
+                RelOperation::Less => (l < r) as i32,
+                RelOperation::Greater => (l > r) as i32,
+                RelOperation::LessEq => (l <= r) as i32,
+                RelOperation::GreaterEq => (l >= r) as i32,
+            })
+        } else {
+            self.add_exp.as_ref()?.eval_const(table)
+        }
+    }
+}
+
+impl EvalConst for AddExp{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        if let Some((lhs, op, rhs)) = &self.add_operate{
+            let l = lhs.eval_const(table)?;
+            let r = rhs.eval_const(table)?;
+            Some(match op{
+                AddOperator::Add => l.wrapping_add(r),
+                AddOperator::Sub => l.wrapping_sub(r),
+            })
+        } else {
+            self.mul_exp.as_ref()?.eval_const(table)
+        }
+    }
+}
+
+impl EvalConst for MulExp{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        if let Some((lhs, op, rhs)) = &self.mul_operate{
+            let l = lhs.eval_const(table)?;
+            let r = rhs.eval_const(table)?;
+            match op{
+                MulOperator::Times => Some(l.wrapping_mul(r)),
+                MulOperator::Divide => l.checked_div(r),
+                MulOperator::Quote => l.checked_rem(r),
+            }
+        } else {
+            self.unary_exp.as_ref()?.eval_const(table)
+        }
+    }
+}
+
+impl EvalConst for UnaryExp{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        if let Some(primary) = &self.primary_exp{
+            primary.eval_const(table)
+        } else if let Some((op, exp)) = &self.unary_exp{
+            let v = exp.eval_const(table)?;
+            Some(match op.unary_op{
+                UnaryOperator::Add => v,
+                UnaryOperator::Sub => v.wrapping_neg(),
+                UnaryOperator::False => (v == 0) as i32,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl EvalConst for PrimaryExp{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        if let Some(exp) = &self.exp{
+            exp.eval_const(table)
+        } else if let Some(lval) = &self.lval{
+            lval.eval_const(table)
+        } else {
+            self.num
+        }
+    }
+}
+
+impl EvalConst for Lval{
+    fn eval_const(&self, table: &SymbolTable) -> Option<i32>{
+        match table.get_symbol(&self.ident)?{
+            SymbolType::Const(v) if self.array_idx.is_empty() => Some(*v),
+            SymbolType::ConstArray(_, dims, values) if self.array_idx.len() == dims.len() => {
+                let mut offset = 0;
+                for (exp, &len) in self.array_idx.iter().zip(dims.iter()){
+                    let idx = exp.eval_const(table)?;
+                    if idx < 0 || idx >= len{
+                        return None;
+                    }
+                    offset = offset * len as usize + idx as usize;
+                }
+                values.get(offset).copied()
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use crate::front_end::ast::*;
+
+/// 把语法树输出为 SysY 源代码，输出再解析得到的语法树和原来的一样（只是 if、while 的分支都加了花括号）
+pub fn print<T: Print>(node: &T) -> String{
+    let mut printer = Printer{out: String::new(), indent: 0};
+    node.print(&mut printer);
+    printer.out
+}
+
+pub struct Printer{
+    out: String,
+    indent: usize,
+}
+impl Printer{
+    fn push(&mut self, text: &str){
+        self.out.push_str(text);
+    }
+    /// 换行并缩进到当前层次
+    fn newline(&mut self){
+        self.out.push('\n');
+        for _ in 0..self.indent{
+            self.out.push_str("    ");
+        }
+    }
+    /// 用 `sep` 分隔输出各项
+    fn list<T: Print>(&mut self, items: &[&T], sep: &str){
+        for (i, item) in items.iter().enumerate(){
+            if i > 0{
+                self.push(sep);
+            }
+            item.print(self);
+        }
+    }
+}
+
+pub trait Print{
+    fn print(&self, printer: &mut Printer);
+}
+
+impl Print for CompUnit{
+    fn print(&self, printer: &mut Printer){
+        for (i, item) in self.items.iter().enumerate(){
+            match item{
+                GlobalItem::Decl(decl) => decl.print(printer),
+                GlobalItem::FuncDef(func_def) => {
+                    // 函数前后空一行
+                    if i > 0{
+                        printer.push("\n");
+                    }
+                    func_def.print(printer);
+                }
+            }
+            printer.push("\n");
+        }
+    }
+}
+
+impl Print for FuncDef{
+    fn print(&self, printer: &mut Printer){
+        printer.push(match self.func_type{
+            FuncType::Int => "int ",
+            FuncType::Void => "void ",
+        });
+        printer.push(&self.id);
+        printer.push("(");
+        if let Some(params) = &self.params{
+            let params: Vec<&FuncParam> = std::iter::once(&params.param).chain(params.params.iter()).collect();
+            printer.list(&params, ", ");
+        }
+        printer.push(") ");
+        self.block.print(printer);
+    }
+}
+
+impl Print for FuncParam{
+    fn print(&self, printer: &mut Printer){
+        printer.push("int ");
+        printer.push(&self.ident);
+        if let Some(idx) = &self.array_idx{
+            printer.push("[]");
+            for exp in &idx.const_exp{
+                printer.push("[");
+                exp.print(printer);
+                printer.push("]");
+            }
+        }
+    }
+}
+
+impl Print for Block{
+    fn print(&self, printer: &mut Printer){
+        printer.push("{");
+        printer.indent += 1;
+        for item in &self.block_item{
+            printer.newline();
+            if let Some(decl) = &item.decl{
+                decl.print(printer);
+            }
+            if let Some(stmt) = &item.stmt{
+                stmt.print(printer);
+            }
+        }
+        printer.indent -= 1;
+        printer.newline();
+        printer.push("}");
+    }
+}
+
+/// if、while 的分支总是放进花括号里，不用考虑悬挂的 else
+fn print_branch(stmt: &Stmt, printer: &mut Printer){
+    match &stmt.stmt_type{
+        StmtType::StmtBlock(block) => block.print(printer),
+        _ => {
+            printer.push("{");
+            printer.indent += 1;
+            printer.newline();
+            stmt.print(printer);
+            printer.indent -= 1;
+            printer.newline();
+            printer.push("}");
+        }
+    }
+}
+
+impl Print for Stmt{
+    fn print(&self, printer: &mut Printer){
+        match &self.stmt_type{
+            StmtType::Return(None) => printer.push("return;"),
+            StmtType::Return(Some(exp)) => {
+                printer.push("return ");
+                exp.print(printer);
+                printer.push(";");
+            }
+            StmtType::Assign((lval, exp)) => {
+                lval.print(printer);
+                printer.push(" = ");
+                exp.print(printer);
+                printer.push(";");
+            }
+            StmtType::StmtBlock(block) => block.print(printer),
+            StmtType::Exp(None) => printer.push(";"),
+            StmtType::Exp(Some(exp)) => {
+                exp.print(printer);
+                printer.push(";");
+            }
+            StmtType::Branch(branch) => {
+                let (cond, then, otherwise) = match branch{
+                    BranchType::Matched(b) => (&b.0, &b.1, Some(&b.2)),
+                    BranchType::UnMatched(b) => (&b.0, &b.1, b.2.as_ref()),
+                };
+                printer.push("if (");
+                cond.print(printer);
+                printer.push(") ");
+                print_branch(then, printer);
+                if let Some(otherwise) = otherwise{
+                    printer.push(" else ");
+                    print_branch(otherwise, printer);
+                }
+            }
+            StmtType::While(w) => {
+                printer.push("while (");
+                w.0.print(printer);
+                printer.push(") ");
+                print_branch(&w.1, printer);
+            }
+            StmtType::Break => printer.push("break;"),
+            StmtType::Continue => printer.push("continue;"),
+        }
+    }
+}
+
+impl Print for Decl{
+    fn print(&self, printer: &mut Printer){
+        if let Some(const_decl) = &self.const_decl{
+            printer.push("const int ");
+            let defs: Vec<&ConstDef> = std::iter::once(&const_decl.const_def)
+                .chain(const_decl.const_def_vec.iter().flatten())
+                .collect();
+            printer.list(&defs, ", ");
+            printer.push(";");
+        }
+        if let Some(var_decl) = &self.var_decl{
+            printer.push("int ");
+            let defs: Vec<&VarDef> = std::iter::once(&var_decl.var_def).chain(var_decl.var_def_vec.iter()).collect();
+            printer.list(&defs, ", ");
+            printer.push(";");
+        }
+    }
+}
+
+fn print_dims(dims: &[ConstExp], printer: &mut Printer){
+    for exp in dims{
+        printer.push("[");
+        exp.print(printer);
+        printer.push("]");
+    }
+}
+
+impl Print for ConstDef{
+    fn print(&self, printer: &mut Printer){
+        printer.push(&self.ident);
+        print_dims(&self.array_idx, printer);
+        if let Some(init) = &self.const_init_val{
+            printer.push(" = ");
+            init.print(printer);
+        }
+    }
+}
+
+impl Print for ConstInitVal{
+    fn print(&self, printer: &mut Printer){
+        if let Some(exp) = &self.const_exp{
+            exp.print(printer);
+        } else {
+            printer.push("{");
+            if let Some(init) = &self.array_init_vec{
+                let elems: Vec<&ConstInitVal> = std::iter::once(&init.array_init).chain(init.array_init_vec.iter()).collect();
+                printer.list(&elems, ", ");
+            }
+            printer.push("}");
+        }
+    }
+}
+
+impl Print for VarDef{
+    fn print(&self, printer: &mut Printer){
+        printer.push(&self.ident);
+        print_dims(&self.array_init, printer);
+        if let Some(init) = &self.initval{
+            printer.push(" = ");
+            init.print(printer);
+        }
+    }
+}
+
+impl Print for InitVal{
+    fn print(&self, printer: &mut Printer){
+        if let Some(exp) = &self.exp{
+            exp.print(printer);
+        } else {
+            printer.push("{");
+            if let Some(init) = &self.array_init_vec{
+                let elems: Vec<&InitVal> = std::iter::once(&init.array_init).chain(init.array_init_vec.iter()).collect();
+                printer.list(&elems, ", ");
+            }
+            printer.push("}");
+        }
+    }
+}
+
+impl Print for ConstExp{
+    fn print(&self, printer: &mut Printer){
+        self.exp.print(printer);
+    }
+}
+
+impl Print for Exp{
+    fn print(&self, printer: &mut Printer){
+        if let Some(exp) = &self.exp{
+            exp.print(printer);
+        }
+    }
+}
+
+impl Print for Lval{
+    fn print(&self, printer: &mut Printer){
+        printer.push(&self.ident);
+        for exp in &self.array_idx{
+            printer.push("[");
+            exp.print(printer);
+            printer.push("]");
+        }
+    }
+}
+
+impl Print for LOrExp{
+    fn print(&self, printer: &mut Printer){
+        if let Some((lhs, rhs)) = &self.lor_operate{
+            lhs.print(printer);
+            printer.push(" || ");
+            rhs.print(printer);
+        } else if let Some(exp) = &self.land_exp{
+            exp.print(printer);
+        }
+    }
+}
+
+impl Print for LAndExp{
+    fn print(&self, printer: &mut Printer){
+        if let Some((lhs, rhs)) = &self.land_operate{
+            lhs.print(printer);
+            printer.push(" && ");
+            rhs.print(printer);
+        } else if let Some(exp) = &self.eq_exp{
+            exp.print(printer);
+        }
+    }
+}
+
+impl Print for EqExp{
+    fn print(&self, printer: &mut Printer){
+        if let Some((lhs, op, rhs)) = &self.eq_operate{
+            lhs.print(printer);
+            printer.push(match op{
+                EqOperation::Eq => " == ",
+                EqOperation::NEq => " != ",
+            });
+            rhs.print(printer);
+        } else if let Some(exp) = &self.rel_exp{
+            exp.print(printer);
+        }
+    }
+}
+
+impl Print for RelExp{
+    fn print(&self, printer: &mut Printer){
+        if let Some((lhs, op, rhs)) = &self.rel_operate{
+            lhs.print(printer);
+            printer.push(match op{
+                RelOperation::Less => " < ",
+                RelOperation::Greater => " > ",
+                RelOperation::LessEq => " <= ",
+                RelOperation::GreaterEq => " >= ",
+            });
+            rhs.print(printer);
+        } else if let Some(exp) = &self.add_exp{
+            exp.print(printer);
+        }
+    }
+}
+
+impl Print for AddExp{
+    fn print(&self, printer: &mut Printer){
+        if let Some((lhs, op, rhs)) = &self.add_operate{
+            lhs.print(printer);
+            printer.push(match op{
+                AddOperator::Add => " + ",
+                AddOperator::Sub => " - ",
+            });
+            rhs.print(printer);
+        } else if let Some(exp) = &self.mul_exp{
+            exp.print(printer);
+        }
+    }
+}
+
+impl Print for MulExp{
+    fn print(&self, printer: &mut Printer){
+        if let Some((lhs, op, rhs)) = &self.mul_operate{
+            lhs.print(printer);
+            printer.push(match op{
+                MulOperator::Times => " * ",
+                MulOperator::Divide => " / ",
+                MulOperator::Quote => " % ",
+            });
+            rhs.print(printer);
+        } else if let Some(exp) = &self.unary_exp{
+            exp.print(printer);
+        }
+    }
+}
+
+impl Print for UnaryExp{
+    fn print(&self, printer: &mut Printer){
+        if let Some(exp) = &self.primary_exp{
+            exp.print(printer);
+        } else if let Some((op, exp)) = &self.unary_exp{
+            printer.push(match op.unary_op{
+                UnaryOperator::Add => "+",
+                UnaryOperator::Sub => "-",
+                UnaryOperator::False => "!",
+            });
+            exp.print(printer);
+        } else if let Some((ident, args)) = &self.func_call{
+            printer.push(ident);
+            printer.push("(");
+            if let Some(args) = args{
+                let args: Vec<&Exp> = std::iter::once(&args.exp).chain(args.exp_vec.iter()).collect();
+                printer.list(&args, ", ");
+            }
+            printer.push(")");
+        }
+    }
+}
+
+impl Print for PrimaryExp{
+    fn print(&self, printer: &mut Printer){
+        if let Some(exp) = &self.exp{
+            printer.push("(");
+            exp.print(printer);
+            printer.push(")");
+        } else if let Some(lval) = &self.lval{
+            lval.print(printer);
+        } else if let Some(num) = self.num{
+            printer.push(&num.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::fuzz::generator::{generate, Config};
+    use crate::{compile, Emit, Options};
+
+    #[test]
+    fn printed_programs_compile_to_the_same_ir(){
+        let options = Options{emit: vec![Emit::Koopa], ..Options::default()};
+        let sources = (0..5).map(|seed| generate(seed, &Config::default()))
+            .chain(std::iter::once("int f(int a[][2]) { if (a[0][1]) if (!a[1][0]) return -(-1); else { ; } \
+                return 0; }\nint main() { const int n = 2, m = n * 2; int a[m][n] = {{1}, {}, 3, 4}; \
+                while (1) { if (a[0][0] || f(a) && 1) break; } return f(a) % 7; }".to_string()));
+        for source in sources{
+            let ast = crate::sysy::CompUnitParser::new().parse(&source).unwrap();
+            let printed = print(&ast);
+            let expected = compile(&source, &options).unwrap();
+            let actual = compile(&printed, &options).unwrap();
+            assert_eq!(expected.get(Emit::Koopa), actual.get(Emit::Koopa), "{}", printed);
+            // 输出已经是固定的格式，再输出一次不变
+            assert_eq!(print(&crate::sysy::CompUnitParser::new().parse(&printed).unwrap()), printed);
+        }
+    }
+}
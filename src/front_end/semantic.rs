@@ -0,0 +1,656 @@
+use std::collections::HashMap;
+use crate::front_end::ast::*;
+use crate::diagnostic::{Diagnostic, Diagnostics, Span};
+
+/// 语义检查阶段看到的名字种类
+#[derive(Debug, Clone)]
+enum SymbolKind{
+    Const,
+    Var,
+    /// 数组（包括作为参数的数组指针），记录维数
+    ConstArray(usize),
+    VarArray(usize),
+}
+
+#[derive(Debug, Clone)]
+struct FuncSignature{
+    func_type: FuncType,
+    /// 每个参数的数组维数，0 表示整数参数
+    params: Vec<usize>,
+}
+
+/// 表达式检查的结果类型
+#[derive(Debug, Clone)]
+pub enum ExpType{
+    Int,
+    /// 未取满下标的数组，只能作为函数实参
+    Array(usize),
+    /// 调用了返回 void 的函数，记录函数名
+    Void(String),
+    /// 已经报过错的表达式，避免重复报错
+    Error,
+}
+
+pub struct SemanticChecker{
+    scopes: Vec<HashMap<String, SymbolKind>>,
+    functions: HashMap<String, FuncSignature>,
+    now_function_type: Option<FuncType>,
+    loop_depth: usize,
+    diagnostics: Diagnostics,
+}
+
+impl SemanticChecker{
+    pub fn new() -> Self{
+        let mut checker = SemanticChecker{
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            now_function_type: None,
+            loop_depth: 0,
+            diagnostics: Diagnostics::new(),
+        };
+        checker.init_lib_fun();
+        checker
+    }
+    /// 与 `SymbolTable::init_lib_fun` 中声明的库函数保持一致
+    fn init_lib_fun(&mut self){
+        let lib = [("getint", FuncType::Int, vec![]),
+            ("getch", FuncType::Int, vec![]),
+            ("getarray", FuncType::Int, vec![1]),
+            ("putint", FuncType::Void, vec![0]),
+            ("putch", FuncType::Void, vec![0]),
+            ("putarray", FuncType::Void, vec![0, 1]),
+            ("starttime", FuncType::Void, vec![]),
+            ("stoptime", FuncType::Void, vec![])];
+        for (name, func_type, params) in lib{
+            self.functions.insert(name.to_string(), FuncSignature{func_type, params});
+        }
+    }
+    pub fn finish(self) -> Diagnostics{
+        self.diagnostics
+    }
+    fn error(&mut self, span: Span, message: String){
+        self.diagnostics.push(Diagnostic::error(message).with_span(span));
+    }
+    fn warning(&mut self, span: Span, message: String){
+        self.diagnostics.push(Diagnostic::warning(message).with_span(span));
+    }
+    fn enter_scope(&mut self){
+        self.scopes.push(HashMap::new());
+    }
+    fn exit_scope(&mut self){
+        self.scopes.pop();
+    }
+    fn declare(&mut self, span: Span, name: &Ident, kind: SymbolKind){
+        // 全局变量和函数在同一个命名空间里
+        let is_function = self.scopes.len() == 1 && self.functions.contains_key(name);
+        let scope = self.scopes.last_mut().unwrap();
+        if scope.contains_key(name) || is_function{
+            self.error(span, format!("redeclaration of `{}` in the same scope", name));
+        } else {
+            scope.insert(name.clone(), kind);
+        }
+    }
+    fn lookup(&self, name: &Ident) -> Option<&SymbolKind>{
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+    /// 要求表达式产生一个整数值，`what` 描述使用该值的位置
+    fn expect_int(&mut self, span: Span, ty: ExpType, what: &str){
+        match ty{
+            ExpType::Void(name) => {
+                self.error(span, format!("void function `{}` used as a value in {}", name, what));
+            }
+            ExpType::Array(_) => {
+                self.error(span, format!("array used as a value in {}", what));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for SemanticChecker{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+/// 对整个编译单元做语义检查，返回检查中发现的所有诊断信息
+pub fn check(comp_unit: &CompUnit) -> Diagnostics{
+    let mut checker = SemanticChecker::new();
+    comp_unit.check(&mut checker);
+    checker.finish()
+}
+
+pub trait SemanticCheck{
+    type Output;
+    fn check(&self, checker: &mut SemanticChecker) -> Self::Output;
+}
+
+impl SemanticCheck for CompUnit{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        for item in &self.items{
+            match item{
+                GlobalItem::Decl(decl) => decl.check(checker),
+                GlobalItem::FuncDef(func_def) => func_def.check(checker),
+            }
+        }
+        if !checker.functions.contains_key("main"){
+            checker.diagnostics.push(Diagnostic::error("function `main` is not defined".to_string()));
+        }
+    }
+}
+
+impl SemanticCheck for FuncDef{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        let params: Vec<&FuncParam> = match &self.params{
+            Some(p) => std::iter::once(&p.param).chain(p.params.iter()).collect(),
+            None => vec![],
+        };
+        for param in &params{
+            if let Some(idx) = &param.array_idx{
+                for exp in &idx.const_exp{
+                    exp.check(checker);
+                }
+            }
+        }
+        let signature = FuncSignature{
+            func_type: self.func_type.clone(),
+            params: params.iter().map(|p| param_dimension(p)).collect(),
+        };
+        if checker.functions.contains_key(&self.id){
+            checker.error(self.span, format!("redefinition of function `{}`", self.id));
+        } else if checker.scopes[0].contains_key(&self.id){
+            checker.error(self.span, format!("redeclaration of `{}` in the same scope", self.id));
+        } else {
+            checker.functions.insert(self.id.clone(), signature);
+        }
+        checker.now_function_type = Some(self.func_type.clone());
+        // 参数与函数体最外层的块共用同一个作用域
+        checker.enter_scope();
+        for param in &params{
+            let kind = match param_dimension(param){
+                0 => SymbolKind::Var,
+                d => SymbolKind::VarArray(d),
+            };
+            checker.declare(param.span, &param.ident, kind);
+        }
+        for item in &self.block.block_item{
+            item.check(checker);
+        }
+        checker.exit_scope();
+        checker.now_function_type = None;
+    }
+}
+
+fn param_dimension(param: &FuncParam) -> usize{
+    match &param.array_idx{
+        Some(idx) => idx.const_exp.len() + 1,
+        None => 0,
+    }
+}
+
+impl SemanticCheck for Block{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        checker.enter_scope();
+        for item in &self.block_item{
+            item.check(checker);
+        }
+        checker.exit_scope();
+    }
+}
+
+impl SemanticCheck for BlockItem{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        if let Some(decl) = &self.decl{
+            decl.check(checker);
+        }
+        if let Some(stmt) = &self.stmt{
+            stmt.check(checker);
+        }
+    }
+}
+
+impl SemanticCheck for Decl{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        if let Some(const_decl) = &self.const_decl{
+            const_decl.const_def.check(checker);
+            if let Some(defs) = &const_decl.const_def_vec{
+                for def in defs{
+                    def.check(checker);
+                }
+            }
+        }
+        if let Some(var_decl) = &self.var_decl{
+            var_decl.var_def.check(checker);
+            for def in &var_decl.var_def_vec{
+                def.check(checker);
+            }
+        }
+    }
+}
+
+impl SemanticCheck for ConstDef{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        for exp in &self.array_idx{
+            exp.check(checker);
+        }
+        // 初始化表达式中不能引用正在定义的名字，所以先检查再声明
+        if let Some(init) = &self.const_init_val{
+            init.check(checker);
+        }
+        let kind = match self.array_idx.len(){
+            0 => SymbolKind::Const,
+            d => SymbolKind::ConstArray(d),
+        };
+        checker.declare(self.span, &self.ident, kind);
+    }
+}
+
+impl SemanticCheck for ConstInitVal{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        if let Some(exp) = &self.const_exp{
+            exp.check(checker);
+        }
+        if let Some(array) = &self.array_init_vec{
+            array.array_init.check(checker);
+            for init in &array.array_init_vec{
+                init.check(checker);
+            }
+        }
+    }
+}
+
+impl SemanticCheck for ConstExp{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        let ty = self.exp.check(checker);
+        checker.expect_int(self.span, ty, "constant expression");
+    }
+}
+
+impl SemanticCheck for VarDef{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        for exp in &self.array_init{
+            exp.check(checker);
+        }
+        if let Some(init) = &self.initval{
+            init.check(checker);
+        }
+        let kind = match self.array_init.len(){
+            0 => SymbolKind::Var,
+            d => SymbolKind::VarArray(d),
+        };
+        checker.declare(self.span, &self.ident, kind);
+    }
+}
+
+impl SemanticCheck for InitVal{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        if let Some(exp) = &self.exp{
+            let ty = exp.check(checker);
+            checker.expect_int(exp.span, ty, "initializer");
+        }
+        if let Some(array) = &self.array_init_vec{
+            array.array_init.check(checker);
+            for init in &array.array_init_vec{
+                init.check(checker);
+            }
+        }
+    }
+}
+
+impl SemanticCheck for Stmt{
+    type Output = ();
+    fn check(&self, checker: &mut SemanticChecker){
+        match &self.stmt_type{
+            StmtType::Return(exp) => {
+                let is_void = matches!(checker.now_function_type, Some(FuncType::Void));
+                match exp{
+                    Some(exp) => {
+                        let ty = exp.check(checker);
+                        if is_void{
+                            checker.error(self.span, "returning a value from a void function".to_string());
+                        } else {
+                            checker.expect_int(exp.span, ty, "return statement");
+                        }
+                    }
+                    None => {
+                        if !is_void{
+                            checker.warning(self.span, "return without a value in a function returning \
+                            int".to_string());
+                        }
+                    }
+                }
+            }
+            StmtType::Assign((lval, exp)) => {
+                match checker.lookup(&lval.ident){
+                    Some(SymbolKind::Const) | Some(SymbolKind::ConstArray(_)) => {
+                        checker.error(lval.span, format!("cannot assign to const `{}`", lval.ident));
+                    }
+                    _ => {}
+                }
+                let target = lval.check(checker);
+                if let ExpType::Array(_) = target{
+                    checker.error(lval.span, format!("cannot assign to array `{}`", lval.ident));
+                }
+                let ty = exp.check(checker);
+                checker.expect_int(exp.span, ty, "assignment");
+            }
+            StmtType::StmtBlock(block) => block.check(checker),
+            StmtType::Exp(exp) => {
+                // 表达式语句可以丢弃结果，允许调用 void 函数
+                if let Some(exp) = exp{
+                    exp.check(checker);
+                }
+            }
+            StmtType::Branch(branch) => {
+                let (cond, then, otherwise) = match branch{
+                    BranchType::Matched(b) => (&b.0, &b.1, Some(&b.2)),
+                    BranchType::UnMatched(b) => (&b.0, &b.1, b.2.as_ref()),
+                };
+                let ty = cond.check(checker);
+                checker.expect_int(cond.span, ty, "if condition");
+                then.check(checker);
+                if let Some(s) = otherwise{
+                    s.check(checker);
+                }
+            }
+            StmtType::While(w) => {
+                let ty = w.0.check(checker);
+                checker.expect_int(w.0.span, ty, "while condition");
+                checker.loop_depth += 1;
+                w.1.check(checker);
+                checker.loop_depth -= 1;
+            }
+            StmtType::Break => {
+                if checker.loop_depth == 0{
+                    checker.error(self.span, "`break` outside of a loop".to_string());
+                }
+            }
+            StmtType::Continue => {
+                if checker.loop_depth == 0{
+                    checker.error(self.span, "`continue` outside of a loop".to_string());
+                }
+            }
+        }
+    }
+}
+
+impl SemanticCheck for Lval{
+    type Output = ExpType;
+    fn check(&self, checker: &mut SemanticChecker) -> ExpType{
+        for exp in &self.array_idx{
+            let ty = exp.check(checker);
+            checker.expect_int(exp.span, ty, "array index");
+        }
+        let dimension = match checker.lookup(&self.ident){
+            Some(SymbolKind::Const) | Some(SymbolKind::Var) => 0,
+            Some(SymbolKind::ConstArray(d)) | Some(SymbolKind::VarArray(d)) => *d,
+            None => {
+                if checker.functions.contains_key(&self.ident){
+                    checker.error(self.span, format!("function `{}` used as a variable", self.ident));
+                } else {
+                    checker.error(self.span, format!("use of undeclared identifier `{}`", self.ident));
+                }
+                return ExpType::Error;
+            }
+        };
+        let idx = self.array_idx.len();
+        if idx > dimension{
+            checker.error(self.span, format!("`{}` has {} dimension(s) but is indexed with {} \
+            subscript(s)", self.ident, dimension, idx));
+            ExpType::Error
+        } else if idx < dimension{
+            ExpType::Array(dimension - idx)
+        } else {
+            ExpType::Int
+        }
+    }
+}
+
+impl SemanticCheck for Exp{
+    type Output = ExpType;
+    fn check(&self, checker: &mut SemanticChecker) -> ExpType{
+        match &self.exp{
+            Some(exp) => exp.check(checker),
+            None => ExpType::Error,
+        }
+    }
+}
+
+/// 检查二元运算的两个操作数，结果总是整数
+fn check_binary(checker: &mut SemanticChecker, lhs: (Span, ExpType), rhs: (Span, ExpType)) -> ExpType{
+    checker.expect_int(lhs.0, lhs.1, "binary expression");
+    checker.expect_int(rhs.0, rhs.1, "binary expression");
+    ExpType::Int
+}
+
+impl SemanticCheck for LOrExp{
+    type Output = ExpType;
+    fn check(&self, checker: &mut SemanticChecker) -> ExpType{
+        if let Some((lhs, rhs)) = &self.lor_operate{
+            let lhs = (lhs.span, lhs.check(checker));
+            let rhs = (rhs.span, rhs.check(checker));
+            check_binary(checker, lhs, rhs)
+        } else if let Some(exp) = &self.land_exp{
+            exp.check(checker)
+        } else {
+            ExpType::Error
+        }
+    }
+}
+
+impl SemanticCheck for LAndExp{
+    type Output = ExpType;
+    fn check(&self, checker: &mut SemanticChecker) -> ExpType{
+        if let Some((lhs, rhs)) = &self.land_operate{
+            let lhs = (lhs.span, lhs.check(checker));
+            let rhs = (rhs.span, rhs.check(checker));
+            check_binary(checker, lhs, rhs)
+        } else if let Some(exp) = &self.eq_exp{
+            exp.check(checker)
+        } else {
+            ExpType::Error
+        }
+    }
+}
+
+impl SemanticCheck for EqExp{
+    type Output = ExpType;
+    fn check(&self, checker: &mut SemanticChecker) -> ExpType{
+        if let Some((lhs, _, rhs)) = &self.eq_operate{
+            let lhs = (lhs.span, lhs.check(checker));
+            let rhs = (rhs.span, rhs.check(checker));
+            check_binary(checker, lhs, rhs)
+        } else if let Some(exp) = &self.rel_exp{
+            exp.check(checker)
+        } else {
+            ExpType::Error
+        }
+    }
+}
+
+impl SemanticCheck for RelExp{
+    type Output = ExpType;
+    fn check(&self, checker: &mut SemanticChecker) -> ExpType{
+        if let Some((lhs, _, rhs)) = &self.rel_operate{
+            let lhs = (lhs.span, lhs.check(checker));
+            let rhs = (rhs.span, rhs.check(checker));
+            check_binary(checker, lhs, rhs)
+        } else if let Some(exp) = &self.add_exp{
+            exp.check(checker)
+        } else {
+            ExpType::Error
+        }
+    }
+}
+
+impl SemanticCheck for AddExp{
+    type Output = ExpType;
+    fn check(&self, checker: &mut SemanticChecker) -> ExpType{
+        if let Some((lhs, _, rhs)) = &self.add_operate{
+            let lhs = (lhs.span, lhs.check(checker));
+            let rhs = (rhs.span, rhs.check(checker));
+            check_binary(checker, lhs, rhs)
+        } else if let Some(exp) = &self.mul_exp{
+            exp.check(checker)
+        } else {
+            ExpType::Error
+        }
+    }
+}
+
+impl SemanticCheck for MulExp{
+    type Output = ExpType;
+    fn check(&self, checker: &mut SemanticChecker) -> ExpType{
+        if let Some((lhs, _, rhs)) = &self.mul_operate{
+            let lhs = (lhs.span, lhs.check(checker));
+            let rhs = (rhs.span, rhs.check(checker));
+            check_binary(checker, lhs, rhs)
+        } else if let Some(exp) = &self.unary_exp{
+            exp.check(checker)
+        } else {
+            ExpType::Error
+        }
+    }
+}
+
+impl SemanticCheck for UnaryExp{
+    type Output = ExpType;
+    fn check(&self, checker: &mut SemanticChecker) -> ExpType{
+        if let Some(primary) = &self.primary_exp{
+            primary.check(checker)
+        } else if let Some((_, exp)) = &self.unary_exp{
+            let ty = exp.check(checker);
+            checker.expect_int(exp.span, ty, "unary expression");
+            ExpType::Int
+        } else if let Some((ident, args)) = &self.func_call{
+            let args: Vec<&Exp> = match args{
+                Some(a) => std::iter::once(&a.exp).chain(a.exp_vec.iter()).collect(),
+                None => vec![],
+            };
+            let arg_types: Vec<ExpType> = args.iter().map(|a| a.check(checker)).collect();
+            for (arg, ty) in args.iter().zip(arg_types.iter()){
+                if let ExpType::Void(name) = ty{
+                    checker.error(arg.span, format!("void function `{}` used as a value in function \
+                    argument", name));
+                }
+            }
+            let signature = match checker.functions.get(ident){
+                Some(s) => s.clone(),
+                None => {
+                    if checker.lookup(ident).is_some(){
+                        checker.error(self.span, format!("`{}` is not a function", ident));
+                    } else {
+                        checker.error(self.span, format!("call to undefined function `{}`", ident));
+                    }
+                    return ExpType::Error;
+                }
+            };
+            if signature.params.len() != args.len(){
+                checker.error(self.span, format!("function `{}` takes {} argument(s) but {} were supplied",
+                                      ident, signature.params.len(), args.len()));
+            }
+            for (i, ((arg, ty), &dims)) in args.iter().zip(arg_types.iter())
+                .zip(signature.params.iter()).enumerate(){
+                let found = match ty{
+                    ExpType::Int => 0,
+                    ExpType::Array(found) => *found,
+                    _ => continue,
+                };
+                if found != dims{
+                    let describe = |dims: usize| if dims == 0{
+                        "an int".to_string()
+                    } else {
+                        format!("a {}-dimensional array", dims)
+                    };
+                    checker.error(arg.span, format!("argument {} of `{}` expects {} but {} was supplied",
+                                                    i + 1, ident, describe(dims), describe(found)));
+                }
+            }
+            match signature.func_type{
+                FuncType::Int => ExpType::Int,
+                FuncType::Void => ExpType::Void(ident.clone()),
+            }
+        } else {
+            ExpType::Error
+        }
+    }
+}
+
+impl SemanticCheck for PrimaryExp{
+    type Output = ExpType;
+    fn check(&self, checker: &mut SemanticChecker) -> ExpType{
+        if let Some(exp) = &self.exp{
+            exp.check(checker)
+        } else if let Some(lval) = &self.lval{
+            lval.check(checker)
+        } else {
+            ExpType::Int
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// 每条诊断的信息和它标出的源代码
+    fn diagnose(source: &str) -> Vec<(String, &str)>{
+        let ast = crate::sysy::CompUnitParser::new().parse(source).unwrap();
+        check(&ast).list.into_iter()
+            .map(|d| (d.message, d.span.map_or("", |span| &source[span.start..span.end])))
+            .collect()
+    }
+
+    #[test]
+    fn redeclaration(){
+        assert_eq!(diagnose("int main() { int a = 1; int a = 2; return a; }"),
+                   [("redeclaration of `a` in the same scope".to_string(), "a = 2")]);
+        // 内层作用域可以遮蔽外层的名字
+        assert!(diagnose("int a; int main() { int a = 1; { const int a = 2; } return a; }").is_empty());
+    }
+
+    #[test]
+    fn variables_and_functions_share_the_global_scope(){
+        assert_eq!(diagnose("int f; int f() { return 0; } int main() { return 0; }"),
+                   [("redeclaration of `f` in the same scope".to_string(), "int f() { return 0; }")]);
+        assert_eq!(diagnose("int f() { return 0; } const int f = 1; int main() { return 0; }"),
+                   [("redeclaration of `f` in the same scope".to_string(), "f = 1")]);
+        assert!(diagnose("int f() { return 0; } int main() { int f = 1; return f; }").is_empty());
+    }
+
+    #[test]
+    fn assignment_to_const(){
+        assert_eq!(diagnose("const int n = 1; int main() { n = 2; return n; }"),
+                   [("cannot assign to const `n`".to_string(), "n")]);
+    }
+
+    #[test]
+    fn call_to_undefined_function(){
+        assert_eq!(diagnose("int main() { return f(1); }"),
+                   [("call to undefined function `f`".to_string(), "f(1)")]);
+    }
+
+    #[test]
+    fn wrong_argument_count(){
+        assert_eq!(diagnose("int f(int a, int b) { return a + b; } int main() { return f(1); }"),
+                   [("function `f` takes 2 argument(s) but 1 were supplied".to_string(), "f(1)")]);
+    }
+
+    #[test]
+    fn void_call_used_as_value(){
+        assert_eq!(diagnose("void f() {} int main() { int a = f(); return a; }"),
+                   [("void function `f` used as a value in initializer".to_string(), "f()")]);
+        assert_eq!(diagnose("void f() {} int main() { putint(f()); f(); return 0; }"),
+                   [("void function `f` used as a value in function argument".to_string(), "f()")]);
+    }
+}
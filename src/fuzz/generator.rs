@@ -0,0 +1,630 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+/// 生成程序的规模
+#[derive(Debug, Clone)]
+pub struct Config{
+    /// 除 `main` 之外的函数个数上限
+    pub functions: usize,
+    /// 全局变量和常量的个数上限
+    pub globals: usize,
+    /// 每个块里的语句个数上限
+    pub block_len: usize,
+    /// 语句嵌套的深度上限
+    pub stmt_depth: usize,
+    /// 表达式的深度上限
+    pub exp_depth: usize,
+    /// 每个函数估计执行的语句条数上限，保证程序很快结束
+    pub budget: u64,
+}
+impl Default for Config{
+    fn default() -> Self{
+        Config{functions: 4, globals: 4, block_len: 6, stmt_depth: 3, exp_depth: 3, budget: 20_000}
+    }
+}
+
+/// 递归函数的深度参数不超过这个值
+const MAX_RECURSION: i32 = 5;
+/// 循环的次数上限
+const MAX_TRIPS: i32 = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Ty{
+    Int,
+    /// 各维的长度，作为形参时第一维是调用者保证的长度
+    Array(Vec<usize>),
+}
+
+#[derive(Debug, Clone)]
+struct Var{
+    name: String,
+    ty: Ty,
+    /// 循环变量、递归深度和常量不能赋值
+    mutable: bool,
+    /// 常量的值
+    value: Option<i32>,
+    /// 循环变量：在循环体里取值在 `1..=trips` 之间
+    trips: Option<i32>,
+}
+impl Var{
+    fn new(name: String, ty: Ty) -> Var{
+        Var{name, ty, mutable: true, value: None, trips: None}
+    }
+}
+
+struct Func{
+    name: String,
+    returns_int: bool,
+    params: Vec<Ty>,
+    /// 调用一次估计执行的语句条数
+    cost: u64,
+    /// 第一个参数是递归深度
+    recursive: bool,
+}
+
+/// 生成一个良类型、一定会结束、输出确定的 SysY 程序，同一个 `seed` 总是得到同一个程序。
+/// 数组下标都落在范围内，除数不为 0，局部变量都有初始值；
+/// 循环次数有上限，递归由每次减一的深度参数控制，调用的代价按所在循环的次数累计。
+/// 表达式里的调用可能有副作用，输出按本编译器从左到右的求值顺序确定，和 gcc 不一定相同。
+pub fn generate(seed: u64, config: &Config) -> String{
+    let mut gen = Generator{rng: StdRng::seed_from_u64(seed), config, out: String::new(), indent: 0,
+        scopes: vec![Vec::new()], funcs: Vec::new(), returns_int: true, weight: 1, cost: 0, loops: 0, hidden: None, names: 0};
+    gen.program();
+    gen.out
+}
+
+struct Generator<'c>{
+    rng: StdRng,
+    config: &'c Config,
+    out: String,
+    indent: usize,
+    /// 第一层是全局作用域
+    scopes: Vec<Vec<Var>>,
+    /// 已经定义的函数，只能调用它们
+    funcs: Vec<Func>,
+    /// 正在生成的函数是否返回 `int`
+    returns_int: bool,
+    /// 当前位置每调用一次函数会执行多少次
+    weight: u64,
+    /// 当前函数累计的代价
+    cost: u64,
+    /// 所在循环的层数
+    loops: usize,
+    /// 正在声明的名字：初始值里不用它，免得和外层的同名变量混淆
+    hidden: Option<String>,
+    names: usize,
+}
+
+impl<'c> Generator<'c>{
+    fn line(&mut self, text: &str){
+        for _ in 0..self.indent{
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String{
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+
+    /// 可见的变量，内层的同名变量遮住外层的
+    fn visible(&self) -> Vec<Var>{
+        let mut vars: Vec<Var> = Vec::new();
+        for scope in self.scopes.iter().rev(){
+            for var in scope.iter().rev(){
+                if !vars.iter().any(|v| v.name == var.name) && self.hidden.as_ref() != Some(&var.name){
+                    vars.push(var.clone());
+                }
+            }
+        }
+        vars
+    }
+
+    fn declare(&mut self, var: Var){
+        self.scopes.last_mut().unwrap().push(var);
+    }
+
+    /// 新变量的名字，有时故意和外层的变量重名
+    fn local_name(&mut self) -> String{
+        if self.scopes.len() > 2 && self.rng.gen_ratio(1, 4){
+            let current: Vec<String> = self.scopes.last().unwrap().iter().map(|v| v.name.clone()).collect();
+            let outer: Vec<String> = self.scopes[1..self.scopes.len() - 1].iter().flatten()
+                .map(|v| v.name.clone())
+                .filter(|name| !current.contains(name))
+                .collect();
+            if let Some(name) = outer.choose(&mut self.rng){
+                return name.clone();
+            }
+        }
+        self.fresh("v")
+    }
+
+    fn program(&mut self){
+        for _ in 0..self.rng.gen_range(0..=self.config.globals){
+            self.global();
+        }
+        self.line("");
+        for _ in 0..self.rng.gen_range(0..=self.config.functions){
+            self.function();
+            self.line("");
+        }
+        self.main();
+    }
+
+    fn global(&mut self){
+        match self.rng.gen_range(0..3){
+            0 => self.const_decl(),
+            1 => {
+                let name = self.fresh("g");
+                let init = if self.rng.gen_bool(0.5) {format!(" = {}", self.const_exp(2).0)} else {String::new()};
+                self.line(&format!("int {}{};", name, init));
+                self.declare(Var::new(name, Ty::Int));
+            }
+            _ => {
+                let name = self.fresh("g");
+                let dims = self.dims();
+                let init = if self.rng.gen_bool(0.5){
+                    format!(" = {}", self.array_init(&dims.iter().map(|(_, n)| *n).collect::<Vec<_>>(), true))
+                } else {
+                    String::new()
+                };
+                let text: String = dims.iter().map(|(text, _)| format!("[{}]", text)).collect();
+                self.line(&format!("int {}{}{};", name, text, init));
+                self.declare(Var::new(name, Ty::Array(dims.into_iter().map(|(_, n)| n).collect())));
+            }
+        }
+    }
+
+    fn const_decl(&mut self){
+        let name = if self.scopes.len() == 1 {self.fresh("c")} else {self.local_name()};
+        self.hidden = Some(name.clone());
+        let (text, value) = self.const_exp(2);
+        self.hidden = None;
+        self.line(&format!("const int {} = {};", name, text));
+        self.declare(Var{name, ty: Ty::Int, mutable: false, value: Some(value), trips: None});
+    }
+
+    /// 数组的各维，长度有时用值合适的常量给出
+    fn dims(&mut self) -> Vec<(String, usize)>{
+        let consts: Vec<Var> = self.visible().into_iter().filter(|v| v.value.is_some_and(|n| (1..=4).contains(&n))).collect();
+        (0..self.rng.gen_range(1..=3)).map(|_|{
+            match consts.choose(&mut self.rng){
+                Some(c) if self.rng.gen_bool(0.3) => (c.name.clone(), c.value.unwrap() as usize),
+                _ => {
+                    let n = self.rng.gen_range(1..=4);
+                    (n.to_string(), n)
+                }
+            }
+        }).collect()
+    }
+
+    /// 嵌套的初始化列表，每层可以只给出前面几个
+    fn array_init(&mut self, dims: &[usize], constant: bool) -> String{
+        let count = self.rng.gen_range(0..=dims[0]);
+        let elems: Vec<String> = (0..count).map(|_|{
+            if dims.len() == 1{
+                if constant {self.const_exp(1).0} else {self.exp(1)}
+            } else {
+                self.array_init(&dims[1..], constant)
+            }
+        }).collect();
+        format!("{{{}}}", elems.join(", "))
+    }
+
+    /// 由字面量和常量组成的表达式，同时算出它的值
+    fn const_exp(&mut self, depth: usize) -> (String, i32){
+        let consts: Vec<Var> = self.visible().into_iter().filter(|v| v.value.is_some()).collect();
+        if depth == 0 || self.rng.gen_bool(0.4){
+            return match consts.choose(&mut self.rng){
+                Some(c) if self.rng.gen_bool(0.4) => (c.name.clone(), c.value.unwrap()),
+                _ => {
+                    let n = self.rng.gen_range(0..20);
+                    (n.to_string(), n)
+                }
+            };
+        }
+        let (lhs, a) = self.const_exp(depth - 1);
+        let (rhs, b) = self.const_exp(depth - 1);
+        match self.rng.gen_range(0..3){
+            0 => (format!("({} + {})", lhs, rhs), a.wrapping_add(b)),
+            1 => (format!("({} - {})", lhs, rhs), a.wrapping_sub(b)),
+            _ => (format!("({} * {})", lhs, rhs), a.wrapping_mul(b)),
+        }
+    }
+
+    fn literal(&mut self) -> String{
+        match self.rng.gen_range(0..10){
+            0..=6 => self.rng.gen_range(0..10).to_string(),
+            7 | 8 => self.rng.gen_range(0..1000).to_string(),
+            _ => self.rng.gen_range(0..=i32::MAX).to_string(),
+        }
+    }
+
+    /// 长度为 `n` 的一维上合法的下标
+    fn index(&mut self, n: usize) -> String{
+        if n == 1 && self.rng.gen_bool(0.7){
+            return "0".to_string();
+        }
+        let counters: Vec<Var> = self.visible().into_iter().filter(|v| v.trips.is_some_and(|t| t as usize <= n)).collect();
+        match self.rng.gen_range(0..3){
+            0 => self.rng.gen_range(0..n).to_string(),
+            1 if !counters.is_empty() => format!("{} - 1", counters.choose(&mut self.rng).unwrap().name),
+            _ => {
+                let e = self.exp(1);
+                format!("({} % {} + {}) % {}", e, n, n, n)
+            }
+        }
+    }
+
+    /// 随机的数组元素，`mutable` 为真时只选能赋值的数组
+    fn element(&mut self, mutable: bool) -> Option<String>{
+        let arrays: Vec<Var> = self.visible().into_iter()
+            .filter(|v| matches!(v.ty, Ty::Array(_)) && (v.mutable || !mutable))
+            .collect();
+        let var = arrays.choose(&mut self.rng)?.clone();
+        let Ty::Array(dims) = &var.ty else {unreachable!()};
+        let mut text = var.name.clone();
+        for &n in dims{
+            text += &format!("[{}]", self.index(n));
+        }
+        Some(text)
+    }
+
+    fn atom(&mut self) -> String{
+        let scalars: Vec<Var> = self.visible().into_iter().filter(|v| v.ty == Ty::Int).collect();
+        match self.rng.gen_range(0..6){
+            0 | 1 => self.literal(),
+            2 => match self.element(false){
+                Some(element) => element,
+                None => self.literal(),
+            },
+            _ => match scalars.choose(&mut self.rng){
+                Some(var) => var.name.clone(),
+                None => self.literal(),
+            },
+        }
+    }
+
+    fn exp(&mut self, depth: usize) -> String{
+        if depth == 0 || self.rng.gen_ratio(1, 4){
+            return self.atom();
+        }
+        let d = depth - 1;
+        match self.rng.gen_range(0..12){
+            0..=2 => {
+                let op = ["+", "-", "*"].choose(&mut self.rng).unwrap();
+                format!("({} {} {})", self.exp(d), op, self.exp(d))
+            }
+            // 除数落在 2..=14 之间
+            3 => {
+                let op = ["/", "%"].choose(&mut self.rng).unwrap();
+                format!("({} {} ({} % 7 + 8))", self.exp(d), op, self.exp(d))
+            }
+            4 => {
+                let op = ["-", "!", "+"].choose(&mut self.rng).unwrap();
+                format!("{}({})", op, self.exp(d))
+            }
+            5 | 6 => {
+                let op = ["<", ">", "<=", ">=", "==", "!="].choose(&mut self.rng).unwrap();
+                format!("({} {} {})", self.exp(d), op, self.exp(d))
+            }
+            7 | 8 => {
+                let op = ["&&", "||"].choose(&mut self.rng).unwrap();
+                format!("({} {} {})", self.exp(d), op, self.exp(d))
+            }
+            9 | 10 => self.call(true, d).unwrap_or_else(|| self.atom()),
+            _ => self.atom(),
+        }
+    }
+
+    /// 调用一个已经定义的函数，代价超出预算或者没有合适的数组实参时返回 `None`
+    fn call(&mut self, returns_int: bool, depth: usize) -> Option<String>{
+        let budget = self.config.budget;
+        let candidates: Vec<usize> = (0..self.funcs.len())
+            .filter(|&i| !returns_int || self.funcs[i].returns_int)
+            .filter(|&i| self.cost.saturating_add(self.weight.saturating_mul(self.funcs[i].cost)) <= budget)
+            .collect();
+        let index = *candidates.choose(&mut self.rng)?;
+        self.call_to(index, depth)
+    }
+
+    /// 调用第 `index` 个函数，没有合适的数组实参时返回 `None`
+    fn call_to(&mut self, index: usize, depth: usize) -> Option<String>{
+        let params = self.funcs[index].params.clone();
+        let mut args = Vec::new();
+        for (i, param) in params.iter().enumerate(){
+            match param{
+                Ty::Int if i == 0 && self.funcs[index].recursive => args.push(self.rng.gen_range(0..=MAX_RECURSION).to_string()),
+                Ty::Int => args.push(self.exp(depth)),
+                Ty::Array(dims) => args.push(self.array_arg(dims)?),
+            }
+        }
+        let func = &self.funcs[index];
+        self.cost += self.weight.saturating_mul(func.cost);
+        Some(format!("{}({})", func.name, args.join(", ")))
+    }
+
+    /// 形状为 `dims` 的数组实参，可以是更高维数组的一部分
+    fn array_arg(&mut self, dims: &[usize]) -> Option<String>{
+        let arrays: Vec<(Var, usize)> = self.visible().into_iter().filter_map(|v| match &v.ty{
+            Ty::Array(shape) if shape.len() >= dims.len() && shape[shape.len() - dims.len()..] == *dims => {
+                let prefix = shape.len() - dims.len();
+                Some((v, prefix))
+            }
+            _ => None,
+        }).collect();
+        let (var, prefix) = arrays.choose(&mut self.rng)?.clone();
+        let Ty::Array(shape) = &var.ty else {unreachable!()};
+        let mut text = var.name.clone();
+        for &n in &shape[..prefix]{
+            text += &format!("[{}]", self.index(n));
+        }
+        Some(text)
+    }
+
+    fn function(&mut self){
+        let name = self.fresh("f");
+        let returns_int = self.rng.gen_bool(0.7);
+        let recursive = self.rng.gen_bool(0.3);
+        let mut params = Vec::new();
+        let mut vars = Vec::new();
+        if recursive{
+            params.push(Ty::Int);
+            vars.push(Var{name: "d".to_string(), ty: Ty::Int, mutable: false, value: None, trips: None});
+        }
+        // 有时超过 8 个参数，多出来的实参经栈传递
+        let count = if self.rng.gen_ratio(1, 5) {self.rng.gen_range(9..=12)} else {self.rng.gen_range(0..=4)};
+        for _ in 0..count{
+            let ty = if self.rng.gen_bool(0.7){
+                Ty::Int
+            } else {
+                Ty::Array((0..self.rng.gen_range(1..=3)).map(|_| self.rng.gen_range(1..=4)).collect())
+            };
+            params.push(ty.clone());
+            vars.push(Var::new(self.fresh("p"), ty));
+        }
+        let header: Vec<String> = vars.iter().map(|v| match &v.ty{
+            Ty::Int => format!("int {}", v.name),
+            Ty::Array(dims) => format!("int {}[]{}", v.name, dims[1..].iter().map(|n| format!("[{}]", n)).collect::<String>()),
+        }).collect();
+        self.line(&format!("{} {}({}) {{", if returns_int {"int"} else {"void"}, name, header.join(", ")));
+        self.indent += 1;
+        self.scopes.push(vars.clone());
+        self.returns_int = returns_int;
+        self.weight = 1;
+        self.cost = 0;
+
+        let result = self.fresh("r");
+        if returns_int{
+            let init = self.exp(2);
+            self.line(&format!("int {} = {};", result, init));
+            self.declare(Var::new(result.clone(), Ty::Int));
+        }
+        // 递归调用放在函数开头，不在循环里，调用次数只取决于深度参数
+        let mut fan_out = 1;
+        if recursive{
+            fan_out = if self.rng.gen_bool(0.3) {2} else {1};
+            let args: Vec<String> = vars[1..].iter().map(|v| v.name.clone()).collect();
+            let call = format!("{}(d - 1{})", name, args.iter().map(|a| format!(", {}", a)).collect::<String>());
+            let text = if returns_int{
+                let calls = vec![call; fan_out].join(" + ");
+                format!("if (d > 0) {{ {} = {} + {}; }}", result, result, calls)
+            } else {
+                format!("if (d > 0) {{ {} }}", vec![format!("{};", call); fan_out].join(" "))
+            };
+            self.line(&text);
+        }
+        // 在 main 之外也调用经栈传参的函数，调用之后还要用到保存在 s 寄存器里的局部变量
+        let wide: Vec<usize> = (0..self.funcs.len())
+            .filter(|&i| self.funcs[i].params.len() > 8 && self.cost.saturating_add(self.funcs[i].cost) <= self.config.budget)
+            .collect();
+        if let Some(&index) = wide.choose(&mut self.rng){
+            if let Some(call) = self.call_to(index, 1){
+                if returns_int && self.funcs[index].returns_int{
+                    self.line(&format!("{} = {} + {};", result, result, call));
+                } else {
+                    self.line(&format!("{};", call));
+                }
+            }
+        }
+        self.block_items(self.config.stmt_depth);
+        if returns_int{
+            let e = self.exp(2);
+            self.line(&format!("return {} + {};", result, e));
+        }
+        self.indent -= 1;
+        self.line("}");
+        self.scopes.pop();
+        // 深度为 d 时一共执行 1 + k + ... + k^d 次函数体
+        let calls: u64 = (0..=MAX_RECURSION as u32).map(|i| (fan_out as u64).pow(i)).sum();
+        let cost = if recursive {self.cost.saturating_mul(calls)} else {self.cost}.max(1);
+        self.funcs.push(Func{name, returns_int, params, cost, recursive});
+    }
+
+    fn main(&mut self){
+        self.line("int main() {");
+        self.indent += 1;
+        self.scopes.push(Vec::new());
+        self.returns_int = true;
+        self.weight = 1;
+        self.cost = 0;
+        self.block_items(self.config.stmt_depth);
+        // 最后输出所有全局变量
+        let globals: Vec<Var> = self.scopes[0].iter().filter(|v| v.mutable).cloned().collect();
+        for var in globals{
+            match &var.ty{
+                Ty::Int => self.line(&format!("putint({}); putch(10);", var.name)),
+                Ty::Array(dims) => {
+                    let rows: usize = dims[..dims.len() - 1].iter().product();
+                    for row in 0..rows{
+                        let mut rest = row;
+                        let mut index = String::new();
+                        for &n in dims[..dims.len() - 1].iter().rev(){
+                            index = format!("[{}]{}", rest % n, index);
+                            rest /= n;
+                        }
+                        self.line(&format!("putarray({}, {}{});", dims[dims.len() - 1], var.name, index));
+                    }
+                }
+            }
+        }
+        let e = self.exp(2);
+        self.line(&format!("return {};", e));
+        self.indent -= 1;
+        self.line("}");
+        self.scopes.pop();
+    }
+
+    fn block_items(&mut self, depth: usize){
+        for _ in 0..self.rng.gen_range(1..=self.config.block_len){
+            self.stmt(depth, true);
+        }
+    }
+
+    /// 带花括号的块，有自己的作用域
+    fn block(&mut self, depth: usize){
+        self.line("{");
+        self.indent += 1;
+        self.scopes.push(Vec::new());
+        self.block_items(depth);
+        self.scopes.pop();
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    /// if、while 的分支：块，或者单条语句
+    fn branch(&mut self, depth: usize){
+        if self.rng.gen_bool(0.7){
+            self.block(depth);
+        } else {
+            self.indent += 1;
+            self.scopes.push(Vec::new());
+            self.stmt(depth, false);
+            self.scopes.pop();
+            self.indent -= 1;
+        }
+    }
+
+    /// 生成一条语句，`allow_decl` 为假时不生成声明
+    fn stmt(&mut self, depth: usize, allow_decl: bool){
+        self.cost += self.weight;
+        let depth_left = depth > 0;
+        match self.rng.gen_range(0..20){
+            0..=2 if allow_decl => {
+                // 后一个变量的初始值可以用到前一个变量
+                let mut defs = Vec::new();
+                for _ in 0..self.rng.gen_range(1..=2){
+                    let name = self.local_name();
+                    self.hidden = Some(name.clone());
+                    let init = self.exp(self.config.exp_depth);
+                    self.hidden = None;
+                    defs.push(format!("{} = {}", name, init));
+                    self.declare(Var::new(name, Ty::Int));
+                }
+                self.line(&format!("int {};", defs.join(", ")));
+            }
+            3 if allow_decl => {
+                let name = self.local_name();
+                self.hidden = Some(name.clone());
+                let dims = self.dims();
+                let init = self.array_init(&dims.iter().map(|(_, n)| *n).collect::<Vec<_>>(), false);
+                self.hidden = None;
+                let text: String = dims.iter().map(|(text, _)| format!("[{}]", text)).collect();
+                self.line(&format!("int {}{} = {};", name, text, init));
+                self.declare(Var::new(name, Ty::Array(dims.into_iter().map(|(_, n)| n).collect())));
+            }
+            4 if allow_decl => self.const_decl(),
+            5..=7 => {
+                let scalars: Vec<Var> = self.visible().into_iter().filter(|v| v.ty == Ty::Int && v.mutable).collect();
+                let target = match scalars.choose(&mut self.rng){
+                    Some(var) if self.rng.gen_bool(0.7) => Some(var.name.clone()),
+                    _ => self.element(true),
+                };
+                match target{
+                    Some(target) => {
+                        let e = self.exp(self.config.exp_depth);
+                        self.line(&format!("{} = {};", target, e));
+                    }
+                    None => self.output(allow_decl),
+                }
+            }
+            8 | 9 if depth_left => {
+                let cond = self.exp(self.config.exp_depth);
+                self.line(&format!("if ({})", cond));
+                self.branch(depth - 1);
+                if self.rng.gen_bool(0.5){
+                    self.line("else");
+                    self.branch(depth - 1);
+                }
+            }
+            // 循环前面要声明循环变量，不能作为单条语句
+            10 | 11 if depth_left && allow_decl => self.while_loop(depth - 1),
+            12 if depth_left && allow_decl => self.block(depth - 1),
+            13 | 14 => {
+                let returns_int = self.rng.gen_bool(0.3);
+                match self.call(returns_int, 2){
+                    Some(call) => self.line(&format!("{};", call)),
+                    None => self.output(allow_decl),
+                }
+            }
+            15 if self.loops > 0 => {
+                let cond = self.exp(2);
+                let jump = if self.rng.gen_bool(0.5) {"break"} else {"continue"};
+                self.line(&format!("if ({}) {};", cond, jump));
+            }
+            16 if self.rng.gen_ratio(1, 4) => {
+                let cond = self.exp(2);
+                if self.returns_int{
+                    let e = self.exp(2);
+                    self.line(&format!("if ({}) return {};", cond, e));
+                } else {
+                    self.line(&format!("if ({}) return;", cond));
+                }
+            }
+            17 => self.line(";"),
+            _ => self.output(allow_decl),
+        }
+    }
+
+    /// 输出一个表达式，`separate` 为真时后面跟一个分隔符
+    fn output(&mut self, separate: bool){
+        let e = self.exp(self.config.exp_depth);
+        if separate{
+            let sep = if self.rng.gen_bool(0.5) {10} else {32};
+            self.line(&format!("putint({}); putch({});", e, sep));
+        } else {
+            self.line(&format!("putint({});", e));
+        }
+    }
+
+    /// 循环变量在循环体开头加一，`continue` 也不会让循环停不下来
+    fn while_loop(&mut self, depth: usize){
+        let trips = self.rng.gen_range(1..=MAX_TRIPS);
+        if self.weight * (trips as u64 + 1) * self.config.block_len as u64 > self.config.budget{
+            return self.output(true);
+        }
+        let counter = self.fresh("i");
+        self.line(&format!("int {} = 0;", counter));
+        let outer = self.weight;
+        self.weight *= trips as u64 + 1;
+        let extra = if self.rng.gen_bool(0.3) {format!(" && {}", self.exp(2))} else {String::new()};
+        self.weight = outer * trips as u64;
+        self.line(&format!("while ({} < {}{}) {{", counter, trips, extra));
+        self.indent += 1;
+        self.line(&format!("{} = {} + 1;", counter, counter));
+        self.scopes.push(Vec::new());
+        self.declare(Var{name: counter.clone(), ty: Ty::Int, mutable: false, value: None, trips: Some(trips)});
+        self.loops += 1;
+        self.block_items(depth);
+        self.loops -= 1;
+        self.scopes.pop();
+        self.indent -= 1;
+        self.line("}");
+        self.weight = outer;
+        // 条件里多出来的部分可能让循环一次也不执行，循环之后不再把它当作下标
+        self.declare(Var{name: counter, ty: Ty::Int, mutable: false, value: None, trips: None});
+    }
+}
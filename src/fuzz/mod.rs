@@ -0,0 +1,167 @@
+pub mod generator;
+pub mod reduce;
+
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use crate::interpreter::{ir, riscv, Execution, RuntimeError};
+use crate::{compile, compile_to_koopa, Emit, OptLevel, Options, RegAllocKind};
+
+/// 编译流水线上可以拿来运行的一个阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage{
+    /// 在解释器里运行优化后的 Koopa IR
+    Koopa(OptLevel),
+    /// 在模拟器里运行生成的汇编
+    Riscv(OptLevel, RegAllocKind),
+}
+impl Stage{
+    /// 其余阶段比较的基准
+    pub const REFERENCE: Stage = Stage::Koopa(OptLevel::O0);
+
+    /// 所有阶段，第一个是 [`Stage::REFERENCE`]
+    pub fn all() -> Vec<Stage>{
+        let mut stages = Vec::new();
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2]{
+            stages.push(Stage::Koopa(opt_level));
+            for reg_alloc in RegAllocKind::ALL{
+                stages.push(Stage::Riscv(opt_level, reg_alloc));
+            }
+        }
+        stages
+    }
+}
+impl fmt::Display for Stage{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            Stage::Koopa(opt_level) => write!(f, "koopa {:?}", opt_level),
+            Stage::Riscv(opt_level, reg_alloc) => write!(f, "riscv {:?} --regalloc={}", opt_level, reg_alloc.name()),
+        }
+    }
+}
+
+/// 交叉检验发现的问题
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure{
+    /// 编译器在这个阶段 panic，附带 panic 的信息
+    Panic(Stage, String),
+    /// 编译器拒绝了合法的程序
+    Rejected(Stage),
+    Runtime(Stage, RuntimeError),
+    /// 运行结果和基准阶段不一致
+    Mismatch{stage: Stage, reference: Stage, expected: Execution, actual: Execution},
+}
+impl Failure{
+    pub fn stage(&self) -> Stage{
+        match self{
+            Failure::Panic(stage, _) | Failure::Rejected(stage) | Failure::Runtime(stage, _) => *stage,
+            Failure::Mismatch{stage, ..} => *stage,
+        }
+    }
+
+    /// `source` 是否还有同样的问题，只运行出问题的阶段和基准阶段。
+    /// panic 要求信息相同；其余的要求基准阶段能正常运行（它本身出的问题除外），
+    /// 免得化简程序时引入了未定义行为
+    pub fn reproduces(&self, source: &str, input: &[u8], max_steps: Option<u64>) -> bool{
+        if let Failure::Panic(stage, message) = self{
+            return matches!(run_stage(source, *stage, input, max_steps), Err(Failure::Panic(_, m)) if m == *message);
+        }
+        let expected = run_stage(source, Stage::REFERENCE, input, max_steps);
+        let actual = if self.stage() == Stage::REFERENCE{
+            expected.clone()
+        } else if expected.is_err(){
+            return false;
+        } else {
+            run_stage(source, self.stage(), input, max_steps)
+        };
+        match (self, actual){
+            (Failure::Rejected(_), Err(Failure::Rejected(_))) => true,
+            (Failure::Runtime(_, e), Err(Failure::Runtime(_, actual))) => {
+                std::mem::discriminant(e) == std::mem::discriminant(&actual)
+            }
+            (Failure::Mismatch{..}, Ok(actual)) => {
+                let expected = expected.unwrap();
+                actual.output != expected.output || actual.exit_code != expected.exit_code
+            }
+            _ => false,
+        }
+    }
+}
+impl fmt::Display for Failure{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            Failure::Panic(stage, message) => write!(f, "{}: compiler panicked: {}", stage, message),
+            Failure::Rejected(stage) => write!(f, "{}: compiler rejected the program", stage),
+            Failure::Runtime(stage, e) => write!(f, "{}: {}", stage, e),
+            Failure::Mismatch{stage, reference, expected, actual} => {
+                if expected.exit_code != actual.exit_code{
+                    write!(f, "{}: returned {} but {} returned {}", stage, actual.exit_code, reference, expected.exit_code)
+                } else {
+                    write!(f, "{}: output differs from {}", stage, reference)
+                }
+            }
+        }
+    }
+}
+
+/// 编译到 `stage` 并运行，编译器的 panic 也作为结果返回
+pub fn run_stage(source: &str, stage: Stage, input: &[u8], max_steps: Option<u64>) -> Result<Execution, Failure>{
+    let result = catch_unwind(AssertUnwindSafe(|| match stage{
+        Stage::Koopa(opt_level) => {
+            let options = Options{opt_level, ..Options::default()};
+            compile_to_koopa(source, &options).map(|(program, _)| ir::run(&program, input, max_steps))
+        }
+        Stage::Riscv(opt_level, reg_alloc) => {
+            let options = Options{emit: vec![Emit::Asm], opt_level, reg_alloc, ..Options::default()};
+            compile(source, &options).map(|output| riscv::run(output.get(Emit::Asm).unwrap(), input, max_steps))
+        }
+    }));
+    match result{
+        Ok(Ok(execution)) => execution.map_err(|e| Failure::Runtime(stage, e)),
+        Ok(Err(_)) => Err(Failure::Rejected(stage)),
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(Failure::Panic(stage, message))
+        }
+    }
+}
+
+/// 在所有阶段运行程序，结果都一致时返回基准阶段的结果，否则返回第一个问题
+pub fn cross_check(source: &str, input: &[u8], max_steps: Option<u64>) -> Result<Execution, Failure>{
+    let stages = Stage::all();
+    let reference = Stage::REFERENCE;
+    let expected = run_stage(source, reference, input, max_steps)?;
+    for &stage in &stages[1..]{
+        let actual = run_stage(source, stage, input, max_steps)?;
+        // 执行的指令条数本来就不同
+        if actual.output != expected.output || actual.exit_code != expected.exit_code{
+            return Err(Failure::Mismatch{stage, reference, expected, actual});
+        }
+    }
+    Ok(expected)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use super::generator::{generate, Config};
+
+    #[test]
+    fn generated_programs_agree_across_stages(){
+        let config = Config::default();
+        for seed in 0..10{
+            let source = generate(seed, &config);
+            if let Err(failure) = cross_check(&source, &[], Some(100_000_000)){
+                panic!("seed {}: {}\n{}", seed, failure, source);
+            }
+        }
+    }
+
+    #[test]
+    fn generation_is_deterministic(){
+        let config = Config::default();
+        assert_eq!(generate(42, &config), generate(42, &config));
+        assert_ne!(generate(1, &config), generate(2, &config));
+    }
+}
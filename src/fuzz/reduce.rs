@@ -0,0 +1,587 @@
+use std::collections::HashSet;
+use crate::front_end::ast::*;
+use crate::front_end::printer::print;
+
+/// 对语法树的一类化简，每一类在树上有若干个可以下手的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pass{
+    /// 删掉一个全局声明或者 `main` 以外的函数
+    RemoveGlobal,
+    /// 删掉块里连续的若干项
+    RemoveItems(usize),
+    /// 删掉函数的一个形参和所有调用处对应的实参
+    RemoveParam,
+    /// 删掉一条声明里的一个定义
+    RemoveDef,
+    /// 把 if、while 换成它的分支，或者把块语句并入外层的块
+    Unwrap,
+    /// 把表达式换成它的一个操作数或者常数
+    Simplify,
+    /// 删掉形参之后，去掉调用处对应的实参
+    RemoveArgs,
+}
+
+const PASSES: [Pass; 11] = [Pass::RemoveGlobal, Pass::RemoveItems(32), Pass::RemoveItems(16), Pass::RemoveItems(8),
+    Pass::RemoveItems(4), Pass::RemoveItems(2), Pass::RemoveItems(1), Pass::RemoveParam, Pass::RemoveDef, Pass::Unwrap,
+    Pass::Simplify];
+
+/// 在语法树上做 delta debugging：反复删掉全局声明、函数、语句、定义和形参，把语句换成分支、
+/// 把表达式换成操作数或常数，只要 `interesting` 对化简后的程序仍然成立就保留，直到哪一步都不再成立。
+/// 为了不引入未定义行为，不删 `main` 和 int 函数最后的 `return`，数组下标只换成 0。
+/// `source` 无法解析，或者重新输出之后 `interesting` 就不成立时返回 `None`
+pub fn reduce(source: &str, mut interesting: impl FnMut(&str) -> bool) -> Option<String>{
+    let mut best = crate::sysy::CompUnitParser::new().parse(source).ok()?;
+    let mut best_text = print(&best);
+    if !interesting(&best_text){
+        return None;
+    }
+    // 不同的化简可能得到同样的程序，每个程序只问一次
+    let mut tried = HashSet::new();
+    tried.insert(best_text.clone());
+    let mut progress = true;
+    while progress{
+        progress = false;
+        for pass in PASSES{
+            let mut site = 0;
+            while let Some(candidate) = apply(&best, pass, site){
+                let text = print(&candidate);
+                if tried.insert(text.clone()) && interesting(&text){
+                    // 这个位置上的东西没了，原来后面的位置顺次前移，所以位置不变
+                    best = candidate;
+                    best_text = text;
+                    progress = true;
+                } else {
+                    site += 1;
+                }
+            }
+        }
+    }
+    Some(best_text)
+}
+
+/// 在第 `site` 个位置上做 `pass` 这类化简，位置超出范围时返回 `None`
+fn apply(unit: &CompUnit, pass: Pass, site: usize) -> Option<CompUnit>{
+    let mut unit = unit.clone();
+    let mut editor = Editor{pass, target: site, seen: 0, removed_param: None};
+    editor.unit(&mut unit);
+    if !editor.done(){
+        return None;
+    }
+    if let Some(param) = editor.removed_param{
+        let mut editor = Editor{pass: Pass::RemoveArgs, target: usize::MAX, seen: 0, removed_param: Some(param)};
+        editor.unit(&mut unit);
+    }
+    Some(unit)
+}
+
+struct Editor{
+    pass: Pass,
+    /// 要化简的位置
+    target: usize,
+    /// 已经数过的位置
+    seen: usize,
+    /// 删掉的形参：函数名和第几个
+    removed_param: Option<(String, usize)>,
+}
+
+/// 从 `first` 和 `rest` 组成的列表里删掉第 `index` 个，返回是否删成了
+fn remove_nth<T>(first: &mut T, rest: &mut Vec<T>, index: usize) -> bool{
+    if index == 0 && !rest.is_empty(){
+        *first = rest.remove(0);
+        true
+    } else if index > 0 && index <= rest.len(){
+        rest.remove(index - 1);
+        true
+    } else {
+        false
+    }
+}
+
+fn number(num: i32) -> UnaryExp{
+    let span = Span::default();
+    UnaryExp{primary_exp: Some(Box::new(PrimaryExp{exp: None, lval: None, num: Some(num), span})),
+        unary_exp: None, func_call: None, span}
+}
+
+fn number_exp(num: i32) -> Exp{
+    let span = Span::default();
+    let mul = MulExp{unary_exp: Some(Box::new(number(num))), mul_operate: None, span};
+    let add = AddExp{mul_exp: Some(Box::new(mul)), add_operate: None, span};
+    let rel = RelExp{add_exp: Some(Box::new(add)), rel_operate: None, span};
+    let eq = EqExp{rel_exp: Some(Box::new(rel)), eq_operate: None, span};
+    let land = LAndExp{eq_exp: Some(Box::new(eq)), land_operate: None, span};
+    let lor = LOrExp{land_exp: Some(Box::new(land)), lor_operate: None, span};
+    Exp{exp: Some(Box::new(lor)), span}
+}
+
+/// 只是一个一元表达式的表达式
+fn as_unary(exp: &Exp) -> Option<&UnaryExp>{
+    let lor = exp.exp.as_ref().filter(|e| e.lor_operate.is_none())?;
+    let land = lor.land_exp.as_ref().filter(|e| e.land_operate.is_none())?;
+    let eq = land.eq_exp.as_ref().filter(|e| e.eq_operate.is_none())?;
+    let rel = eq.rel_exp.as_ref().filter(|e| e.rel_operate.is_none())?;
+    let add = rel.add_exp.as_ref().filter(|e| e.add_operate.is_none())?;
+    let mul = add.mul_exp.as_ref().filter(|e| e.mul_operate.is_none())?;
+    mul.unary_exp.as_deref()
+}
+
+/// 把表达式放进一元表达式的位置，必要时加括号
+fn to_unary(exp: Exp) -> UnaryExp{
+    if let Some(unary) = as_unary(&exp){
+        return unary.clone();
+    }
+    let span = Span::default();
+    UnaryExp{primary_exp: Some(Box::new(PrimaryExp{exp: Some(Box::new(exp)), lval: None, num: None, span})),
+        unary_exp: None, func_call: None, span}
+}
+
+fn literal(exp: &UnaryExp) -> Option<i32>{
+    exp.primary_exp.as_ref().and_then(|primary| primary.num)
+}
+
+/// int 函数以 `return` 结尾时不能删掉它，否则返回值没有定义
+fn ends_with_return(func_def: &FuncDef) -> bool{
+    matches!(func_def.func_type, FuncType::Int) && func_def.block.block_item.last()
+        .and_then(|item| item.stmt.as_ref())
+        .is_some_and(|stmt| matches!(stmt.stmt_type, StmtType::Return(Some(_))))
+}
+
+impl Editor{
+    /// 数到一个可以化简的位置，返回是不是要化简的那个
+    fn hit(&mut self) -> bool{
+        let hit = self.seen == self.target;
+        self.seen += 1;
+        hit
+    }
+    fn done(&self) -> bool{
+        self.seen > self.target
+    }
+
+    fn unit(&mut self, unit: &mut CompUnit){
+        match self.pass{
+            Pass::RemoveGlobal => {
+                for i in 0..unit.items.len(){
+                    let is_main = matches!(&unit.items[i], GlobalItem::FuncDef(f) if f.id == "main");
+                    if !is_main && self.hit(){
+                        unit.items.remove(i);
+                        return;
+                    }
+                }
+            }
+            Pass::RemoveParam => {
+                for item in &mut unit.items{
+                    let GlobalItem::FuncDef(func_def) = item else {continue};
+                    let Some(params) = &mut func_def.params else {continue};
+                    if func_def.id == "main"{
+                        continue;
+                    }
+                    for index in 0..=params.params.len(){
+                        if self.hit(){
+                            if !remove_nth(&mut params.param, &mut params.params, index){
+                                func_def.params = None;
+                            }
+                            self.removed_param = Some((func_def.id.clone(), index));
+                            return;
+                        }
+                    }
+                }
+            }
+            _ => {
+                for item in &mut unit.items{
+                    match item{
+                        GlobalItem::Decl(decl) => self.decl(decl),
+                        GlobalItem::FuncDef(func_def) => {
+                            let keep_last = ends_with_return(func_def);
+                            self.block(&mut func_def.block, keep_last);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, block: &mut Block, keep_last: bool){
+        if self.done(){
+            return;
+        }
+        let len = block.block_item.len() - keep_last as usize;
+        match self.pass{
+            Pass::RemoveItems(n) => {
+                for start in (0..len).step_by(n){
+                    if self.hit(){
+                        block.block_item.drain(start..(start + n).min(len));
+                        return;
+                    }
+                }
+            }
+            Pass::Unwrap => {
+                for i in 0..block.block_item.len(){
+                    let is_block = matches!(&block.block_item[i].stmt, Some(Stmt{stmt_type: StmtType::StmtBlock(_), ..}));
+                    if is_block && self.hit(){
+                        let Some(Stmt{stmt_type: StmtType::StmtBlock(inner), ..}) = block.block_item[i].stmt.take()
+                            else {unreachable!()};
+                        block.block_item.splice(i..=i, inner.block_item);
+                        return;
+                    }
+                }
+            }
+            _ => {}
+        }
+        for item in &mut block.block_item{
+            if let Some(decl) = &mut item.decl{
+                self.decl(decl);
+            }
+            if let Some(stmt) = &mut item.stmt{
+                self.stmt(stmt);
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt){
+        if self.done(){
+            return;
+        }
+        if self.pass == Pass::Unwrap{
+            let options = match &stmt.stmt_type{
+                StmtType::Branch(BranchType::Matched(_)) => 3,
+                StmtType::Branch(BranchType::UnMatched(_)) | StmtType::While(_) => 1,
+                _ => 0,
+            };
+            for option in 0..options{
+                if self.hit(){
+                    let stmt_type = std::mem::replace(&mut stmt.stmt_type, StmtType::Break);
+                    *stmt = match (stmt_type, option){
+                        (StmtType::Branch(BranchType::Matched(branch)), 0) => branch.1,
+                        (StmtType::Branch(BranchType::Matched(branch)), 1) => branch.2,
+                        (StmtType::Branch(BranchType::Matched(branch)), _) => {
+                            let (cond, then, _) = *branch;
+                            Stmt{stmt_type: StmtType::Branch(BranchType::UnMatched(Box::new((cond, then, None)))), span: stmt.span}
+                        }
+                        (StmtType::Branch(BranchType::UnMatched(branch)), _) => branch.1,
+                        (StmtType::While(w), _) => w.1,
+                        _ => unreachable!(),
+                    };
+                    return;
+                }
+            }
+        }
+        match &mut stmt.stmt_type{
+            StmtType::Return(Some(exp)) | StmtType::Exp(Some(exp)) => self.exp(exp),
+            StmtType::Assign((lval, exp)) => {
+                self.lval(lval);
+                self.exp(exp);
+            }
+            StmtType::StmtBlock(block) => self.block(block, false),
+            StmtType::Branch(BranchType::Matched(branch)) => {
+                self.exp(&mut branch.0);
+                self.stmt(&mut branch.1);
+                self.stmt(&mut branch.2);
+            }
+            StmtType::Branch(BranchType::UnMatched(branch)) => {
+                self.exp(&mut branch.0);
+                self.stmt(&mut branch.1);
+                if let Some(otherwise) = &mut branch.2{
+                    self.stmt(otherwise);
+                }
+            }
+            StmtType::While(w) => {
+                self.exp(&mut w.0);
+                self.stmt(&mut w.1);
+            }
+            _ => {}
+        }
+    }
+
+    /// 数组的长度不化简，改成 0 就不合法了
+    fn decl(&mut self, decl: &mut Decl){
+        if self.done(){
+            return;
+        }
+        if let Some(const_decl) = &mut decl.const_decl{
+            let rest = const_decl.const_def_vec.get_or_insert_with(Vec::new);
+            if self.pass == Pass::RemoveDef && !rest.is_empty(){
+                for index in 0..=rest.len(){
+                    if self.hit(){
+                        remove_nth(&mut const_decl.const_def, rest, index);
+                        return;
+                    }
+                }
+            }
+            for def in std::iter::once(&mut const_decl.const_def).chain(rest.iter_mut()){
+                if let Some(init) = &mut def.const_init_val{
+                    self.const_init(init);
+                }
+            }
+        }
+        if let Some(var_decl) = &mut decl.var_decl{
+            if self.pass == Pass::RemoveDef && !var_decl.var_def_vec.is_empty(){
+                for index in 0..=var_decl.var_def_vec.len(){
+                    if self.hit(){
+                        remove_nth(&mut var_decl.var_def, &mut var_decl.var_def_vec, index);
+                        return;
+                    }
+                }
+            }
+            for def in std::iter::once(&mut var_decl.var_def).chain(var_decl.var_def_vec.iter_mut()){
+                if let Some(init) = &mut def.initval{
+                    self.init(init);
+                }
+            }
+        }
+    }
+
+    fn const_init(&mut self, init: &mut ConstInitVal){
+        if let Some(exp) = &mut init.const_exp{
+            self.exp(&mut exp.exp);
+        }
+        if let Some(elems) = &mut init.array_init_vec{
+            for elem in std::iter::once(&mut elems.array_init).chain(elems.array_init_vec.iter_mut()){
+                self.const_init(elem);
+            }
+        }
+    }
+
+    fn init(&mut self, init: &mut InitVal){
+        if let Some(exp) = &mut init.exp{
+            self.exp(exp);
+        }
+        if let Some(elems) = &mut init.array_init_vec{
+            for elem in std::iter::once(&mut elems.array_init).chain(elems.array_init_vec.iter_mut()){
+                self.init(elem);
+            }
+        }
+    }
+
+    /// 下标只换成 0，换成其中的操作数可能越界
+    fn lval(&mut self, lval: &mut Lval){
+        for index in &mut lval.array_idx{
+            if self.pass == Pass::Simplify && !self.done() && print(index) != "0" && self.hit(){
+                *index = number_exp(0);
+                return;
+            }
+            if self.pass == Pass::RemoveArgs{
+                self.exp(index);
+            }
+        }
+    }
+
+    fn exp(&mut self, exp: &mut Exp){
+        if let Some(lor) = &mut exp.exp{
+            self.lor(lor);
+        }
+    }
+
+    /// 二元运算换成左边或者右边的操作数
+    fn lor(&mut self, exp: &mut LOrExp){
+        if self.done(){
+            return;
+        }
+        if self.pass == Pass::Simplify && exp.lor_operate.is_some(){
+            if self.hit(){
+                *exp = *exp.lor_operate.take().unwrap().0;
+                return;
+            }
+            if self.hit(){
+                exp.land_exp = Some(exp.lor_operate.take().unwrap().1);
+                return;
+            }
+        }
+        if let Some((lhs, rhs)) = &mut exp.lor_operate{
+            self.lor(lhs);
+            self.land(rhs);
+        } else if let Some(land) = &mut exp.land_exp{
+            self.land(land);
+        }
+    }
+
+    fn land(&mut self, exp: &mut LAndExp){
+        if self.done(){
+            return;
+        }
+        if self.pass == Pass::Simplify && exp.land_operate.is_some(){
+            if self.hit(){
+                *exp = *exp.land_operate.take().unwrap().0;
+                return;
+            }
+            if self.hit(){
+                exp.eq_exp = Some(exp.land_operate.take().unwrap().1);
+                return;
+            }
+        }
+        if let Some((lhs, rhs)) = &mut exp.land_operate{
+            self.land(lhs);
+            self.eq(rhs);
+        } else if let Some(eq) = &mut exp.eq_exp{
+            self.eq(eq);
+        }
+    }
+
+    fn eq(&mut self, exp: &mut EqExp){
+        if self.done(){
+            return;
+        }
+        if self.pass == Pass::Simplify && exp.eq_operate.is_some(){
+            if self.hit(){
+                *exp = *exp.eq_operate.take().unwrap().0;
+                return;
+            }
+            if self.hit(){
+                exp.rel_exp = Some(exp.eq_operate.take().unwrap().2);
+                return;
+            }
+        }
+        if let Some((lhs, _, rhs)) = &mut exp.eq_operate{
+            self.eq(lhs);
+            self.rel(rhs);
+        } else if let Some(rel) = &mut exp.rel_exp{
+            self.rel(rel);
+        }
+    }
+
+    fn rel(&mut self, exp: &mut RelExp){
+        if self.done(){
+            return;
+        }
+        if self.pass == Pass::Simplify && exp.rel_operate.is_some(){
+            if self.hit(){
+                *exp = *exp.rel_operate.take().unwrap().0;
+                return;
+            }
+            if self.hit(){
+                exp.add_exp = Some(exp.rel_operate.take().unwrap().2);
+                return;
+            }
+        }
+        if let Some((lhs, _, rhs)) = &mut exp.rel_operate{
+            self.rel(lhs);
+            self.add(rhs);
+        } else if let Some(add) = &mut exp.add_exp{
+            self.add(add);
+        }
+    }
+
+    fn add(&mut self, exp: &mut AddExp){
+        if self.done(){
+            return;
+        }
+        if self.pass == Pass::Simplify && exp.add_operate.is_some(){
+            if self.hit(){
+                *exp = *exp.add_operate.take().unwrap().0;
+                return;
+            }
+            if self.hit(){
+                exp.mul_exp = Some(exp.add_operate.take().unwrap().2);
+                return;
+            }
+        }
+        if let Some((lhs, _, rhs)) = &mut exp.add_operate{
+            self.add(lhs);
+            self.mul(rhs);
+        } else if let Some(mul) = &mut exp.mul_exp{
+            self.mul(mul);
+        }
+    }
+
+    fn mul(&mut self, exp: &mut MulExp){
+        if self.done(){
+            return;
+        }
+        if self.pass == Pass::Simplify && exp.mul_operate.is_some(){
+            if self.hit(){
+                *exp = *exp.mul_operate.take().unwrap().0;
+                return;
+            }
+            if self.hit(){
+                exp.unary_exp = Some(exp.mul_operate.take().unwrap().2);
+                return;
+            }
+        }
+        if let Some((lhs, _, rhs)) = &mut exp.mul_operate{
+            self.mul(lhs);
+            self.unary(rhs);
+        } else if let Some(unary) = &mut exp.unary_exp{
+            self.unary(unary);
+        }
+    }
+
+    /// 换成 0，非零的常数换成 1，一元运算换成操作数，函数调用换成一个实参，去掉多余的括号
+    fn unary(&mut self, exp: &mut UnaryExp){
+        if self.done(){
+            return;
+        }
+        if let (Some((name, index)), Some((ident, Some(args)))) = (&self.removed_param, &mut exp.func_call){
+            if name == ident && !remove_nth(&mut args.exp, &mut args.exp_vec, *index) && *index == 0{
+                exp.func_call.as_mut().unwrap().1 = None;
+            }
+        }
+        if self.pass == Pass::Simplify{
+            let num = literal(exp);
+            if num != Some(0) && self.hit(){
+                *exp = number(0);
+                return;
+            }
+            if num.is_some_and(|n| n != 0 && n != 1) && self.hit(){
+                *exp = number(1);
+                return;
+            }
+            if exp.unary_exp.is_some() && self.hit(){
+                *exp = *exp.unary_exp.take().unwrap().1;
+                return;
+            }
+            let paren = exp.primary_exp.as_ref().and_then(|primary| primary.exp.as_deref());
+            if paren.is_some_and(|inner| as_unary(inner).is_some()) && self.hit(){
+                *exp = as_unary(paren.unwrap()).unwrap().clone();
+                return;
+            }
+            if let Some((_, Some(args))) = &exp.func_call{
+                for arg in std::iter::once(&args.exp).chain(args.exp_vec.iter()){
+                    if self.hit(){
+                        *exp = to_unary(arg.clone());
+                        return;
+                    }
+                }
+            }
+        }
+        if let Some(primary) = &mut exp.primary_exp{
+            if let Some(inner) = &mut primary.exp{
+                self.exp(inner);
+            } else if let Some(lval) = &mut primary.lval{
+                self.lval(lval);
+            }
+        } else if let Some((_, inner)) = &mut exp.unary_exp{
+            self.unary(inner);
+        } else if let Some((_, Some(args))) = &mut exp.func_call{
+            for arg in std::iter::once(&mut args.exp).chain(args.exp_vec.iter_mut()){
+                self.exp(arg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::fuzz::{run_stage, Stage};
+
+    #[test]
+    fn reduces_to_the_statement_that_matters(){
+        let source = "int g = 3, h[2] = {1, 2};
+            int f(int a, int b) { int c = a * b; if (c > 5) { c = c - 5; } else c = c + g; return c + 1; }
+            void show(int x) { putint(x); putch(10); }
+            int main() {
+                int i = 0, s = 0;
+                while (i < 4) { s = s + f(i, h[1]); i = i + 1; }
+                show(s);
+                { if (s % 2) putint(f(s, 7)); else show(7); }
+                return s / 3;
+            }";
+        // 输出里有 7 就算有问题
+        let mut runs = 0;
+        let reduced = reduce(source, |text| {
+            runs += 1;
+            run_stage(text, Stage::REFERENCE, &[], Some(100_000)).is_ok_and(|e| e.output.contains(&b'7'))
+        }).unwrap();
+        assert_eq!(reduced, "int main() {\n    putint(7);\n    return 0;\n}\n", "after {} runs", runs);
+        assert!(reduce("int main() { return 0; }", |text| text.contains("putint")).is_none());
+    }
+}
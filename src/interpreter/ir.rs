@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use crate::interpreter::{Execution, Memory, Runtime, RuntimeError, MEMORY_LIMIT};
+
+/// 调用栈的最大深度
+const MAX_DEPTH: usize = 1 << 20;
+
+/// 解释执行 Koopa IR 的 `main`，SSA 形式（基本块参数）和消去 SSA 之后的 IR 都可以执行。
+/// 指针是 32 位的字节地址，`max_steps` 限制执行的指令条数
+pub fn run(program: &Program, input: &[u8], max_steps: Option<u64>) -> Result<Execution, RuntimeError>{
+    let mut interpreter = Interpreter::new(program, input)?;
+    let main = program.func_layout().iter().copied()
+        .find(|&func| program.func(func).name() == "@main" && program.func(func).layout().entry_bb().is_some())
+        .ok_or(RuntimeError::NoMain)?;
+    interpreter.enter(main, Vec::new(), None)?;
+    let mut steps = 0;
+    loop{
+        if max_steps.is_some_and(|limit| steps >= limit){
+            return Err(RuntimeError::StepLimitExceeded(steps));
+        }
+        steps += 1;
+        if let Some(exit_code) = interpreter.step()?{
+            return Ok(Execution{exit_code, output: interpreter.runtime.into_output(), steps});
+        }
+    }
+}
+
+/// 地址 0 不可用，这样空指针访问会报错
+struct Words(Vec<i32>);
+impl Words{
+    /// 在栈顶分配 `size` 字节
+    fn alloc(&mut self, size: usize) -> Result<i32, RuntimeError>{
+        let addr = self.0.len() * 4;
+        let len = self.0.len() + size.div_ceil(4);
+        if len * 4 > MEMORY_LIMIT{
+            return Err(RuntimeError::StackOverflow);
+        }
+        self.0.resize(len, 0);
+        Ok(addr as i32)
+    }
+    fn index(&self, addr: i32) -> Result<usize, RuntimeError>{
+        if addr < 4 || addr % 4 != 0 || addr as usize / 4 >= self.0.len(){
+            return Err(RuntimeError::InvalidAddress(addr));
+        }
+        Ok(addr as usize / 4)
+    }
+}
+impl Memory for Words{
+    fn load(&self, addr: i32) -> Result<i32, RuntimeError>{
+        Ok(self.0[self.index(addr)?])
+    }
+    fn store(&mut self, addr: i32, value: i32) -> Result<(), RuntimeError>{
+        let index = self.index(addr)?;
+        self.0[index] = value;
+        Ok(())
+    }
+}
+
+struct Frame{
+    func: Function,
+    bb: BasicBlock,
+    /// 下一条要执行的指令在块里的位置
+    next: usize,
+    values: HashMap<Value, i32>,
+    /// 进入函数时的栈顶，返回时释放之后分配的内存
+    sp: usize,
+    /// 调用者里等待返回值的 `call`
+    call: Option<Value>,
+}
+
+struct Interpreter<'p>{
+    program: &'p Program,
+    /// 每个函数每个基本块里的指令
+    insts: HashMap<Function, HashMap<BasicBlock, Vec<Value>>>,
+    /// 全局变量的地址
+    globals: HashMap<Value, i32>,
+    memory: Words,
+    frames: Vec<Frame>,
+    runtime: Runtime,
+}
+
+impl<'p> Interpreter<'p>{
+    fn new(program: &'p Program, input: &[u8]) -> Result<Interpreter<'p>, RuntimeError>{
+        let insts = program.funcs().iter().map(|(&func, data)|{
+            let blocks = data.layout().bbs().iter()
+                .map(|(&bb, node)| (bb, node.insts().keys().copied().collect()))
+                .collect();
+            (func, blocks)
+        }).collect();
+        let mut interpreter = Interpreter{program, insts, globals: HashMap::new(), memory: Words(vec![0]),
+            frames: Vec::new(), runtime: Runtime::new(input)};
+        for &global in program.inst_layout(){
+            let data = program.borrow_value(global);
+            if let ValueKind::GlobalAlloc(alloc) = data.kind(){
+                let addr = interpreter.memory.alloc(size_of(&pointee(data.ty())))?;
+                let mut words = Vec::new();
+                flatten(&|value| {
+                    let data = program.borrow_value(value);
+                    (data.kind().clone(), data.ty().clone())
+                }, alloc.init(), &mut words);
+                for (i, word) in words.into_iter().enumerate(){
+                    interpreter.memory.store(addr + 4 * i as i32, word)?;
+                }
+                interpreter.globals.insert(global, addr);
+            }
+        }
+        Ok(interpreter)
+    }
+
+    fn enter(&mut self, func: Function, args: Vec<i32>, call: Option<Value>) -> Result<(), RuntimeError>{
+        if self.frames.len() >= MAX_DEPTH{
+            return Err(RuntimeError::StackOverflow);
+        }
+        let data = self.program.func(func);
+        let values = data.params().iter().copied().zip(args).collect();
+        let bb = data.layout().entry_bb().unwrap();
+        self.frames.push(Frame{func, bb, next: 0, values, sp: self.memory.0.len(), call});
+        Ok(())
+    }
+
+    fn operand(&self, func_data: &FunctionData, value: Value) -> i32{
+        if let Some(&addr) = self.globals.get(&value){
+            return addr;
+        }
+        match func_data.dfg().value(value).kind(){
+            ValueKind::Integer(i) => i.value(),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => 0,
+            _ => *self.frames.last().unwrap().values.get(&value).expect("value used before it is defined"),
+        }
+    }
+
+    fn ty(&self, func_data: &FunctionData, value: Value) -> Type{
+        if self.globals.contains_key(&value){
+            self.program.borrow_value(value).ty().clone()
+        } else {
+            func_data.dfg().value(value).ty().clone()
+        }
+    }
+
+    /// 跳到 `bb`，同时把实参赋给块参数
+    fn jump(&mut self, func_data: &FunctionData, bb: BasicBlock, args: &[Value]){
+        let args: Vec<i32> = args.iter().map(|&arg| self.operand(func_data, arg)).collect();
+        let frame = self.frames.last_mut().unwrap();
+        for (&param, arg) in func_data.dfg().bb(bb).params().iter().zip(args){
+            frame.values.insert(param, arg);
+        }
+        frame.bb = bb;
+        frame.next = 0;
+    }
+
+    /// 执行一条指令，`main` 返回时给出它的返回值
+    fn step(&mut self) -> Result<Option<i32>, RuntimeError>{
+        let program = self.program;
+        let frame = self.frames.last_mut().unwrap();
+        let func_data = program.func(frame.func);
+        let inst = self.insts[&frame.func][&frame.bb][frame.next];
+        frame.next += 1;
+        let result = match func_data.dfg().value(inst).kind(){
+            ValueKind::Alloc(_) => Some(self.memory.alloc(size_of(&pointee(func_data.dfg().value(inst).ty())))?),
+            ValueKind::Load(load) => Some(self.memory.load(self.operand(func_data, load.src()))?),
+            ValueKind::Store(store) => {
+                let dest = self.operand(func_data, store.dest());
+                match func_data.dfg().value(store.value()).kind(){
+                    ValueKind::Aggregate(_) | ValueKind::ZeroInit(_) => {
+                        let mut words = Vec::new();
+                        flatten(&|value| {
+                            let data = func_data.dfg().value(value);
+                            (data.kind().clone(), data.ty().clone())
+                        }, store.value(), &mut words);
+                        for (i, word) in words.into_iter().enumerate(){
+                            self.memory.store(dest.wrapping_add(4 * i as i32), word)?;
+                        }
+                    }
+                    _ => self.memory.store(dest, self.operand(func_data, store.value()))?,
+                }
+                None
+            }
+            ValueKind::GetPtr(ptr) => {
+                let size = size_of(&pointee(&self.ty(func_data, ptr.src())));
+                let src = self.operand(func_data, ptr.src());
+                Some(src.wrapping_add(self.operand(func_data, ptr.index()).wrapping_mul(size as i32)))
+            }
+            ValueKind::GetElemPtr(ptr) => {
+                let size = match pointee(&self.ty(func_data, ptr.src())).kind(){
+                    TypeKind::Array(elem, _) => size_of(elem),
+                    kind => unreachable!("getelemptr on a pointer to {}", kind),
+                };
+                let src = self.operand(func_data, ptr.src());
+                Some(src.wrapping_add(self.operand(func_data, ptr.index()).wrapping_mul(size as i32)))
+            }
+            ValueKind::Binary(bin) => {
+                let lhs = self.operand(func_data, bin.lhs());
+                let rhs = self.operand(func_data, bin.rhs());
+                Some(binary(bin.op(), lhs, rhs)?)
+            }
+            ValueKind::Branch(branch) => {
+                if self.operand(func_data, branch.cond()) != 0{
+                    self.jump(func_data, branch.true_bb(), branch.true_args());
+                } else {
+                    self.jump(func_data, branch.false_bb(), branch.false_args());
+                }
+                None
+            }
+            ValueKind::Jump(jump) => {
+                self.jump(func_data, jump.target(), jump.args());
+                None
+            }
+            ValueKind::Call(call) => {
+                let args: Vec<i32> = call.args().iter().map(|&arg| self.operand(func_data, arg)).collect();
+                let callee = program.func(call.callee());
+                if callee.layout().entry_bb().is_some(){
+                    self.enter(call.callee(), args, Some(inst))?;
+                    None
+                } else {
+                    Some(self.runtime.call(&callee.name()[1..], &args, &mut self.memory)?)
+                }
+            }
+            ValueKind::Return(ret) => {
+                let value = ret.value().map(|value| self.operand(func_data, value));
+                let frame = self.frames.pop().unwrap();
+                self.memory.0.truncate(frame.sp);
+                match self.frames.last_mut(){
+                    None => return Ok(Some(value.unwrap_or(0))),
+                    Some(caller) => {
+                        if let (Some(call), Some(value)) = (frame.call, value){
+                            caller.values.insert(call, value);
+                        }
+                    }
+                }
+                None
+            }
+            kind => unreachable!("unexpected instruction {:?}", kind),
+        };
+        if let Some(result) = result{
+            self.frames.last_mut().unwrap().values.insert(inst, result);
+        }
+        Ok(None)
+    }
+}
+
+fn binary(op: BinaryOp, lhs: i32, rhs: i32) -> Result<i32, RuntimeError>{
+    let value = match op{
+        BinaryOp::NotEq => (lhs != rhs) as i32,
+        BinaryOp::Eq => (lhs == rhs) as i32,
+        BinaryOp::Gt => (lhs > rhs) as i32,
+        BinaryOp::Lt => (lhs < rhs) as i32,
+        BinaryOp::Ge => (lhs >= rhs) as i32,
+        BinaryOp::Le => (lhs <= rhs) as i32,
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+        BinaryOp::Div | BinaryOp::Mod if rhs == 0 => return Err(RuntimeError::DivideByZero),
+        BinaryOp::Div => lhs.wrapping_div(rhs),
+        BinaryOp::Mod => lhs.wrapping_rem(rhs),
+        BinaryOp::And => lhs & rhs,
+        BinaryOp::Or => lhs | rhs,
+        BinaryOp::Xor => lhs ^ rhs,
+        // 和 RISC-V 一样只看移位量的低 5 位
+        BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BinaryOp::Shr => (lhs as u32).wrapping_shr(rhs as u32) as i32,
+        BinaryOp::Sar => lhs.wrapping_shr(rhs as u32),
+    };
+    Ok(value)
+}
+
+fn pointee(ty: &Type) -> Type{
+    match ty.kind(){
+        TypeKind::Pointer(base) => base.clone(),
+        kind => unreachable!("{} is not a pointer", kind),
+    }
+}
+
+/// 指针按 32 位计算，不依赖 `Type::size` 的全局设置
+fn size_of(ty: &Type) -> usize{
+    match ty.kind(){
+        TypeKind::Int32 | TypeKind::Pointer(_) | TypeKind::Function(..) => 4,
+        TypeKind::Unit => 0,
+        TypeKind::Array(elem, len) => size_of(elem) * len,
+    }
+}
+
+/// 把初始值按顺序展开成字，`data` 给出值的种类和类型
+fn flatten(data: &dyn Fn(Value) -> (ValueKind, Type), value: Value, words: &mut Vec<i32>){
+    let (kind, ty) = data(value);
+    match kind{
+        ValueKind::Integer(i) => words.push(i.value()),
+        ValueKind::Aggregate(aggregate) => {
+            for &elem in aggregate.elems(){
+                flatten(data, elem, words);
+            }
+        }
+        _ => words.extend(std::iter::repeat_n(0, size_of(&ty) / 4)),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{compile_to_koopa, OptLevel, Options};
+
+    const SOURCE: &str = "int g[2][2] = {{1}, {2, 3}}; int n;\n\
+        int fib(int x) { if (x < 2) return x; return fib(x - 1) + fib(x - 2); }\n\
+        void fill(int a[], int k) { int i = 0; while (i < k) { a[i] = i * i; i = i + 1; } }\n\
+        int main() { int a[5]; n = getint(); fill(a, 5); putarray(5, a); putint(fib(n) + g[1][1]); putch(10);\n\
+        if (n < 8 && a[2] / 0) return 1; return a[4] - g[1][0]; }";
+
+    fn run_source(source: &str, opt_level: OptLevel, input: &str) -> Result<Execution, RuntimeError>{
+        let options = Options{opt_level, ..Options::default()};
+        let (program, _) = compile_to_koopa(source, &options).unwrap();
+        run(&program, input.as_bytes(), Some(1_000_000))
+    }
+
+    #[test]
+    fn runs_at_every_opt_level(){
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2]{
+            let execution = run_source(SOURCE, opt_level, " 10\n").unwrap();
+            assert_eq!(String::from_utf8(execution.output).unwrap(), "5: 0 1 4 9 16\n58\n");
+            assert_eq!(execution.exit_code, 14);
+        }
+    }
+
+    #[test]
+    fn runtime_errors(){
+        assert_eq!(run_source(SOURCE, OptLevel::O0, "6"), Err(RuntimeError::DivideByZero));
+        assert!(matches!(run_source("int main() { while (1) {} return 0; }", OptLevel::O1, ""),
+                         Err(RuntimeError::StepLimitExceeded(_))));
+        assert_eq!(run_source("int f(int x) { int a[1000000]; a[x] = x; return f(a[x] + 1); }\n\
+                              int main() { return f(0); }", OptLevel::O0, ""),
+                   Err(RuntimeError::StackOverflow));
+    }
+}
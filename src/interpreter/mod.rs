@@ -0,0 +1,140 @@
+pub mod ir;
+pub mod riscv;
+
+use std::fmt;
+
+/// 栈和全局变量一共能用的内存（字节）
+pub const MEMORY_LIMIT: usize = 256 << 20;
+
+/// 按字节编址、以字为单位读写的内存，解释器和模拟器各自实现
+pub trait Memory{
+    fn load(&self, addr: i32) -> Result<i32, RuntimeError>;
+    fn store(&mut self, addr: i32, value: i32) -> Result<(), RuntimeError>;
+}
+
+/// 一次运行的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution{
+    /// `main` 的返回值
+    pub exit_code: i32,
+    pub output: Vec<u8>,
+    /// 执行过的指令条数，运行时库函数的调用算一条
+    pub steps: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError{
+    NoMain,
+    UndefinedFunction(String),
+    DivideByZero,
+    InvalidAddress(i32),
+    StackOverflow,
+    StepLimitExceeded(u64),
+    /// 汇编文本里认不出的行或者找不到的标号
+    InvalidAssembly(String),
+}
+impl fmt::Display for RuntimeError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            RuntimeError::NoMain => write!(f, "no `main` function"),
+            RuntimeError::UndefinedFunction(name) => write!(f, "call to undefined function `{}`", name),
+            RuntimeError::DivideByZero => write!(f, "division by zero"),
+            RuntimeError::InvalidAddress(addr) => write!(f, "invalid memory access at {:#x}", addr),
+            RuntimeError::StackOverflow => write!(f, "stack overflow"),
+            RuntimeError::StepLimitExceeded(limit) => write!(f, "exceeded the limit of {} steps", limit),
+            RuntimeError::InvalidAssembly(reason) => write!(f, "invalid assembly: {}", reason),
+        }
+    }
+}
+
+/// SysY 运行时库，和 `SymbolTable::init_lib_fun` 声明的函数一一对应，
+/// 行为与 `libsysy` 相同：输入按 `scanf("%d")` 解析，输出写进缓冲区
+pub struct Runtime{
+    input: Vec<u8>,
+    pos: usize,
+    output: Vec<u8>,
+}
+impl Runtime{
+    pub fn new(input: &[u8]) -> Runtime{
+        Runtime{input: input.to_vec(), pos: 0, output: Vec::new()}
+    }
+    pub fn into_output(self) -> Vec<u8>{
+        self.output
+    }
+    /// 库函数的参数个数，不是库函数时返回 `None`
+    pub fn arity(name: &str) -> Option<usize>{
+        match name{
+            "getint" | "getch" | "starttime" | "stoptime" => Some(0),
+            "getarray" | "putint" | "putch" => Some(1),
+            "putarray" => Some(2),
+            _ => None,
+        }
+    }
+    /// 调用名为 `name` 的库函数，`void` 函数返回 0
+    pub fn call(&mut self, name: &str, args: &[i32], mem: &mut dyn Memory) -> Result<i32, RuntimeError>{
+        let ret = match (name, args){
+            ("getint", []) => self.getint(),
+            ("getch", []) => self.getch(),
+            ("getarray", &[array]) => {
+                let n = self.getint();
+                for i in 0..n{
+                    let value = self.getint();
+                    mem.store(array.wrapping_add(i.wrapping_mul(4)), value)?;
+                }
+                n
+            }
+            ("putint", &[value]) => {
+                self.output.extend(value.to_string().bytes());
+                0
+            }
+            ("putch", &[value]) => {
+                self.output.push(value as u8);
+                0
+            }
+            ("putarray", &[n, array]) => {
+                self.output.extend(format!("{}:", n).bytes());
+                for i in 0..n{
+                    let value = mem.load(array.wrapping_add(i.wrapping_mul(4)))?;
+                    self.output.extend(format!(" {}", value).bytes());
+                }
+                self.output.push(b'\n');
+                0
+            }
+            ("starttime", []) | ("stoptime", []) => 0,
+            _ => return Err(RuntimeError::UndefinedFunction(name.to_string())),
+        };
+        Ok(ret)
+    }
+    fn getch(&mut self) -> i32{
+        match self.input.get(self.pos){
+            Some(&c) => {
+                self.pos += 1;
+                c as i32
+            }
+            None => -1,
+        }
+    }
+    /// 跳过空白读一个十进制整数，读不到时返回 0
+    fn getint(&mut self) -> i32{
+        while self.input.get(self.pos).is_some_and(|c| c.is_ascii_whitespace()){
+            self.pos += 1;
+        }
+        let negative = match self.input.get(self.pos){
+            Some(b'-') => {
+                self.pos += 1;
+                true
+            }
+            Some(b'+') => {
+                self.pos += 1;
+                false
+            }
+            _ => false,
+        };
+        let mut value: i32 = 0;
+        while let Some(c) = self.input.get(self.pos).filter(|c| c.is_ascii_digit()){
+            value = value.wrapping_mul(10).wrapping_add((c - b'0') as i32);
+            self.pos += 1;
+        }
+        if negative {value.wrapping_neg()} else {value}
+    }
+}
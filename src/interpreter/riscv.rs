@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use crate::code_generator::asm::{self, Line};
+use crate::code_generator::machine::{AluOp, MachineInst, Reg};
+use crate::interpreter::{Execution, Memory, Runtime, RuntimeError, MEMORY_LIMIT};
+
+/// 第一条指令的地址，`ra` 里保存的是这样的地址
+const TEXT_BASE: i32 = 0x1_0000;
+/// `.data` 段的起始地址
+const DATA_BASE: i32 = 0x1000_0000;
+/// 栈从这里向下增长
+const STACK_TOP: i32 = 0x7ff0_0000;
+/// `main` 返回到这个地址时结束运行
+const EXIT: i32 = 0;
+/// 库函数返回后写进调用者保存的寄存器，依赖它们跨调用保持不变的代码会算错
+const CLOBBER: i32 = 0x5eed_dead;
+
+/// 模拟执行后端生成的 RV32IM 汇编，从 `main` 开始，`main` 返回时结束。
+/// 库函数调用由 `Runtime` 直接完成，`steps` 是执行过的机器指令条数
+pub fn run(asm: &str, input: &[u8], max_steps: Option<u64>) -> Result<Execution, RuntimeError>{
+    let image = Image::load(asm)?;
+    let mut pc = *image.labels.get("main").ok_or(RuntimeError::NoMain)?;
+    let mut regs = [0i32; 32];
+    regs[Reg::SP.0 as usize] = STACK_TOP;
+    regs[Reg::RA.0 as usize] = EXIT;
+    let mut ram = Ram{data: image.data.clone(), stack: Vec::new()};
+    let mut runtime = Runtime::new(input);
+    let mut steps = 0;
+    loop{
+        if max_steps.is_some_and(|limit| steps >= limit){
+            return Err(RuntimeError::StepLimitExceeded(steps));
+        }
+        steps += 1;
+        let inst = image.insts.get(pc).ok_or(RuntimeError::InvalidAddress(text_addr(pc)))?;
+        pc += 1;
+        let get = |reg: &Reg| regs[reg.0 as usize];
+        let value = match inst{
+            MachineInst::Li(_, imm) => *imm,
+            MachineInst::Lui(_, imm) => imm << 12,
+            MachineInst::La(_, symbol) => image.address(symbol),
+            MachineInst::Mv(_, rs) => get(rs),
+            MachineInst::Op(op, _, rs1, rs2) => alu(*op, get(rs1), get(rs2)),
+            MachineInst::OpImm(op, _, rs, imm) => alu(*op, get(rs), *imm),
+            MachineInst::Seqz(_, rs) => (get(rs) == 0) as i32,
+            MachineInst::Snez(_, rs) => (get(rs) != 0) as i32,
+            MachineInst::Lw(_, offset, base) => ram.load(get(base).wrapping_add(*offset))?,
+            MachineInst::Sw(rs, offset, base) => {
+                ram.store(get(base).wrapping_add(*offset), get(rs))?;
+                continue;
+            }
+            MachineInst::Bnez(rs, label) | MachineInst::Beqz(rs, label) => {
+                if (get(rs) != 0) == matches!(inst, MachineInst::Bnez(..)){
+                    pc = image.labels[label];
+                }
+                continue;
+            }
+            MachineInst::J(label) => {
+                pc = image.labels[label];
+                continue;
+            }
+            MachineInst::Call(func) => {
+                if let Some(&target) = image.labels.get(func){
+                    regs[Reg::RA.0 as usize] = text_addr(pc);
+                    pc = target;
+                    continue;
+                }
+                let arity = Runtime::arity(func).unwrap();
+                let args: Vec<i32> = (0..arity as i32).map(|i| get(&Reg::a(i))).collect();
+                let ret = runtime.call(func, &args, &mut ram)?;
+                for reg in (0..7).map(Reg::t).chain((1..8).map(Reg::a)).chain([Reg::RA]){
+                    regs[reg.0 as usize] = CLOBBER;
+                }
+                regs[Reg::a(0).0 as usize] = ret;
+                continue;
+            }
+            MachineInst::Ret => {
+                let ra = get(&Reg::RA);
+                if ra == EXIT{
+                    return Ok(Execution{exit_code: get(&Reg::a(0)), output: runtime.into_output(), steps});
+                }
+                if ra < TEXT_BASE || (ra - TEXT_BASE) % 4 != 0{
+                    return Err(RuntimeError::InvalidAddress(ra));
+                }
+                pc = ((ra - TEXT_BASE) / 4) as usize;
+                continue;
+            }
+            MachineInst::Comment(_) => continue,
+        };
+        let rd = inst.def().unwrap();
+        if rd != Reg::ZERO{
+            regs[rd.0 as usize] = value;
+        }
+    }
+}
+
+fn text_addr(pc: usize) -> i32{
+    TEXT_BASE.wrapping_add(4 * pc as i32)
+}
+
+fn alu(op: AluOp, lhs: i32, rhs: i32) -> i32{
+    // 除以 0 和溢出不会陷入，结果按 RISC-V 的规定
+    match op{
+        AluOp::Add => lhs.wrapping_add(rhs),
+        AluOp::Sub => lhs.wrapping_sub(rhs),
+        AluOp::Mul => lhs.wrapping_mul(rhs),
+        AluOp::Mulh => ((lhs as i64 * rhs as i64) >> 32) as i32,
+        AluOp::Div if rhs == 0 => -1,
+        AluOp::Div => lhs.wrapping_div(rhs),
+        AluOp::Rem if rhs == 0 => lhs,
+        AluOp::Rem => lhs.wrapping_rem(rhs),
+        AluOp::And => lhs & rhs,
+        AluOp::Or => lhs | rhs,
+        AluOp::Xor => lhs ^ rhs,
+        AluOp::Slt => (lhs < rhs) as i32,
+        AluOp::Sltu => ((lhs as u32) < (rhs as u32)) as i32,
+        AluOp::Sll => lhs.wrapping_shl(rhs as u32),
+        AluOp::Srl => (lhs as u32).wrapping_shr(rhs as u32) as i32,
+        AluOp::Sra => lhs.wrapping_shr(rhs as u32),
+    }
+}
+
+/// 装入内存之前的程序：所有指令排成一列，数据段按字展开
+struct Image{
+    insts: Vec<MachineInst>,
+    /// 代码标号对应的指令下标
+    labels: HashMap<String, usize>,
+    /// 数据标号的地址
+    symbols: HashMap<String, i32>,
+    data: Vec<i32>,
+}
+impl Image{
+    fn load(asm: &str) -> Result<Image, RuntimeError>{
+        let mut image = Image{insts: Vec::new(), labels: HashMap::new(), symbols: HashMap::new(), data: Vec::new()};
+        let mut in_data = false;
+        for line in asm::parse(asm){
+            match line{
+                Line::Inst(MachineInst::Comment(_)) | Line::Comment(_) => {}
+                Line::Inst(inst) if !in_data => image.insts.push(inst),
+                Line::Label(label) if in_data => {
+                    image.symbols.insert(label, DATA_BASE + 4 * image.data.len() as i32);
+                }
+                Line::Label(label) => {
+                    image.labels.insert(label, image.insts.len());
+                }
+                Line::Directive(directive) => {
+                    let (name, arg) = directive.split_once(char::is_whitespace).unwrap_or((&directive, ""));
+                    let number = || arg.trim().parse::<i32>().ok().filter(|_| in_data)
+                        .ok_or_else(|| RuntimeError::InvalidAssembly(format!("`{}`", directive)));
+                    match name{
+                        ".data" => in_data = true,
+                        ".text" => in_data = false,
+                        ".global" | ".globl" => {}
+                        ".word" => image.data.push(number()?),
+                        ".zero" => {
+                            let size = number()? as usize;
+                            image.data.extend(std::iter::repeat_n(0, size.div_ceil(4)));
+                        }
+                        _ => return Err(RuntimeError::InvalidAssembly(format!("unknown directive `{}`", directive))),
+                    }
+                }
+                Line::Inst(inst) => return Err(RuntimeError::InvalidAssembly(format!("`{}` in the data section", inst))),
+                Line::Other(text) => return Err(RuntimeError::InvalidAssembly(format!("`{}`", text.trim()))),
+            }
+        }
+        // 执行之前先检查所有符号都有定义
+        for inst in &image.insts{
+            match inst{
+                MachineInst::Bnez(_, label) | MachineInst::Beqz(_, label) | MachineInst::J(label)
+                    if !image.labels.contains_key(label) =>
+                    return Err(RuntimeError::InvalidAssembly(format!("undefined label `{}`", label))),
+                MachineInst::La(_, symbol) if !image.symbols.contains_key(symbol) && !image.labels.contains_key(symbol) =>
+                    return Err(RuntimeError::InvalidAssembly(format!("undefined symbol `{}`", symbol))),
+                MachineInst::Call(func) if !image.labels.contains_key(func) && Runtime::arity(func).is_none() =>
+                    return Err(RuntimeError::UndefinedFunction(func.clone())),
+                _ => {}
+            }
+        }
+        Ok(image)
+    }
+    fn address(&self, symbol: &str) -> i32{
+        match self.symbols.get(symbol){
+            Some(&addr) => addr,
+            None => text_addr(self.labels[symbol]),
+        }
+    }
+}
+
+/// 数据段和栈两块内存，中间的地址都不能访问
+struct Ram{
+    data: Vec<i32>,
+    /// 从栈顶往下存，用到哪里分配到哪里
+    stack: Vec<i32>,
+}
+enum Slot{
+    Data(usize),
+    Stack(usize),
+}
+impl Ram{
+    fn slot(&self, addr: i32) -> Result<Slot, RuntimeError>{
+        if addr % 4 != 0{
+            return Err(RuntimeError::InvalidAddress(addr));
+        }
+        if addr >= DATA_BASE && ((addr - DATA_BASE) / 4) < self.data.len() as i32{
+            return Ok(Slot::Data(((addr - DATA_BASE) / 4) as usize));
+        }
+        if (STACK_TOP - MEMORY_LIMIT as i32..STACK_TOP).contains(&addr){
+            return Ok(Slot::Stack(((STACK_TOP - 4 - addr) / 4) as usize));
+        }
+        if (DATA_BASE..STACK_TOP).contains(&addr){
+            return Err(RuntimeError::StackOverflow);
+        }
+        Err(RuntimeError::InvalidAddress(addr))
+    }
+}
+impl Memory for Ram{
+    fn load(&self, addr: i32) -> Result<i32, RuntimeError>{
+        Ok(match self.slot(addr)?{
+            Slot::Data(i) => self.data[i],
+            Slot::Stack(i) => self.stack.get(i).copied().unwrap_or(0),
+        })
+    }
+    fn store(&mut self, addr: i32, value: i32) -> Result<(), RuntimeError>{
+        match self.slot(addr)?{
+            Slot::Data(i) => self.data[i] = value,
+            Slot::Stack(i) => {
+                if i >= self.stack.len(){
+                    self.stack.resize(i + 1, 0);
+                }
+                self.stack[i] = value;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{compile, compile_to_koopa, Emit, OptLevel, Options, RegAllocKind};
+    use crate::interpreter::ir;
+
+    const SOURCE: &str = "int g[2][3] = {{1, 2}, {3}}; int n;\n\
+        int fib(int x) { if (x < 2) return x; return fib(x - 1) + fib(x - 2); }\n\
+        int sum(int a, int b, int c, int d, int e, int f, int g, int h, int i, int j) {\n\
+            return a + b * 2 + c * 3 + d * 4 + e * 5 + f * 6 + g * 7 + h * 8 + i * 9 + j * 10; }\n\
+        int main() { int a[10]; n = getarray(a); int i = 0; while (i < n) { a[i] = a[i] * g[i % 2][0] / 3 % 7; i = i + 1; }\n\
+            putarray(n, a); putint(fib(a[0] + 10)); putch(32); putint(sum(a[0], a[1], a[2], n, 5, 6, 7, 8, 9, -1));\n\
+            putch(10); return g[1][0] + n; }";
+
+    #[test]
+    fn agrees_with_the_koopa_interpreter(){
+        let input = b"4 -7 100 3 2147483647";
+        for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2]{
+            let (program, _) = compile_to_koopa(SOURCE, &Options{opt_level, ..Options::default()}).unwrap();
+            let expected = ir::run(&program, input, None).unwrap();
+            for reg_alloc in RegAllocKind::ALL{
+                let options = Options{emit: vec![Emit::Asm], opt_level, reg_alloc, ..Options::default()};
+                let output = compile(SOURCE, &options).unwrap();
+                let execution = run(output.get(Emit::Asm).unwrap(), input, Some(1_000_000)).unwrap();
+                assert_eq!((&execution.output, execution.exit_code), (&expected.output, expected.exit_code),
+                           "{:?} {:?}", opt_level, reg_alloc);
+            }
+        }
+    }
+
+    #[test]
+    fn counts_instructions(){
+        let asm = "\t.data\n\t.global x\nx:\n\t.word 5\n\t.zero 4\n\t.text\n\t.global main\nmain:\n\
+            \tla t0, x\n\tlw a0, 0(t0)\n\tli t1, 3\n\tdiv a0, a0, t1\n\tbnez a0, end\n\tli a0, 9\nend:\n\tret\n";
+        let execution = run(asm, b"", None).unwrap();
+        assert_eq!((execution.exit_code, execution.steps), (1, 6));
+        assert_eq!(run("main:\n\tj nowhere\n", b"", None),
+                   Err(RuntimeError::InvalidAssembly("undefined label `nowhere`".to_string())));
+        assert_eq!(run("main:\n\tlw a0, 0(x0)\n\tret\n", b"", None), Err(RuntimeError::InvalidAddress(0)));
+    }
+}
@@ -0,0 +1,220 @@
+extern crate koopa;
+
+use lalrpop_util::lalrpop_mod;
+
+pub mod code_generator;
+pub mod front_end;
+pub mod optim;
+pub mod diagnostic;
+pub mod interpreter;
+pub mod fuzz;
+
+use code_generator::code_generator::{CodegenContext, GenerateAsm};
+use diagnostic::{parse_error_to_diagnostic, Diagnostics};
+use front_end::ast::CompUnit;
+use koopa::ir::Program;
+use optim::cfg::BuildControlFlowGraph;
+use optim::dominance::DominanceAnalysis;
+use optim::loops::LoopAnalysis;
+
+// 引用 lalrpop 生成的解析器
+lalrpop_mod!(pub sysy);
+
+pub use optim::OptLevel;
+pub use optim::reg_alloc::RegAllocKind;
+
+/// 编译产物的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit{
+    /// 语法树
+    Ast,
+    /// 前端生成的 Koopa IR
+    Koopa,
+    /// 优化后的 Koopa IR
+    KoopaOpt,
+    /// 优化后各函数的控制流图（graphviz 格式）
+    Cfg,
+    /// 优化后各函数的支配、后支配信息
+    Dom,
+    /// 优化后各函数的循环嵌套信息
+    Loops,
+    /// RISC-V 汇编
+    Asm,
+    /// 生成汇编时各函数的寄存器分配统计
+    RegAlloc,
+}
+impl Emit{
+    pub const ALL: [Emit; 8] = [Emit::Ast, Emit::Koopa, Emit::KoopaOpt, Emit::Cfg, Emit::Dom, Emit::Loops, Emit::Asm, Emit::RegAlloc];
+    /// `--emit=` 中使用的名字
+    pub fn name(&self) -> &'static str{
+        match self{
+            Emit::Ast => "ast",
+            Emit::Koopa => "koopa",
+            Emit::KoopaOpt => "koopa-opt",
+            Emit::Cfg => "cfg",
+            Emit::Dom => "dom",
+            Emit::Loops => "loops",
+            Emit::Asm => "asm",
+            Emit::RegAlloc => "regalloc",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Emit>{
+        Emit::ALL.iter().copied().find(|emit| emit.name() == name)
+    }
+    /// 输出到文件时默认使用的扩展名
+    pub fn extension(&self) -> &'static str{
+        match self{
+            Emit::Ast => "ast",
+            Emit::Koopa => "koopa",
+            Emit::KoopaOpt => "opt.koopa",
+            Emit::Cfg => "dot",
+            Emit::Dom => "dom",
+            Emit::Loops => "loops",
+            Emit::Asm => "S",
+            Emit::RegAlloc => "regalloc",
+        }
+    }
+}
+
+/// 一次编译的选项
+#[derive(Debug, Clone, Default)]
+pub struct Options{
+    /// 需要输出的产物，按给出的顺序输出
+    pub emit: Vec<Emit>,
+    pub opt_level: OptLevel,
+    pub reg_alloc: RegAllocKind,
+    /// 内联的函数大小上限，`None` 时 `-O1` 使用 `optim::inline::DEFAULT_THRESHOLD`，
+    /// `-O2` 使用 `optim::inline::O2_THRESHOLD`
+    pub inline_threshold: Option<usize>,
+}
+
+/// 编译成功的结果：各个产物的文本以及编译过程中产生的警告
+#[derive(Debug)]
+pub struct Output{
+    pub artifacts: Vec<(Emit, String)>,
+    pub warnings: Diagnostics,
+}
+impl Output{
+    pub fn get(&self, emit: Emit) -> Option<&str>{
+        self.artifacts.iter().find(|(e, _)| *e == emit).map(|(_, text)| text.as_str())
+    }
+}
+
+/// 编译一个 SysY 源文件。所有状态都保存在本次调用创建的上下文中，
+/// 因此可以在同一进程里反复调用，也可以在多个线程中同时调用。
+/// 出错时返回全部诊断信息（包括警告），由调用者结合 `SourceMap` 输出。
+pub fn compile(source: &str, options: &Options) -> Result<Output, Diagnostics>{
+    let (ast, mut program, diagnostics) = build_koopa(source)?;
+    let koopa = if options.emit.contains(&Emit::Koopa){
+        Some(dump_koopa(&program))
+    } else {
+        None
+    };
+    optim::optimize(&mut program, options.opt_level, options.inline_threshold);
+    let mut artifacts: Vec<(Emit, String)> = options.emit.iter().map(|&emit|{
+        let text = match emit{
+            Emit::Ast => format!("{:#?}\n", ast),
+            Emit::Koopa => koopa.clone().unwrap(),
+            Emit::KoopaOpt => dump_koopa(&program),
+            Emit::Cfg => program.print_control_flow_graph(&program.build_control_flow_graph()),
+            Emit::Dom => program.print_dominance(&program.dominance()),
+            Emit::Loops => program.print_loops(&program.loops()),
+            // 后端不认识 SSA 形式，汇编在下面消去 SSA 之后再生成
+            Emit::Asm | Emit::RegAlloc => String::new(),
+        };
+        (emit, text)
+    }).collect();
+    if options.emit.iter().any(|emit| matches!(emit, Emit::Asm | Emit::RegAlloc)){
+        optim::out_of_ssa::destruct_ssa(&mut program);
+        let mut ctx = CodegenContext::with_reg_alloc(options.reg_alloc);
+        ctx.peephole = options.opt_level >= OptLevel::O1;
+        let asm = program.generate(&mut ctx);
+        for (emit, text) in artifacts.iter_mut(){
+            match emit{
+                Emit::Asm => *text = asm.clone(),
+                Emit::RegAlloc => *text = ctx.alloc_stats.iter().map(|stats| format!("{}\n", stats)).collect(),
+                _ => {}
+            }
+        }
+    }
+    Ok(Output{artifacts, warnings: diagnostics})
+}
+
+/// 编译到优化后的 Koopa IR 为止，给解释器直接执行。成功时一并返回警告
+pub fn compile_to_koopa(source: &str, options: &Options) -> Result<(Program, Diagnostics), Diagnostics>{
+    let (_, mut program, diagnostics) = build_koopa(source)?;
+    optim::optimize(&mut program, options.opt_level, options.inline_threshold);
+    Ok((program, diagnostics))
+}
+
+/// 解析、检查并生成未优化的 Koopa IR
+fn build_koopa(source: &str) -> Result<(CompUnit, Program, Diagnostics), Diagnostics>{
+    let ast = sysy::CompUnitParser::new().parse(source)
+        .map_err(|e| Diagnostics::from(parse_error_to_diagnostic(e, source)))?;
+    let mut diagnostics = front_end::semantic::check(&ast);
+    if diagnostics.has_error(){
+        return Err(diagnostics);
+    }
+    let ctx = front_end::parser::build_program(&ast);
+    for diagnostic in ctx.diagnostics.list{
+        diagnostics.push(diagnostic);
+    }
+    if diagnostics.has_error(){
+        return Err(diagnostics);
+    }
+    let mut program = ctx.program;
+    // 前端生成的 IR 里有不少死代码，删掉之后 `-koopa` 的输出也是干净的
+    optim::dce::dce(&mut program);
+    Ok((ast, program, diagnostics))
+}
+
+/// 把内存中的 Koopa IR 输出为文本形式
+pub fn dump_koopa(program: &Program) -> String{
+    let mut gen = koopa::back::KoopaGenerator::new(Vec::new());
+    gen.generate_on(program).unwrap();
+    String::from_utf8(gen.writer()).unwrap()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const SOURCE: &str = "int g; int f(int x) { if (x > 1) return x * f(x - 1); return 1; }\n\
+        int main() { int a[3] = {1, 2}; while (g < 3) { g = g + 1; } return f(a[1] + g); }";
+
+    #[test]
+    fn compile_twice_in_one_process(){
+        let options = Options{emit: Emit::ALL.to_vec(), opt_level: OptLevel::O2, ..Options::default()};
+        let first = compile(SOURCE, &options).unwrap().artifacts;
+        let second = compile(SOURCE, &options).unwrap().artifacts;
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn compile_in_parallel(){
+        let options = Options{emit: vec![Emit::Asm], ..Options::default()};
+        let expected = compile(SOURCE, &options).unwrap().artifacts;
+        let handles: Vec<_> = (0..4).map(|_|{
+            let options = options.clone();
+            std::thread::spawn(move || compile(SOURCE, &options).unwrap().artifacts)
+        }).collect();
+        for handle in handles{
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn interval_analysis_is_deterministic(){
+        use optim::cfg::IntervalAnalysis;
+        let ast = sysy::CompUnitParser::new().parse(SOURCE).unwrap();
+        let program = front_end::parser::build_program(&ast).program;
+        assert!(program.get_interval() == program.get_interval());
+    }
+
+    #[test]
+    fn errors_are_returned_as_diagnostics(){
+        let options = Options{emit: vec![Emit::Koopa], ..Options::default()};
+        let diagnostics = compile("int main() { return x; }", &options).unwrap_err();
+        assert!(diagnostics.has_error());
+    }
+}
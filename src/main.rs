@@ -1,103 +1,102 @@
-extern crate koopa;
-
-use std::collections::HashSet;
-use lalrpop_util::lalrpop_mod;
 use std::env::args;
-use std::fs::read_to_string;
-use std::io::{Result, Write};
-use std::fs::File;
-use std::hash::Hash;
-use optim::ControlFlowGraph;
-
-
-mod code_generator;
-mod front_end;
-mod optim;
+use std::fs::{read_to_string, File};
+use std::io::{self, Read, Write};
+use sysy_compiler::{compile, Emit, Options};
+use sysy_compiler::interpreter;
+use sysy_compiler::diagnostic::SourceMap;
 
-use front_end::parser::GetKoopa;
-use crate::code_generator::code_generator::GenerateAsm;
-use crate::optim::cfg::{ActiveAnalysis, BuildControlFlowGraph, IntervalAnalysis};
-// 引用 lalrpop 生成的解析器
-// 因为我们刚刚创建了 sysy.lalrpop, 所以模块名是 sysy
+mod cli;
 
-
-lalrpop_mod!(sysy);
+use cli::{Command, Invocation, Run, Stream};
 
 fn main() {
-    let a = try_main();
-    match a{
-        Ok(_) => return,
-        Err(e) => eprintln!("{}", e)
+    let invocation = match cli::parse_args(args().skip(1)){
+        Ok(Command::Compile(invocation)) => invocation,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            eprintln!("run with `--help` for usage");
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = try_main(invocation){
+        eprintln!("error: {}", e);
+        std::process::exit(1);
     }
 }
-fn try_main() -> Result<()> {
-    // 解析命令行参数
-    let mut args = args();
-    args.next();
-    let mode = args.next().unwrap();
-    let input = args.next().unwrap();
-    args.next();
-    let output = args.next().unwrap();
-    let mut file = File::create(output).unwrap();
 
+fn try_main(invocation: Invocation) -> Result<(), String> {
     // 读取输入文件
-    let input = read_to_string(input)?;
-
-
-    // 调用 lalrpop 生成的 parser 解析输入文件
-    let ast = sysy::CompUnitParser::new().parse(&input).unwrap();
-    // println!("{:#?}", ast);
-    // 输出解析得到的 AST
-    // println!("{:#?}", ast);
-    // println!("{}", ir);
-
-    if mode == "-koopa"{
-        let ir = ast.get_koopa();
-        file.write(ir.as_bytes());
-    } else if mode == "-riscv"{
-        let ir = ast.get_koopa();
-        println!("{}", ir);
-        let driver = koopa::front::Driver::from(ir);
-        let program = driver.generate_program().unwrap();
-        // println!("{:#?}", program.func_layout());
-        let ir = program.generate();
-        file.write(ir.as_bytes());
-    } else if mode == "-perf"{
-        let ir = ast.get_koopa();
-        file.write(ir.as_bytes());
-        let driver = koopa::front::Driver::from(ir);
-        let program = driver.generate_program().unwrap();
-        // println!("{:#?}", program.func_layout());
-        let ir = program.generate();
-        // let (act,cfg) = program.active_analysis();
-        let tmp = program.get_interval();
-        // println!("{:#?}", act);
-        // println!("{:#?}", cfg);
+    let (input_name, input) = match &invocation.input{
+        Stream::Stdio => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input).map_err(|e| format!("cannot read stdin: {}", e))?;
+            ("<stdin>".to_string(), input)
+        }
+        Stream::Path(path) => {
+            let input = read_to_string(path).map_err(|e| format!("cannot read `{}`: {}", path.display(), e))?;
+            (path.display().to_string(), input)
+        }
+    };
+    let source_map = SourceMap::new(input_name, input.clone());
+
+    // 运行时需要的产物也在这次编译里生成，排在要输出的产物之后
+    let mut emit = invocation.emits();
+    let run_emit = invocation.run.map(|run| match run{
+        Run::Koopa => Emit::KoopaOpt,
+        Run::Riscv => Emit::Asm,
+    });
+    if let Some(run_emit) = run_emit.filter(|e| !emit.contains(e)){
+        emit.push(run_emit);
     }
-    Ok(())
-}
-
-#[test]
-fn test(){
-    // 读取输入文件
-    let input = read_to_string("/Users/csy/testcases/lv5/6_complex_scopes.c").unwrap();
-
-
-    // 调用 lalrpop 生成的 parser 解析输入文件
-    let ast = sysy::CompUnitParser::new().parse(&input).unwrap();
-    let ir = ast.get_koopa();
-    println!("{}", ir);
-    let driver = koopa::front::Driver::from(ir);
-    let program = driver.generate_program().unwrap();
-    // println!("{:#?}", program.func_layout());
-    let a = program.get_interval();
-    let b = program.get_interval();
-    for (func, map_1) in a{
-        for (func, map_2) in b{
-            println!("{}", map_2 == map_1);
+    let options = Options{emit, opt_level: invocation.opt_level, reg_alloc: invocation.reg_alloc,
+                          inline_threshold: invocation.inline_threshold};
+    let output = match compile(&input, &options){
+        Ok(output) => output,
+        Err(diagnostics) => {
+            eprint!("{}", diagnostics.render(&source_map));
+            std::process::exit(1);
+        }
+    };
+    eprint!("{}", output.warnings.render(&source_map));
+
+    // 输出到同一位置的产物按顺序拼接在一起
+    let mut files: Vec<(&Stream, String)> = Vec::new();
+    for ((_, stream), (_, text)) in invocation.outputs.iter().zip(output.artifacts.iter()){
+        match files.iter_mut().find(|(s, _)| *s == stream){
+            Some((_, content)) => content.push_str(text),
+            None => files.push((stream, text.clone())),
         }
     }
+    for (stream, content) in files{
+        let result = match stream{
+            Stream::Stdio => io::stdout().write_all(content.as_bytes()),
+            Stream::Path(path) => File::create(path).and_then(|mut f| f.write_all(content.as_bytes())),
+        };
+        result.map_err(|e| format!("cannot write `{}`: {}", stream, e))?;
+    }
 
+    if let (Some(run), Some(run_emit)) = (invocation.run, run_emit){
+        let mut stdin = Vec::new();
+        io::stdin().read_to_end(&mut stdin).map_err(|e| format!("cannot read stdin: {}", e))?;
+        let text = output.get(run_emit).unwrap();
+        let result = match run{
+            Run::Koopa => {
+                // `Program` 在生成汇编时会被改写，所以从优化后的 IR 文本重新读入
+                let program = koopa::front::Driver::from(text).generate_program()
+                    .map_err(|e| format!("cannot read back the optimized Koopa IR: {:?}", e))?;
+                interpreter::ir::run(&program, &stdin, None)
+            }
+            Run::Riscv => interpreter::riscv::run(text, &stdin, None),
+        };
+        let execution = result.map_err(|e| format!("runtime error: {}", e))?;
+        io::stdout().write_all(&execution.output).and_then(|_| io::stdout().flush())
+            .map_err(|e| format!("cannot write stdout: {}", e))?;
+        eprintln!("executed {} instructions", execution.steps);
+        std::process::exit(execution.exit_code);
+    }
+    Ok(())
 }
-
-
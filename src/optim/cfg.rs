@@ -3,9 +3,8 @@ use std::borrow::BorrowMut;
 use std::cell::{Cell, Ref};
 use std::cmp::{Ordering, Reverse};
 use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
-use std::collections::hash_map::DefaultHasher;
 use std::fmt::Debug;
-use std::hash::{Hash, Hasher};
+use std::hash::Hash;
 use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, TypeKind, Value};
 use koopa::ir::entities::ValueData;
 use koopa::ir::ValueKind;
@@ -123,6 +122,10 @@ impl ActiveVar{
             out_var: HashMap::new()
         }
     }
+    /// 基本块出口处活跃的值
+    pub fn live_out(&self, bb: BasicBlock) -> Option<&HashSet<Value>>{
+        self.out_var.get(&BBType::Other(bb))
+    }
 }
 
 pub trait ActiveAnalysis: BuildControlFlowGraph{
@@ -133,12 +136,45 @@ pub trait ActiveAnalysis: BuildControlFlowGraph{
 
 pub trait BuildControlFlowGraph{
     fn build_control_flow_graph(&self) -> HashMap<Function, ControlFlowGraph>;
-    fn print_control_flow_graph(&self, cfg: HashMap<Function, ControlFlowGraph>);
+    fn print_control_flow_graph(&self, cfg: &HashMap<Function, ControlFlowGraph>) -> String;
 }
 
 impl BuildControlFlowGraph for Program{
-    fn print_control_flow_graph(&self, cfg: HashMap<Function, ControlFlowGraph>) {
-        todo!()
+    /// print the control flow graph of every function as a graphviz digraph
+    fn print_control_flow_graph(&self, cfg: &HashMap<Function, ControlFlowGraph>) -> String {
+        let mut s = "".to_string();
+        for func in self.func_layout(){
+            let graph = match cfg.get(func){
+                Some(graph) => graph,
+                None => continue,
+            };
+            let func_data = self.func(func.clone());
+            if func_data.layout().entry_bb().is_none(){
+                continue;
+            }
+            let bb_name = |bb: &BasicBlock| func_data.dfg().bb(bb.clone()).name().clone()
+                .unwrap_or_else(|| format!("{:?}", bb));
+            s += &format!("digraph \"{}\" {{\n", &graph.name[1..]);
+            s += "\t\"enter\" [shape=Mdiamond];\n\t\"exit\" [shape=Msquare];\n";
+            for son in graph.enter.son.iter().flatten(){
+                s += &format!("\t\"enter\" -> \"{}\";\n", bb_name(son));
+            }
+            for (bb, _) in func_data.layout().bbs(){
+                let node = match graph.other.get(bb){
+                    Some(node) => node,
+                    None => continue,
+                };
+                s += &format!("\t\"{}\";\n", bb_name(bb));
+                for son in &node.son{
+                    match son{
+                        Some(son) => s += &format!("\t\"{}\" -> \"{}\";\n", bb_name(bb), bb_name(son)),
+                        None => s += &format!("\t\"{}\" -> \"exit\";\n", bb_name(bb)),
+                    }
+                }
+            }
+            s += "}\n";
+        }
+        s
     }
     ///build control flow graph for function in program
     fn build_control_flow_graph(&self) -> HashMap<Function, ControlFlowGraph> {
@@ -352,8 +388,6 @@ fn get_in<'a>(out: &'a mut HashSet<Value>, define_value: &HashSet<Value>, use_va
     let mut changed = false;
     let mut define_set:HashSet<Value> = HashSet::new();
     let mut use_set:HashSet<Value> = HashSet::new();
-    println!("define set len: {}", define_value.len());
-    println!("use set len: {}", use_value.len());
     for val in define_value{
         let result = out.remove(val);
         // if result {
@@ -407,15 +441,18 @@ fn get_in_and_out(cfg: &ControlFlowGraph, define_and_use: &HashMap<BasicBlock, (
     let mut flatten = VecDeque::new();
     let mut visited = HashMap::new();
     cfg.flatten_back(&cfg.exit, &mut flatten, &mut visited);
+    // 走不到出口的块（死循环）也要参与迭代
+    for &bb in func_data.layout().bbs().keys(){
+        if !flatten.contains(&bb){
+            flatten.push_back(bb);
+        }
+    }
     while in_changed{
         in_changed = false;
         count += 1;
         for i in 0..flatten.len(){
             let now_bb = flatten.get(i).unwrap();
             let now_bb_data = func_data.dfg().bbs().get(&now_bb).unwrap();
-            if let Some(name) = now_bb_data.name(){
-                println!("{}", name);
-            }
             let now_cfg = cfg.other.get(&now_bb).unwrap();
             let mut vec: Vec<HashSet<Value>> = Vec::new();
             for son in &now_cfg.son{
@@ -664,7 +701,6 @@ fn check_vec(func_data: &FunctionData, map: &Ref<HashMap<Value, ValueData>>, val
                 if let TypeKind::Array(_, _) = point.kind(){
                     return true;
                 } else {
-                    println!("{:#?}", point.to_string());
                     return false;
                 }
             } else {
@@ -727,7 +763,14 @@ impl ActiveAnalysis for Program{
                             }
                         }
                         ValueKind::Store(store) => {
-                            if !check_vec(func_data, &global_val, &store.dest()) && check_used
+                            // 通过指针写内存是对指针的使用，写 alloc 才是对它的定义
+                            let through_ptr = matches!(func_data.dfg().values().get(&store.dest()).map(|d| d.kind()),
+                                Some(ValueKind::GetElemPtr(_)) | Some(ValueKind::GetPtr(_)));
+                            if through_ptr{
+                                if !define_value.contains(&store.dest()){
+                                    use_value.insert(store.dest());
+                                }
+                            } else if !check_vec(func_data, &global_val, &store.dest()) && check_used
                                 (func_data, &store.dest(), &global_val){
                                 define_value.insert(store.dest().clone());
                             }
@@ -750,7 +793,6 @@ impl ActiveAnalysis for Program{
                                 && !check_int(func_data, &load.src()){
                                 use_value.insert(load.src());
                             }
-                            println!("in load:{:#?}", inst);
                             define_value.insert(inst.clone());
                         }
                         ValueKind::Alloc(alloc) =>{
@@ -833,34 +875,20 @@ impl ActiveAnalysis for Program{
     }
 }
 
-struct StartQueueInner(Value, i32);
+/// (值, 位置, 值在函数中的定义顺序)，位置相同时按定义顺序排序，保证每次分配结果相同
+struct StartQueueInner(Value, i32, usize);
 
 impl Eq for StartQueueInner {}
 
 impl PartialEq<Self> for StartQueueInner {
     fn eq(&self, other: &Self) -> bool {
-        self.1 == other.1
+        self.1 == other.1 && self.2 == other.2
     }
 }
 
 impl PartialOrd<Self> for StartQueueInner {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.1 < other.1{
-            Some(Ordering::Less)
-        } else if self.1 > other.1{
-            Some(Ordering::Greater)
-        } else {
-            let mut hasher = DefaultHasher::new();
-            self.0.hash(&mut hasher);
-            let a = hasher.finish();
-            other.0.hash(&mut hasher);
-            let b = hasher.finish();
-            if  a < b{
-                Some(Ordering::Less)
-            } else {
-                Some(Ordering::Greater)
-            }
-        }
+        Some((self.1, self.2).cmp(&(other.1, other.2)))
     }
 }
 
@@ -875,32 +903,38 @@ pub struct IntervalHandler{
     end: BinaryHeap<Reverse<StartQueueInner>>
 }
 impl IntervalHandler{
-    pub(crate) fn new(inner: HashMap<Function, HashMap<Value, Interval>>) -> HashMap<Function, IntervalHandler>{
+    pub(crate) fn new(program: &Program, inner: HashMap<Function, HashMap<Value, Interval>>) ->
+                                                                     HashMap<Function, IntervalHandler>{
         let mut tmp = HashMap::new();
         for (func, mut hash_inner) in inner{
+            let order = define_order(program.func(func));
             let size = hash_inner.len();
             let mut start = BinaryHeap::with_capacity(size);
             let mut end = BinaryHeap::with_capacity(size);
             for (val, interval) in &mut hash_inner{
                 let (l ,r) = interval.interval.pop_front().unwrap();
-                start.push(std::cmp::Reverse(StartQueueInner(val.clone(), l)));
-                end.push(std::cmp::Reverse(StartQueueInner(val.clone(),  r)));
+                let rank = order.get(val).copied().unwrap_or(usize::MAX);
+                start.push(std::cmp::Reverse(StartQueueInner(val.clone(), l, rank)));
+                end.push(std::cmp::Reverse(StartQueueInner(val.clone(),  r, rank)));
             }
             tmp.insert(func, IntervalHandler{inner: hash_inner, start, end});
         }
         tmp
     }
 }
+/// 参数与指令在函数中出现的顺序
+fn define_order(func_data: &FunctionData) -> HashMap<Value, usize>{
+    let insts = func_data.layout().bbs().nodes().flat_map(|node| node.insts().keys().copied());
+    func_data.params().iter().copied().chain(insts).enumerate().map(|(i, v)| (v, i)).collect()
+}
 impl Iterator for IntervalHandler{
     type Item = (Value, Vec<Value>);
     fn next(&mut self) -> Option<Self::Item> {
         if !self.start.is_empty(){
             let mut tmp = Vec::new();
-            let StartQueueInner(val, idx) = self.start.pop().unwrap().0;
-            println!("{:#?} start at {}", val, idx);
+            let StartQueueInner(val, idx, _) = self.start.pop().unwrap().0;
             while idx > self.end.peek().unwrap().0.1{
-                let StartQueueInner(val, idx) = self.end.pop().unwrap().0;
-                println!("{:#?} end at {}", val, idx);
+                let StartQueueInner(val, idx, _) = self.end.pop().unwrap().0;
                 tmp.push(val.clone());
             }
             Some((val, tmp))
@@ -921,6 +955,7 @@ fn test(){
     }
 }
 
+#[derive(Debug, PartialEq)]
 pub struct Interval{
     interval: VecDeque<(i32, i32)>,
     margins: HashMap<BasicBlock, (i32, i32)>
@@ -934,14 +969,12 @@ impl Interval{
             self.margins.insert(bb, (left, right));
         }
     }
-    /// cut the range, if not contain, do nothing
+    /// 在定值处截断区间。定值之后不再使用时区间里没有这一点，
+    /// 但这条指令仍然要写寄存器，所以补上只有一点的区间
     fn cut(&mut self, bb: &BasicBlock, now: i32){
-        if let Some((left, right)) = self.interval.pop_front(){
-            if now > left {
-                self.interval.push_front((now, right));
-            } else {
-                self.interval.push_front((left, right));
-            }
+        match self.interval.front_mut(){
+            Some((left, right)) if *left <= now && now <= *right => *left = now,
+            _ => self.interval.push_front((now, now)),
         }
     }
     fn insert(&mut self, bb: &BasicBlock, now: i32){
@@ -957,25 +990,13 @@ impl Interval{
     //now strategy: ignore the internal blank
     fn merge_interval(&mut self, val: &Value, func_data: &FunctionData){
         let mut tmp = VecDeque::new();
-        let mut merged: (i32, i32) = (0, 0);
-        if let Some(first) = self.interval.pop_front(){
-            merged.0 = first.0;
-            if let Some(last) = self.interval.pop_back(){
-                merged.1 = last.1;
-            } else {
-                merged.1 = first.1;
-            }
-            tmp.push_back(merged);
+        if !self.interval.is_empty(){
+            let left = self.interval.iter().map(|range| range.0).min().unwrap();
+            let right = self.interval.iter().map(|range| range.1).max().unwrap();
+            tmp.push_back((left, right));
             self.interval = tmp;
         } else {
-            let vv = func_data.dfg().value(val.clone());
-            println!("{:#?}", vv);
-            println!("{:#?}",func_data.dfg().value(val.clone()).kind());
-            println!("{:#?}", func_data.dfg().value(vv.used_by().iter().next().unwrap().clone()));
-            if let ValueKind::Load(load) = func_data.dfg().value(val.clone()).kind(){
-                println!("{:#?}", func_data.dfg().value(load.src()));
-            }
-            unreachable!();
+            unreachable!("{:#?} has no live interval", func_data.dfg().value(val.clone()));
         }
         // for interval in &self.interval{
         //     if merged.1 != interval.0{
@@ -1012,9 +1033,6 @@ impl IntervalAnalysis for Program{
                         let func_data = self.func(func.clone());
                         let bb_data = func_data.dfg().bbs().get(&bb);
                         let bb_data = bb_data.unwrap();
-                        if let Some(name) = bb_data.name(){
-                            println!("{}", name);
-                        }
                         let end = cnt;
                         let begin  = cnt - bbn.insts().len() as i32;
                         if let Some(out_var) = act.out_var.get(&BBType::Other(bb)){
@@ -0,0 +1,211 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, FunctionData, Program, Value, ValueKind};
+use crate::optim::utils::{edge_args, predecessors, remove_block_params, remove_blocks, remove_insts, replace_uses, successors};
+
+/// 死代码删除：不可达的基本块、只写不读的 alloc 以及写它的 store、
+/// 没有副作用且结果无人使用的指令和块参数，反复删除直到不再变化；
+/// 最后删除 main 调用不到的函数。
+pub fn dce(program: &mut Program){
+    for func in program.func_layout().to_vec(){
+        let func_data = program.func_mut(func);
+        if func_data.layout().entry_bb().is_none(){
+            continue;
+        }
+        let mut changed = true;
+        while changed{
+            changed = remove_unreachable_blocks(func_data);
+            changed |= remove_write_only_allocs(func_data);
+            changed |= sweep(func_data);
+        }
+    }
+    remove_unused_functions(program);
+}
+
+fn remove_unreachable_blocks(func_data: &mut FunctionData) -> bool{
+    let entry = func_data.layout().entry_bb().unwrap();
+    let mut reachable = HashSet::from([entry]);
+    let mut stack = vec![entry];
+    while let Some(bb) = stack.pop(){
+        for succ in successors(func_data, bb){
+            if reachable.insert(succ){
+                stack.push(succ);
+            }
+        }
+    }
+    let dead: Vec<BasicBlock> = func_data.layout().bbs().keys().copied()
+        .filter(|bb| !reachable.contains(bb))
+        .collect();
+    if dead.is_empty(){
+        return false;
+    }
+    remove_blocks(func_data, &dead);
+    true
+}
+
+/// 与后端的 `check_used` 一样，只被当作 store 目标的 alloc 没有用处；
+/// 这里还会沿着数组的 getelemptr/getptr 往下看
+fn remove_write_only_allocs(func_data: &mut FunctionData) -> bool{
+    let allocs: Vec<Value> = func_data.layout().bbs().nodes()
+        .flat_map(|node| node.insts().keys().copied())
+        .filter(|&inst| matches!(func_data.dfg().value(inst).kind(), ValueKind::Alloc(_)))
+        .collect();
+    let mut dead = Vec::new();
+    for alloc in allocs{
+        let mut insts = vec![alloc];
+        if write_only(func_data, alloc, &mut insts){
+            dead.extend(insts);
+        }
+    }
+    if dead.is_empty(){
+        return false;
+    }
+    remove_insts(func_data, dead);
+    true
+}
+
+/// `ptr` 以及由它算出的地址只被写入，把经过的指令记到 `insts` 里
+fn write_only(func_data: &FunctionData, ptr: Value, insts: &mut Vec<Value>) -> bool{
+    func_data.dfg().value(ptr).used_by().iter().all(|&user| match func_data.dfg().value(user).kind(){
+        ValueKind::Store(store) if store.dest() == ptr && store.value() != ptr => {
+            insts.push(user);
+            true
+        }
+        ValueKind::GetElemPtr(gep) if gep.src() == ptr => {
+            insts.push(user);
+            write_only(func_data, user, insts)
+        }
+        ValueKind::GetPtr(get_ptr) if get_ptr.src() == ptr => {
+            insts.push(user);
+            write_only(func_data, user, insts)
+        }
+        _ => false,
+    })
+}
+
+fn has_side_effect(kind: &ValueKind) -> bool{
+    matches!(kind, ValueKind::Store(_) | ValueKind::Call(_) | ValueKind::Branch(_) | ValueKind::Jump(_) | ValueKind::Return(_))
+}
+
+/// 标记-清除：有副作用的指令是根，活的值的操作数也是活的；
+/// 跳转的实参只有在对应的块参数活着时才算被使用
+fn sweep(func_data: &mut FunctionData) -> bool{
+    let preds = predecessors(func_data);
+    let mut params: HashMap<Value, (BasicBlock, usize)> = HashMap::new();
+    for &bb in func_data.layout().bbs().keys(){
+        for (i, &param) in func_data.dfg().bb(bb).params().iter().enumerate(){
+            params.insert(param, (bb, i));
+        }
+    }
+    let insts: Vec<Value> = func_data.layout().bbs().nodes()
+        .flat_map(|node| node.insts().keys().copied())
+        .collect();
+    let mut live: HashSet<Value> = HashSet::new();
+    let mut worklist: Vec<Value> = Vec::new();
+    for &inst in &insts{
+        if has_side_effect(func_data.dfg().value(inst).kind()){
+            live.insert(inst);
+            worklist.push(inst);
+        }
+    }
+    while let Some(value) = worklist.pop(){
+        let uses: Vec<Value> = if let Some(&(bb, i)) = params.get(&value){
+            preds[&bb].iter()
+                .flat_map(|&pred| edge_args(func_data, pred, bb))
+                .map(|args| args[i])
+                .collect()
+        } else {
+            match func_data.dfg().value(value).kind(){
+                ValueKind::Branch(branch) => vec![branch.cond()],
+                ValueKind::Jump(_) => Vec::new(),
+                kind => kind.value_uses().collect(),
+            }
+        };
+        for used in uses{
+            let local = params.contains_key(&used) || func_data.layout().parent_bb(used).is_some();
+            if local && live.insert(used){
+                worklist.push(used);
+            }
+        }
+    }
+
+    let dead_insts: Vec<Value> = insts.into_iter().filter(|inst| !live.contains(inst)).collect();
+    let mut dead_params: HashMap<BasicBlock, HashSet<usize>> = HashMap::new();
+    let mut replace: HashMap<Value, Value> = HashMap::new();
+    for (&param, &(bb, i)) in &params{
+        if !live.contains(&param){
+            dead_params.entry(bb).or_default().insert(i);
+            let ty = func_data.dfg().value(param).ty().clone();
+            replace.insert(param, func_data.dfg_mut().new_value().undef(ty));
+        }
+    }
+    if dead_insts.is_empty() && dead_params.is_empty(){
+        return false;
+    }
+    // 死参数只会被死指令和跳转实参使用，先换成 undef，参数才能删掉
+    replace_uses(func_data, &replace);
+    remove_block_params(func_data, &dead_params);
+    remove_insts(func_data, dead_insts);
+    true
+}
+
+/// 从 main 出发沿调用关系找不到的函数定义删除，库函数的声明保留
+fn remove_unused_functions(program: &mut Program){
+    let main = program.func_layout().iter().copied().find(|&func| program.func(func).name() == "@main");
+    let main = match main{
+        Some(main) => main,
+        None => return,
+    };
+    let mut used = HashSet::from([main]);
+    let mut stack = vec![main];
+    while let Some(func) = stack.pop(){
+        let func_data = program.func(func);
+        for node in func_data.layout().bbs().nodes(){
+            for &inst in node.insts().keys(){
+                if let ValueKind::Call(call) = func_data.dfg().value(inst).kind(){
+                    if used.insert(call.callee()){
+                        stack.push(call.callee());
+                    }
+                }
+            }
+        }
+    }
+    for func in program.func_layout().to_vec(){
+        if used.contains(&func) || program.func(func).layout().entry_bb().is_none(){
+            continue;
+        }
+        // 先删掉函数体，全局变量上记录的使用关系才会随之更新
+        let func_data = program.func_mut(func);
+        let blocks: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+        remove_blocks(func_data, &blocks);
+        program.remove_func(func);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::optim::test_utils::{binary_ops, callees, func, insts, program};
+
+    #[test]
+    fn dead_values_stores_and_functions_are_removed(){
+        let mut program = program("int unused(int x) { return x + 1; }\n\
+            int twice(int x) { return x + x; }\n\
+            int main() { int a[4]; a[1] = 3; int r = getint(); int t = 5; t = r * 7; r + 1; return twice(r); }");
+        dce(&mut program);
+        let names: Vec<&str> = program.func_layout().iter().map(|&f| program.func(f).name()).collect();
+        assert!(!names.contains(&"@unused") && names.contains(&"@twice"), "{:?}", names);
+
+        let main = func(&program, "@main");
+        let func_data = program.func(main);
+        // 只写不读的数组和 t 连同写它们的 store 一起删除，result 和 r 还要读
+        let allocs: Vec<String> = insts(func_data).into_iter()
+            .filter(|&inst| matches!(func_data.dfg().value(inst).kind(), ValueKind::Alloc(_)))
+            .map(|inst| func_data.dfg().value(inst).name().clone().unwrap())
+            .collect();
+        assert_eq!(allocs, vec!["@result", "@r"]);
+        assert!(binary_ops(func_data).is_empty());
+        // 有副作用的调用保留
+        assert_eq!(callees(&program, main), vec!["@getint", "@twice"]);
+    }
+}
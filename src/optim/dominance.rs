@@ -0,0 +1,309 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::{BasicBlock, Function, FunctionData, Program};
+use crate::optim::utils::{predecessors, successors};
+
+/// 支配树与支配边界。只包含从根可达的基本块，直接支配者用 Cooper-Harvey-Kennedy
+/// 的迭代算法求出。同一个结构既表示支配树（根为入口），也表示后支配树
+/// （在反向图上计算，根是连接所有 ret 块的虚拟出口）。
+pub struct DominatorTree{
+    /// 参与计算的基本块，下标即结点编号；虚拟出口的编号为 `blocks.len()`，不在其中
+    blocks: Vec<BasicBlock>,
+    index: HashMap<BasicBlock, usize>,
+    /// 可达结点的逆后序，第一个是根
+    order: Vec<usize>,
+    /// 结点在 `order` 中的位置，不可达结点为 None
+    rpo_index: Vec<Option<usize>>,
+    idom: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+    frontier: Vec<Vec<usize>>,
+}
+
+impl DominatorTree{
+    /// 函数的支配树，根为入口块
+    pub fn new(func_data: &FunctionData) -> DominatorTree{
+        let entry = func_data.layout().entry_bb().expect("function has no body");
+        let blocks: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+        let index: HashMap<BasicBlock, usize> = blocks.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+        let preds = predecessors(func_data);
+        let succs: Vec<Vec<usize>> = blocks.iter()
+            .map(|&bb| successors(func_data, bb).iter().map(|s| index[s]).collect())
+            .collect();
+        let preds: Vec<Vec<usize>> = blocks.iter()
+            .map(|bb| preds[bb].iter().map(|p| index[p]).collect())
+            .collect();
+        DominatorTree::build(blocks, index[&entry], succs, preds)
+    }
+
+    /// 函数的后支配树。没有后继的块（ret）都连到虚拟出口上，
+    /// 不能到达出口的块（死循环）不在树中。
+    pub fn post_dominators(func_data: &FunctionData) -> DominatorTree{
+        let blocks: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+        let index: HashMap<BasicBlock, usize> = blocks.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+        let exit = blocks.len();
+        let mut succs: Vec<Vec<usize>> = vec![Vec::new(); blocks.len() + 1];
+        let mut preds: Vec<Vec<usize>> = vec![Vec::new(); blocks.len() + 1];
+        // 反向图：原图的前驱是这里的后继
+        for (i, &bb) in blocks.iter().enumerate(){
+            let bb_succs = successors(func_data, bb);
+            if bb_succs.is_empty(){
+                succs[exit].push(i);
+                preds[i].push(exit);
+            }
+            for succ in bb_succs{
+                let j = index[&succ];
+                if !succs[j].contains(&i){
+                    succs[j].push(i);
+                    preds[i].push(j);
+                }
+            }
+        }
+        DominatorTree::build(blocks, exit, succs, preds)
+    }
+
+    fn build(blocks: Vec<BasicBlock>, root: usize, succs: Vec<Vec<usize>>, preds: Vec<Vec<usize>>) -> DominatorTree{
+        let index: HashMap<BasicBlock, usize> = blocks.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+        let count = succs.len();
+        let order = reverse_postorder(root, &succs);
+        let mut rpo_index = vec![None; count];
+        for (i, &node) in order.iter().enumerate(){
+            rpo_index[node] = Some(i);
+        }
+        let mut idom: Vec<Option<usize>> = vec![None; count];
+        idom[root] = Some(root);
+        let mut changed = true;
+        while changed{
+            changed = false;
+            for &node in order.iter().skip(1){
+                let mut new_idom: Option<usize> = None;
+                for &pred in &preds[node]{
+                    if idom[pred].is_none(){
+                        continue;
+                    }
+                    new_idom = Some(match new_idom{
+                        None => pred,
+                        Some(other) => intersect(&idom, &rpo_index, pred, other),
+                    });
+                }
+                if new_idom.is_some() && idom[node] != new_idom{
+                    idom[node] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); count];
+        for &node in order.iter().skip(1){
+            children[idom[node].unwrap()].push(node);
+        }
+        let mut frontier: Vec<Vec<usize>> = vec![Vec::new(); count];
+        for &node in &order{
+            let reachable_preds: Vec<usize> = preds[node].iter().copied().filter(|&p| rpo_index[p].is_some()).collect();
+            if reachable_preds.len() < 2{
+                continue;
+            }
+            for pred in reachable_preds{
+                let mut runner = pred;
+                while Some(runner) != idom[node]{
+                    if !frontier[runner].contains(&node){
+                        frontier[runner].push(node);
+                    }
+                    runner = idom[runner].unwrap();
+                }
+            }
+        }
+        DominatorTree{blocks, index, order, rpo_index, idom, children, frontier}
+    }
+
+    fn node(&self, bb: BasicBlock) -> Option<usize>{
+        self.index.get(&bb).copied().filter(|&i| self.rpo_index[i].is_some())
+    }
+    fn to_blocks(&self, nodes: &[usize]) -> Vec<BasicBlock>{
+        nodes.iter().filter_map(|&i| self.blocks.get(i).copied()).collect()
+    }
+
+    /// 根（入口块；后支配树的根是虚拟出口，返回 None）
+    pub fn root(&self) -> Option<BasicBlock>{
+        self.blocks.get(self.order[0]).copied()
+    }
+    /// 可达基本块的逆后序
+    pub fn reverse_postorder(&self) -> Vec<BasicBlock>{
+        self.to_blocks(&self.order)
+    }
+    /// 是否在树中：支配树中即从入口可达，后支配树中即能到达出口
+    pub fn is_reachable(&self, bb: BasicBlock) -> bool{
+        self.node(bb).is_some()
+    }
+    /// 直接支配者，根、不可达块以及只被虚拟出口后支配的块没有
+    pub fn idom(&self, bb: BasicBlock) -> Option<BasicBlock>{
+        let node = self.node(bb)?;
+        self.idom[node].filter(|&d| d != node).and_then(|d| self.blocks.get(d).copied())
+    }
+    /// 支配树上的子节点，按逆后序排列
+    pub fn children(&self, bb: BasicBlock) -> Vec<BasicBlock>{
+        self.node(bb).map_or(Vec::new(), |node| self.to_blocks(&self.children[node]))
+    }
+    /// 支配边界；在后支配树上就是控制依赖的来源
+    pub fn frontier(&self, bb: BasicBlock) -> Vec<BasicBlock>{
+        self.node(bb).map_or(Vec::new(), |node| self.to_blocks(&self.frontier[node]))
+    }
+    /// `a` 是否支配 `b`（每个块都支配自己）
+    pub fn dominates(&self, a: BasicBlock, b: BasicBlock) -> bool{
+        let (a, mut runner) = match (self.node(a), self.node(b)){
+            (Some(a), Some(b)) => (a, b),
+            _ => return false,
+        };
+        // 支配者在逆后序中一定更靠前
+        while self.rpo_index[runner] > self.rpo_index[a]{
+            runner = self.idom[runner].unwrap();
+        }
+        runner == a
+    }
+    pub fn strictly_dominates(&self, a: BasicBlock, b: BasicBlock) -> bool{
+        a != b && self.dominates(a, b)
+    }
+    /// 从 `bb` 到根路径上的所有支配者，包括 `bb` 自身
+    pub fn dominators(&self, bb: BasicBlock) -> Vec<BasicBlock>{
+        let mut path = Vec::new();
+        let mut runner = match self.node(bb){
+            Some(node) => node,
+            None => return Vec::new(),
+        };
+        loop{
+            path.push(runner);
+            match self.idom[runner]{
+                Some(parent) if parent != runner => runner = parent,
+                _ => break,
+            }
+        }
+        self.to_blocks(&path)
+    }
+    /// 同时支配 `a` 与 `b` 的最近的块
+    pub fn nearest_common_dominator(&self, a: BasicBlock, b: BasicBlock) -> Option<BasicBlock>{
+        let common = intersect(&self.idom, &self.rpo_index, self.node(a)?, self.node(b)?);
+        self.blocks.get(common).copied()
+    }
+}
+
+fn intersect(idom: &[Option<usize>], rpo_index: &[Option<usize>], mut a: usize, mut b: usize) -> usize{
+    while a != b{
+        while rpo_index[a] > rpo_index[b]{
+            a = idom[a].unwrap();
+        }
+        while rpo_index[b] > rpo_index[a]{
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
+
+/// 从 `root` 出发的深度优先逆后序，后继按给出的顺序访问
+fn reverse_postorder(root: usize, succs: &[Vec<usize>]) -> Vec<usize>{
+    let mut postorder = Vec::new();
+    let mut visited = HashSet::new();
+    visited.insert(root);
+    // 显式栈：(结点, 下一个要访问的后继下标)
+    let mut stack = vec![(root, 0)];
+    while let Some((node, next)) = stack.last_mut(){
+        let node = *node;
+        match succs[node].get(*next){
+            Some(&succ) => {
+                *next += 1;
+                if visited.insert(succ){
+                    stack.push((succ, 0));
+                }
+            }
+            None => {
+                postorder.push(node);
+                stack.pop();
+            }
+        }
+    }
+    postorder.reverse();
+    postorder
+}
+
+/// 一个函数的支配与后支配信息
+pub struct DominanceInfo{
+    pub dom: DominatorTree,
+    pub post_dom: DominatorTree,
+}
+impl DominanceInfo{
+    pub fn new(func_data: &FunctionData) -> DominanceInfo{
+        DominanceInfo{dom: DominatorTree::new(func_data), post_dom: DominatorTree::post_dominators(func_data)}
+    }
+    /// 直接后支配者
+    pub fn ipdom(&self, bb: BasicBlock) -> Option<BasicBlock>{
+        self.post_dom.idom(bb)
+    }
+    pub fn post_dominates(&self, a: BasicBlock, b: BasicBlock) -> bool{
+        self.post_dom.dominates(a, b)
+    }
+}
+
+pub trait DominanceAnalysis{
+    fn dominance(&self) -> HashMap<Function, DominanceInfo>;
+    fn print_dominance(&self, info: &HashMap<Function, DominanceInfo>) -> String;
+}
+impl DominanceAnalysis for Program{
+    /// 计算每个有函数体的函数的支配信息
+    fn dominance(&self) -> HashMap<Function, DominanceInfo>{
+        self.func_layout().iter()
+            .filter(|&&func| self.func(func).layout().entry_bb().is_some())
+            .map(|&func| (func, DominanceInfo::new(self.func(func))))
+            .collect()
+    }
+    /// 按布局顺序列出每个基本块的直接支配者、直接后支配者和两种边界
+    fn print_dominance(&self, info: &HashMap<Function, DominanceInfo>) -> String{
+        let mut s = String::new();
+        for func in self.func_layout(){
+            let (func_data, info) = match info.get(func){
+                Some(info) => (self.func(*func), info),
+                None => continue,
+            };
+            let name = |bb: Option<BasicBlock>| match bb{
+                Some(bb) => func_data.dfg().bb(bb).name().clone().unwrap_or_else(|| format!("{:?}", bb)),
+                None => "-".to_string(),
+            };
+            let names = |bbs: Vec<BasicBlock>| bbs.into_iter().map(|bb| name(Some(bb))).collect::<Vec<_>>().join(", ");
+            s += &format!("fun {}\n", func_data.name());
+            for &bb in func_data.layout().bbs().keys(){
+                if !info.dom.is_reachable(bb){
+                    s += &format!("  {}: unreachable\n", name(Some(bb)));
+                    continue;
+                }
+                s += &format!("  {}: idom {}, ipdom {}, df [{}], pdf [{}]\n", name(Some(bb)),
+                              name(info.dom.idom(bb)), name(info.ipdom(bb)),
+                              names(info.dom.frontier(bb)), names(info.post_dom.frontier(bb)));
+            }
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::optim::test_utils::{block, func, program};
+
+    #[test]
+    fn if_else_and_loop(){
+        let program = program("int main() { int x = getint(); if (x) x = 1; else x = 2; \
+            while (x < 10) x = x + 1; return x; }");
+        let func_data = program.func(func(&program, "@main"));
+        let bb = |name: &str| block(func_data, name);
+        let info = DominanceInfo::new(func_data);
+        let (entry, then, els, end) = (bb("%entry"), bb("%then_1"), bb("%else_1"), bb("%end_1"));
+        let (cond, body, exit) = (bb("%while_entry_2"), bb("%while_body_2"), bb("%while_end_2"));
+        assert_eq!(info.dom.idom(then), Some(entry));
+        assert_eq!(info.dom.idom(end), Some(entry));
+        assert_eq!(info.dom.frontier(then), vec![end]);
+        assert_eq!(info.dom.frontier(body), vec![cond]);
+        assert!(info.dom.dominates(cond, exit));
+        assert!(!info.dom.dominates(then, end));
+        assert_eq!(info.dom.nearest_common_dominator(then, els), Some(entry));
+        assert_eq!(info.ipdom(entry), Some(end));
+        assert_eq!(info.ipdom(body), Some(cond));
+        assert!(info.post_dominates(exit, cond));
+        // 分支块 then 控制依赖于入口的条件跳转
+        assert_eq!(info.post_dom.frontier(then), vec![entry]);
+    }
+}
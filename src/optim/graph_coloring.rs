@@ -0,0 +1,420 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use koopa::ir::{Function, FunctionData, Program, Value, ValueKind};
+use crate::optim::cfg::{ActiveAnalysis, ActiveVar, Interval};
+use crate::optim::loops::LoopForest;
+use crate::optim::reg_alloc::RegClasses;
+
+pub trait GraphColoring{
+    fn graph_coloring(&self, all_interval: HashMap<Function, HashMap<Value, Interval>>) ->
+        HashMap<Function, HashMap<Value, Option<i32>>>;
+}
+impl GraphColoring for Program{
+    /// 与 `RegAlloc::reg_alloc` 的输入输出相同：活跃区间里的每个值分到一个寄存器，`None` 表示溢出。
+    /// 冲突图由 `ActiveAnalysis` 给出的基本块出口活跃集合逐条指令倒推得到，
+    /// 然后用迭代合并（Appel 的 IRC）着色。
+    fn graph_coloring(&self, all_interval: HashMap<Function, HashMap<Value, Interval>>) ->
+        HashMap<Function, HashMap<Value, Option<i32>>>{
+        let (active, _) = self.active_analysis();
+        let mut global_result = HashMap::new();
+        for (func, interval) in all_interval{
+            let func_data = self.func(func);
+            let values: HashSet<Value> = interval.keys().copied().collect();
+            let classes = RegClasses::new(func_data, &active[&func], &values);
+            let mut graph = InterferenceGraph::build(func_data, &active[&func], &values, classes);
+            let mut result = graph.color();
+            // 不在布局里的值没有进图，放到栈上总是安全的
+            for value in values{
+                result.entry(value).or_insert(None);
+            }
+            global_result.insert(func, result);
+        }
+        global_result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeState{
+    Initial,
+    Simplify,
+    Freeze,
+    Spill,
+    OnStack,
+    Coalesced,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MoveState{
+    Worklist,
+    Active,
+    Coalesced,
+    Constrained,
+    Frozen,
+}
+
+struct InterferenceGraph{
+    values: Vec<Value>,
+    adj_set: HashSet<(usize, usize)>,
+    adj_list: Vec<Vec<usize>>,
+    degree: Vec<usize>,
+    /// (目标, 来源)
+    moves: Vec<(usize, usize)>,
+    move_list: Vec<Vec<usize>>,
+    move_state: Vec<MoveState>,
+    state: Vec<NodeState>,
+    simplify: BTreeSet<usize>,
+    freeze: BTreeSet<usize>,
+    spill: BTreeSet<usize>,
+    worklist_moves: BTreeSet<usize>,
+    select_stack: Vec<usize>,
+    alias: Vec<usize>,
+    /// 按循环深度加权的定义、使用次数
+    cost: Vec<f64>,
+    /// 活跃的指令数
+    span: Vec<usize>,
+    /// 跨过调用的值只能用 s 寄存器，合并后的结点只要有一个跨调用就算跨调用
+    across_call: Vec<bool>,
+    classes: RegClasses,
+}
+
+impl InterferenceGraph{
+    fn build(func_data: &FunctionData, active: &ActiveVar, values: &HashSet<Value>, classes: RegClasses) -> InterferenceGraph{
+        let order: Vec<Value> = func_data.layout().bbs().nodes()
+            .flat_map(|node| node.insts().keys().copied())
+            .filter(|inst| values.contains(inst))
+            .collect();
+        let n = order.len();
+        let index: HashMap<Value, usize> = order.iter().enumerate().map(|(i, &v)| (v, i)).collect();
+        let across_call = order.iter().map(|&v| classes.across_call(v)).collect();
+        let mut graph = InterferenceGraph{
+            values: order,
+            adj_set: HashSet::new(),
+            adj_list: vec![Vec::new(); n],
+            degree: vec![0; n],
+            moves: Vec::new(),
+            move_list: vec![Vec::new(); n],
+            move_state: Vec::new(),
+            state: vec![NodeState::Initial; n],
+            simplify: BTreeSet::new(),
+            freeze: BTreeSet::new(),
+            spill: BTreeSet::new(),
+            worklist_moves: BTreeSet::new(),
+            select_stack: Vec::new(),
+            alias: (0..n).collect(),
+            cost: vec![0.0; n],
+            span: vec![1; n],
+            across_call,
+            classes,
+        };
+
+        let loops = LoopForest::new(func_data);
+        for (&bb, node) in func_data.layout().bbs(){
+            let weight = 10f64.powi(loops.depth(bb).min(6) as i32);
+            let mut live: BTreeSet<usize> = active.live_out(bb)
+                .map(|set| set.iter().filter_map(|v| index.get(v).copied()).collect())
+                .unwrap_or_default();
+            let insts: Vec<Value> = node.insts().keys().copied().collect();
+            for inst in insts.into_iter().rev(){
+                let (defs, uses, is_move) = def_use(func_data, inst, &index);
+                for &l in &live{
+                    graph.span[l] += 1;
+                }
+                for &v in defs.iter().chain(&uses){
+                    graph.cost[v] += weight;
+                }
+                if is_move{
+                    // 复制的两端不因为这条复制而冲突
+                    for &u in &uses{
+                        live.remove(&u);
+                    }
+                    let m = graph.moves.len();
+                    graph.moves.push((defs[0], uses[0]));
+                    graph.move_state.push(MoveState::Worklist);
+                    graph.worklist_moves.insert(m);
+                    graph.move_list[defs[0]].push(m);
+                    graph.move_list[uses[0]].push(m);
+                } else {
+                    // 后端会先写结果再读完操作数，结果不能和操作数共用寄存器
+                    live.extend(uses.iter().copied());
+                }
+                for &d in &defs{
+                    for &l in &live{
+                        graph.add_edge(l, d);
+                    }
+                }
+                for d in &defs{
+                    live.remove(d);
+                }
+                live.extend(uses);
+            }
+        }
+        graph
+    }
+
+    /// 结点能用的寄存器个数
+    fn k(&self, n: usize) -> usize{
+        self.classes.registers(self.across_call[n]).len()
+    }
+
+    fn add_edge(&mut self, u: usize, v: usize){
+        if u != v && self.adj_set.insert((u, v)){
+            self.adj_set.insert((v, u));
+            self.adj_list[u].push(v);
+            self.adj_list[v].push(u);
+            self.degree[u] += 1;
+            self.degree[v] += 1;
+        }
+    }
+
+    /// 返回每个值分到的寄存器编号
+    fn color(&mut self) -> HashMap<Value, Option<i32>>{
+        self.make_worklist();
+        loop{
+            if let Some(n) = self.simplify.pop_first(){
+                self.simplify_node(n);
+            } else if let Some(m) = self.worklist_moves.pop_first(){
+                self.coalesce(m);
+            } else if let Some(n) = self.freeze.pop_first(){
+                self.set_state(n, NodeState::Simplify);
+                self.freeze_moves(n);
+            } else if !self.spill.is_empty(){
+                self.select_spill();
+            } else {
+                break;
+            }
+        }
+        self.assign_colors()
+    }
+
+    fn set_state(&mut self, n: usize, state: NodeState){
+        match self.state[n]{
+            NodeState::Simplify => self.simplify.remove(&n),
+            NodeState::Freeze => self.freeze.remove(&n),
+            NodeState::Spill => self.spill.remove(&n),
+            _ => false,
+        };
+        match state{
+            NodeState::Simplify => self.simplify.insert(n),
+            NodeState::Freeze => self.freeze.insert(n),
+            NodeState::Spill => self.spill.insert(n),
+            _ => false,
+        };
+        self.state[n] = state;
+    }
+
+    fn make_worklist(&mut self){
+        for n in 0..self.values.len(){
+            let state = if self.degree[n] >= self.k(n){
+                NodeState::Spill
+            } else if self.move_related(n){
+                NodeState::Freeze
+            } else {
+                NodeState::Simplify
+            };
+            self.set_state(n, state);
+        }
+    }
+
+    fn adjacent(&self, n: usize) -> Vec<usize>{
+        self.adj_list[n].iter().copied()
+            .filter(|&m| !matches!(self.state[m], NodeState::OnStack | NodeState::Coalesced))
+            .collect()
+    }
+
+    fn node_moves(&self, n: usize) -> Vec<usize>{
+        self.move_list[n].iter().copied()
+            .filter(|&m| matches!(self.move_state[m], MoveState::Worklist | MoveState::Active))
+            .collect()
+    }
+
+    fn move_related(&self, n: usize) -> bool{
+        !self.node_moves(n).is_empty()
+    }
+
+    fn simplify_node(&mut self, n: usize){
+        self.state[n] = NodeState::OnStack;
+        self.select_stack.push(n);
+        for m in self.adjacent(n){
+            self.decrement_degree(m);
+        }
+    }
+
+    fn decrement_degree(&mut self, m: usize){
+        let d = self.degree[m];
+        self.degree[m] -= 1;
+        if d == self.k(m){
+            let mut nodes = self.adjacent(m);
+            nodes.push(m);
+            self.enable_moves(&nodes);
+            if self.state[m] == NodeState::Spill{
+                let state = if self.move_related(m) {NodeState::Freeze} else {NodeState::Simplify};
+                self.set_state(m, state);
+            }
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[usize]){
+        for &n in nodes{
+            for m in self.node_moves(n){
+                if self.move_state[m] == MoveState::Active{
+                    self.move_state[m] = MoveState::Worklist;
+                    self.worklist_moves.insert(m);
+                }
+            }
+        }
+    }
+
+    fn alias_of(&self, mut n: usize) -> usize{
+        while self.state[n] == NodeState::Coalesced{
+            n = self.alias[n];
+        }
+        n
+    }
+
+    fn add_work_list(&mut self, u: usize){
+        if self.state[u] == NodeState::Freeze && !self.move_related(u) && self.degree[u] < self.k(u){
+            self.set_state(u, NodeState::Simplify);
+        }
+    }
+
+    /// Briggs 的保守条件：合并后高度数的邻居少于合并结点能用的寄存器个数
+    fn conservative(&self, nodes: &[usize], k: usize) -> bool{
+        nodes.iter().filter(|&&n| self.degree[n] >= self.k(n)).count() < k
+    }
+
+    fn coalesce(&mut self, m: usize){
+        let (x, y) = self.moves[m];
+        let (u, v) = (self.alias_of(x), self.alias_of(y));
+        if u == v{
+            self.move_state[m] = MoveState::Coalesced;
+            self.add_work_list(u);
+        } else if self.adj_set.contains(&(u, v)){
+            self.move_state[m] = MoveState::Constrained;
+            self.add_work_list(u);
+            self.add_work_list(v);
+        } else {
+            let mut nodes = self.adjacent(u);
+            for t in self.adjacent(v){
+                if !nodes.contains(&t){
+                    nodes.push(t);
+                }
+            }
+            let k = self.classes.registers(self.across_call[u] || self.across_call[v]).len();
+            if self.conservative(&nodes, k){
+                self.move_state[m] = MoveState::Coalesced;
+                self.combine(u, v);
+                self.add_work_list(u);
+            } else {
+                self.move_state[m] = MoveState::Active;
+            }
+        }
+    }
+
+    fn combine(&mut self, u: usize, v: usize){
+        self.set_state(v, NodeState::Coalesced);
+        self.alias[v] = u;
+        let moves = self.move_list[v].clone();
+        self.move_list[u].extend(moves);
+        self.cost[u] += self.cost[v];
+        self.span[u] += self.span[v];
+        self.across_call[u] |= self.across_call[v];
+        self.enable_moves(&[v]);
+        for t in self.adjacent(v){
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+        if self.degree[u] >= self.k(u) && self.state[u] == NodeState::Freeze{
+            self.set_state(u, NodeState::Spill);
+        }
+    }
+
+    fn freeze_moves(&mut self, u: usize){
+        for m in self.node_moves(u){
+            let (x, y) = self.moves[m];
+            let v = if self.alias_of(y) == self.alias_of(u) {self.alias_of(x)} else {self.alias_of(y)};
+            self.worklist_moves.remove(&m);
+            self.move_state[m] = MoveState::Frozen;
+            if self.state[v] == NodeState::Freeze && !self.move_related(v) && self.degree[v] < self.k(v){
+                self.set_state(v, NodeState::Simplify);
+            }
+        }
+    }
+
+    /// 溢出使用密度（循环加权的使用次数除以活跃长度）最低的值，相同时溢出冲突更多的
+    fn select_spill(&mut self){
+        let density = |n: usize| self.cost[n] / self.span[n] as f64;
+        let m = self.spill.iter().copied()
+            .min_by(|&a, &b| density(a).total_cmp(&density(b)).then(self.degree[b].cmp(&self.degree[a])))
+            .unwrap();
+        self.set_state(m, NodeState::Simplify);
+        self.freeze_moves(m);
+    }
+
+    fn assign_colors(&mut self) -> HashMap<Value, Option<i32>>{
+        let mut color: Vec<Option<i32>> = vec![None; self.values.len()];
+        while let Some(n) = self.select_stack.pop(){
+            let used: HashSet<i32> = self.adj_list[n].iter()
+                .filter_map(|&w| color[self.alias_of(w)])
+                .collect();
+            color[n] = self.classes.registers(self.across_call[n]).into_iter().find(|c| !used.contains(c));
+        }
+        (0..self.values.len()).map(|n| (self.values[n], color[self.alias_of(n)])).collect()
+    }
+}
+
+/// 指令定义、使用的图中结点，以及它是不是一条可以合并的复制
+fn def_use(func_data: &FunctionData, inst: Value, index: &HashMap<Value, usize>) -> (Vec<usize>, Vec<usize>, bool){
+    let node = |v: Value| index.get(&v).copied();
+    let is_alloc = |v: Value| node(v).is_some() && matches!(func_data.dfg().value(v).kind(), ValueKind::Alloc(_));
+    match func_data.dfg().value(inst).kind(){
+        // 写标量 alloc 是对它的重新定义，相当于 mv alloc, value
+        ValueKind::Store(store) if is_alloc(store.dest()) => {
+            let uses: Vec<usize> = node(store.value()).into_iter().collect();
+            let is_move = !uses.is_empty();
+            (vec![node(store.dest()).unwrap()], uses, is_move)
+        }
+        ValueKind::Load(load) => {
+            let defs: Vec<usize> = node(inst).into_iter().collect();
+            let uses: Vec<usize> = node(load.src()).into_iter().collect();
+            let is_move = !defs.is_empty() && is_alloc(load.src());
+            (defs, uses, is_move)
+        }
+        ValueKind::Alloc(_) => (Vec::new(), Vec::new(), false),
+        kind => (node(inst).into_iter().collect(), kind.value_uses().filter_map(node).collect(), false),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::{compile, Emit, Options, OptLevel, RegAllocKind};
+
+    fn stats(source: &str, reg_alloc: RegAllocKind) -> String{
+        let options = Options{emit: vec![Emit::RegAlloc], opt_level: OptLevel::O1, reg_alloc, ..Options::default()};
+        compile(source, &options).unwrap().get(Emit::RegAlloc).unwrap().to_string()
+    }
+
+    #[test]
+    fn moves_are_coalesced_and_pressure_is_lower_than_linear_scan(){
+        // 值都由同一次调用的结果算出，不跨调用，线性扫描和图着色能用的寄存器一样多
+        let mut source = String::from("int main() {\nint x = getint();\n");
+        for i in 0..24{
+            source += &format!("int a{} = x * {};\n", i, i + 1);
+        }
+        source += "int i = 0; int s = 0; while (i < 10) {\n";
+        for i in 0..24{
+            source += &format!("s = s + a{} * i;\n", i);
+        }
+        source += "i = i + 1; }\nreturn s";
+        for i in 0..24{
+            source += &format!(" + a{}", i);
+        }
+        source += "; }";
+        let graph = stats(&source, RegAllocKind::GraphColoring);
+        let linear = stats(&source, RegAllocKind::LinearScan);
+        let spilled = |line: &str| -> usize{
+            line.split(", ").nth(1).unwrap().split(' ').next().unwrap().parse().unwrap()
+        };
+        assert!(graph.starts_with("fun @main:"), "{}", graph);
+        assert!(spilled(&graph) < spilled(&linear), "{}\n{}", graph, linear);
+        assert!(!graph.contains(" 0 of"), "{}", graph);
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use koopa::ir::{BinaryOp, FunctionData, Program, Value, ValueKind};
+use crate::optim::dominance::DominatorTree;
+use crate::optim::utils::{remove_insts, replace_uses};
+
+/// 基于支配树的公共子表达式消除：沿支配树先序遍历，一条纯计算指令如果和某个支配它的
+/// 指令计算的是同一个表达式，就用后者替换掉它。应在 mem2reg 之后运行。
+pub fn gvn(program: &mut Program){
+    for func in program.func_layout().to_vec(){
+        let func_data = program.func_mut(func);
+        if func_data.layout().entry_bb().is_some(){
+            eliminate(func_data);
+        }
+    }
+}
+
+/// 表达式的操作数。koopa 里相等的常量可能是不同的值，按数值比较
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand{
+    Const(i32),
+    Value(Value),
+}
+
+/// 没有副作用的表达式，操作数已经换成各自的代表值
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Expr{
+    Binary(BinaryOp, Operand, Operand),
+    GetElemPtr(Value, Operand),
+    GetPtr(Value, Operand),
+}
+
+fn eliminate(func_data: &mut FunctionData){
+    let dom = DominatorTree::new(func_data);
+    let mut leader: HashMap<Value, Value> = HashMap::new();
+    let mut table: HashMap<Expr, Value> = HashMap::new();
+    // 离开一个块时撤销它加入的表项
+    let mut undo: Vec<Expr> = Vec::new();
+    let mut stack = vec![(dom.root().unwrap(), None)];
+    while let Some((bb, mark)) = stack.pop(){
+        if let Some(mark) = mark{
+            for expr in undo.drain(mark..){
+                table.remove(&expr);
+            }
+            continue;
+        }
+        stack.push((bb, Some(undo.len())));
+        for &inst in func_data.layout().bbs().node(&bb).unwrap().insts().keys(){
+            let exprs = match expr(func_data, &leader, inst){
+                Some(exprs) => exprs,
+                None => continue,
+            };
+            match exprs.iter().find_map(|e| table.get(e)){
+                Some(&same) => {
+                    leader.insert(inst, same);
+                }
+                None => {
+                    table.insert(exprs[0].clone(), inst);
+                    undo.push(exprs[0].clone());
+                }
+            }
+        }
+        stack.extend(dom.children(bb).into_iter().rev().map(|child| (child, None)));
+    }
+    let redundant: Vec<Value> = leader.keys().copied().collect();
+    replace_uses(func_data, &leader);
+    remove_insts(func_data, redundant);
+}
+
+/// 指令对应的表达式；可交换的二元运算同时给出交换操作数后的写法
+fn expr(func_data: &FunctionData, leader: &HashMap<Value, Value>, inst: Value) -> Option<Vec<Expr>>{
+    let operand = |v: Value| match func_data.dfg().values().get(&v).map(|data| data.kind()){
+        Some(ValueKind::Integer(i)) => Operand::Const(i.value()),
+        _ => Operand::Value(*leader.get(&v).unwrap_or(&v)),
+    };
+    let base = |v: Value| *leader.get(&v).unwrap_or(&v);
+    Some(match func_data.dfg().value(inst).kind(){
+        ValueKind::Binary(binary) => {
+            let (op, l, r) = (binary.op(), operand(binary.lhs()), operand(binary.rhs()));
+            let commutative = matches!(op, BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or
+                | BinaryOp::Xor | BinaryOp::Eq | BinaryOp::NotEq);
+            if commutative && l != r{
+                vec![Expr::Binary(op, l, r), Expr::Binary(op, r, l)]
+            } else {
+                vec![Expr::Binary(op, l, r)]
+            }
+        }
+        ValueKind::GetElemPtr(gep) => vec![Expr::GetElemPtr(base(gep.src()), operand(gep.index()))],
+        ValueKind::GetPtr(gp) => vec![Expr::GetPtr(base(gp.src()), operand(gp.index()))],
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::optim::mem2reg::mem2reg;
+    use crate::optim::test_utils::{binary_ops, block, block_insts, func, insts, program};
+
+    #[test]
+    fn repeated_address_is_computed_once(){
+        let mut program = program("int a[4][4]; int main() { int i = getint(); int j = getint(); \
+            a[i][j] = a[i][j] + 1; putint(i * j); if (i) { putint(a[i][j] + i * j); } putint(j * i); return 0; }");
+        mem2reg(&mut program);
+        gvn(&mut program);
+        let func_data = program.func(func(&program, "@main"));
+        let addresses: Vec<Value> = insts(func_data).into_iter()
+            .filter(|&inst| matches!(func_data.dfg().value(inst).kind(), ValueKind::GetElemPtr(_)))
+            .collect();
+        assert_eq!(addresses.len(), 2);
+        // then 分支里的 `a[i][j]` 直接用入口块算出的地址
+        let load = block_insts(func_data, block(func_data, "%then_1"))[0];
+        match func_data.dfg().value(load).kind(){
+            ValueKind::Load(load) => assert_eq!(load.src(), addresses[1]),
+            kind => panic!("{:?}", kind),
+        }
+        // 入口块的 `i * j` 支配后面的 `i * j` 和交换了操作数的 `j * i`
+        assert_eq!(binary_ops(func_data).iter().filter(|&&op| op == BinaryOp::Mul).count(), 1);
+    }
+}
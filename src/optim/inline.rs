@@ -0,0 +1,355 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::builder_traits::*;
+use koopa::ir::entities::ValueData;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use crate::optim::dominance::DominatorTree;
+use crate::optim::utils::{map_operands, replace_uses};
+
+/// 默认只内联不超过这么多条指令的函数
+pub const DEFAULT_THRESHOLD: usize = 40;
+/// `-O2` 默认的内联上限
+pub const O2_THRESHOLD: usize = 160;
+
+/// 函数内联：被调用函数的指令数不超过 `threshold` 且不在调用环上时，把调用换成
+/// 函数体的副本。调用所在的块在调用处拆开，后半段成为汇合块，返回值是它的参数。
+/// 按调用图自底向上处理，被调用函数里能内联的调用已经先展开了。应在 mem2reg 之后运行。
+pub fn inline_functions(program: &mut Program, threshold: usize){
+    if threshold == 0{
+        return;
+    }
+    let graph = call_graph(program);
+    let recursive: HashSet<Function> = graph.keys().copied().filter(|&f| reaches(&graph, f, f)).collect();
+    let mut counter = 0;
+    for caller in bottom_up(program, &graph){
+        let sites: Vec<(Value, Function)> = program.func(caller).layout().bbs().nodes()
+            .flat_map(|node| node.insts().keys().copied())
+            .filter_map(|inst| match program.func(caller).dfg().value(inst).kind(){
+                ValueKind::Call(call) => Some((inst, call.callee())),
+                _ => None,
+            })
+            .collect();
+        let mut bodies: HashMap<Function, Option<Body>> = HashMap::new();
+        for (call, callee) in sites{
+            let body = bodies.entry(callee).or_insert_with(||{
+                let callee_data = program.func(callee);
+                let size = callee_data.layout().bbs().nodes().map(|node| node.insts().len()).sum::<usize>();
+                if recursive.contains(&callee) || callee_data.layout().entry_bb().is_none() || size > threshold{
+                    None
+                } else {
+                    Some(Body::new(callee_data))
+                }
+            });
+            if let Some(body) = body{
+                counter += 1;
+                inline_call(program.func_mut(caller), call, body, counter);
+            }
+        }
+    }
+}
+
+/// 每个函数直接调用的函数
+fn call_graph(program: &Program) -> HashMap<Function, HashSet<Function>>{
+    program.func_layout().iter().map(|&func|{
+        let func_data = program.func(func);
+        let callees = func_data.layout().bbs().nodes()
+            .flat_map(|node| node.insts().keys())
+            .filter_map(|&inst| match func_data.dfg().value(inst).kind(){
+                ValueKind::Call(call) => Some(call.callee()),
+                _ => None,
+            })
+            .collect();
+        (func, callees)
+    }).collect()
+}
+
+/// 从 `from` 出发沿调用边至少走一步能否到达 `to`
+fn reaches(graph: &HashMap<Function, HashSet<Function>>, from: Function, to: Function) -> bool{
+    let mut visited = HashSet::new();
+    let mut stack: Vec<Function> = graph[&from].iter().copied().collect();
+    while let Some(func) = stack.pop(){
+        if func == to{
+            return true;
+        }
+        if visited.insert(func){
+            stack.extend(graph[&func].iter().copied());
+        }
+    }
+    false
+}
+
+/// 调用图的后序：被调用的函数排在调用者前面，调用环里的顺序任意
+fn bottom_up(program: &Program, graph: &HashMap<Function, HashSet<Function>>) -> Vec<Function>{
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    for &root in program.func_layout(){
+        if !visited.insert(root){
+            continue;
+        }
+        // 显式栈：(函数, 还没有访问的被调用函数)
+        let mut stack = vec![(root, callees_in_layout(program, graph, root))];
+        while let Some((func, pending)) = stack.last_mut(){
+            match pending.pop(){
+                Some(callee) => {
+                    if visited.insert(callee){
+                        let next = callees_in_layout(program, graph, callee);
+                        stack.push((callee, next));
+                    }
+                }
+                None => {
+                    order.push(*func);
+                    stack.pop();
+                }
+            }
+        }
+    }
+    order.retain(|&func| program.func(func).layout().entry_bb().is_some());
+    order
+}
+
+/// 按函数布局的逆序给出，弹栈时就是布局顺序，保证结果是确定的
+fn callees_in_layout(program: &Program, graph: &HashMap<Function, HashSet<Function>>, func: Function) -> Vec<Function>{
+    program.func_layout().iter().rev().copied().filter(|f| graph[&func].contains(f)).collect()
+}
+
+/// 被内联函数的一份快照，复制时不再需要借用它
+struct Body{
+    name: String,
+    params: Vec<Value>,
+    /// 可达的基本块，按布局顺序，第一个是入口
+    blocks: Vec<BasicBlock>,
+    /// 支配者在前的顺序，复制指令时定义先于使用
+    order: Vec<BasicBlock>,
+    block_names: HashMap<BasicBlock, String>,
+    block_params: HashMap<BasicBlock, Vec<Value>>,
+    insts: HashMap<BasicBlock, Vec<Value>>,
+    values: HashMap<Value, ValueData>,
+    /// 只在入口块存入形参、其他地方只被读取的 alloc（数组形参），读出的就是对应的形参
+    forwarded: HashMap<Value, Value>,
+    returns: usize,
+}
+impl Body{
+    fn new(func_data: &FunctionData) -> Body{
+        let dom = DominatorTree::new(func_data);
+        let blocks: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().filter(|&bb| dom.is_reachable(bb)).collect();
+        let insts: HashMap<BasicBlock, Vec<Value>> = blocks.iter()
+            .map(|&bb| (bb, func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect()))
+            .collect();
+        let returns = insts.values().flatten()
+            .filter(|&&inst| matches!(func_data.dfg().value(inst).kind(), ValueKind::Return(_)))
+            .count();
+        Body{
+            name: func_data.name()[1..].to_string(),
+            params: func_data.params().to_vec(),
+            order: dom.reverse_postorder(),
+            block_names: blocks.iter().map(|&bb|{
+                let name = func_data.dfg().bb(bb).name().clone().unwrap_or_else(|| "bb".to_string());
+                (bb, name[1..].to_string())
+            }).collect(),
+            block_params: blocks.iter().map(|&bb| (bb, func_data.dfg().bb(bb).params().to_vec())).collect(),
+            forwarded: forwarded_params(func_data, &insts[&blocks[0]]),
+            insts,
+            values: func_data.dfg().values().clone(),
+            returns,
+            blocks,
+        }
+    }
+}
+
+/// 入口块里 `store 形参, alloc` 之后这个 alloc 只被 load，load 的结果可以直接换成实参
+fn forwarded_params(func_data: &FunctionData, entry: &[Value]) -> HashMap<Value, Value>{
+    let mut forwarded = HashMap::new();
+    for (pos, &inst) in entry.iter().enumerate(){
+        let store = match func_data.dfg().value(inst).kind(){
+            ValueKind::Store(store) => store,
+            _ => continue,
+        };
+        let (param, alloc) = (store.value(), store.dest());
+        if alloc.is_global() || !matches!(func_data.dfg().value(param).kind(), ValueKind::FuncArgRef(_))
+            || !matches!(func_data.dfg().value(alloc).kind(), ValueKind::Alloc(_)){
+            continue;
+        }
+        let only_loaded = func_data.dfg().value(alloc).used_by().iter().all(|&user|{
+            user == inst || (matches!(func_data.dfg().value(user).kind(), ValueKind::Load(_))
+                && !entry[..pos].contains(&user))
+        });
+        if only_loaded{
+            forwarded.insert(alloc, param);
+        }
+    }
+    forwarded
+}
+
+/// 把 `call` 换成 `body` 的副本，新块的名字带上编号 `n`，汇编里的标号不会重复。
+/// 入口块的副本直接接在调用之前的指令后面；只有一处返回时，调用之后的指令
+/// 接在返回所在块的后面，否则放进一个以返回值为参数的汇合块
+fn inline_call(func_data: &mut FunctionData, call: Value, body: &Body, n: usize){
+    let bb = func_data.layout().parent_bb(call).unwrap();
+    let args = match func_data.dfg().value(call).kind(){
+        ValueKind::Call(call) => call.args().to_vec(),
+        _ => unreachable!(),
+    };
+    let ret_ty = func_data.dfg().value(call).ty().clone();
+    let rest: Vec<Value> = func_data.layout().bbs().node(&bb).unwrap().insts().keys()
+        .skip_while(|&&inst| inst != call).copied().collect();
+    for inst in &rest{
+        func_data.layout_mut().bb_mut(bb).insts_mut().remove(inst);
+    }
+    let rest = &rest[1..];
+
+    // 先建好所有块，跳转指令才能引用它们
+    let mut values: HashMap<Value, Value> = body.params.iter().copied().zip(args).collect();
+    let mut blocks: HashMap<BasicBlock, BasicBlock> = HashMap::from([(body.blocks[0], bb)]);
+    let mut last = bb;
+    for &old in &body.blocks[1..]{
+        let tys: Vec<Type> = body.block_params[&old].iter().map(|p| body.values[p].ty().clone()).collect();
+        let name = format!("%inline{}_{}_{}", n, body.name, body.block_names[&old]);
+        let new = func_data.dfg_mut().new_bb().basic_block_with_params(Some(name), tys);
+        func_data.layout_mut().bbs_mut().cursor_mut(last).insert_key_after(new).unwrap();
+        values.extend(body.block_params[&old].iter().copied().zip(func_data.dfg().bb(new).params().to_vec()));
+        blocks.insert(old, new);
+        last = new;
+    }
+    let join = if body.returns == 1{
+        None
+    } else {
+        let params = if ret_ty.is_unit() {Vec::new()} else {vec![ret_ty]};
+        let join = func_data.dfg_mut().new_bb().basic_block_with_params(Some(format!("%inline{}_{}_ret", n, body.name)), params);
+        func_data.layout_mut().bbs_mut().cursor_mut(last).insert_key_after(join).unwrap();
+        Some(join)
+    };
+
+    let entry = func_data.layout().entry_bb().unwrap();
+    // 只有一处返回时，返回所在的块和返回值
+    let mut ret_at: Option<(BasicBlock, Option<Value>)> = None;
+    for old in &body.order{
+        let new = blocks[old];
+        for inst in &body.insts[old]{
+            if body.forwarded.contains_key(inst){
+                continue;
+            }
+            match body.values[inst].kind(){
+                ValueKind::Store(store) if body.forwarded.contains_key(&store.dest()) => continue,
+                ValueKind::Load(load) if body.forwarded.contains_key(&load.src()) => {
+                    let arg = values[&body.forwarded[&load.src()]];
+                    values.insert(*inst, arg);
+                    continue;
+                }
+                _ => {}
+            }
+            let mut kind = body.values[inst].kind().clone();
+            map_operands(&mut kind, |v| operand(func_data, body, &mut values, v));
+            let value = match (kind, join){
+                (ValueKind::Return(ret), Some(join)) => {
+                    func_data.dfg_mut().new_value().jump_with_args(join, ret.value().into_iter().collect())
+                }
+                (ValueKind::Return(ret), None) => {
+                    ret_at = Some((new, ret.value()));
+                    continue;
+                }
+                (kind, _) => build(func_data, kind, body.values[inst].ty(), &blocks),
+            };
+            values.insert(*inst, value);
+            // 数组等局部变量的空间统一放在入口块
+            if matches!(func_data.dfg().value(value).kind(), ValueKind::Alloc(_)){
+                func_data.layout_mut().bb_mut(entry).insts_mut().push_key_front(value).unwrap();
+            } else {
+                func_data.layout_mut().bb_mut(new).insts_mut().push_key_back(value).unwrap();
+            }
+        }
+    }
+
+    let (rest_bb, result) = match (join, ret_at){
+        (Some(join), _) => (join, func_data.dfg().bb(join).params().first().copied()),
+        (None, Some((ret_bb, value))) => (ret_bb, value),
+        (None, None) => unreachable!(),
+    };
+    for &inst in rest{
+        func_data.layout_mut().bb_mut(rest_bb).insts_mut().push_key_back(inst).unwrap();
+    }
+    if let Some(result) = result{
+        replace_uses(func_data, &HashMap::from([(call, result)]));
+    }
+    func_data.dfg_mut().remove_value(call);
+}
+
+/// 副本中对应的值：参数和已经复制的指令查表，全局变量不变，常量在调用者中重新创建
+fn operand(func_data: &mut FunctionData, body: &Body, values: &mut HashMap<Value, Value>, v: Value) -> Value{
+    if v.is_global(){
+        return v;
+    }
+    if let Some(&new) = values.get(&v){
+        return new;
+    }
+    let data = &body.values[&v];
+    let new = match data.kind(){
+        ValueKind::Integer(i) => func_data.dfg_mut().new_value().integer(i.value()),
+        ValueKind::ZeroInit(_) => func_data.dfg_mut().new_value().zero_init(data.ty().clone()),
+        ValueKind::Undef(_) => func_data.dfg_mut().new_value().undef(data.ty().clone()),
+        ValueKind::Aggregate(agg) => {
+            let elems = agg.elems().iter().map(|&e| operand(func_data, body, values, e)).collect();
+            func_data.dfg_mut().new_value().aggregate(elems)
+        }
+        kind => panic!("value {:?} used before its definition", kind),
+    };
+    values.insert(v, new);
+    new
+}
+
+/// 用操作数已经替换好的 `kind` 新建一条指令，跳转目标换成副本中的块
+fn build(func_data: &mut FunctionData, kind: ValueKind, ty: &Type, blocks: &HashMap<BasicBlock, BasicBlock>) -> Value{
+    let builder = func_data.dfg_mut().new_value();
+    match kind{
+        ValueKind::Alloc(_) => match ty.kind(){
+            TypeKind::Pointer(base) => builder.alloc(base.clone()),
+            _ => unreachable!(),
+        },
+        ValueKind::Load(load) => builder.load(load.src()),
+        ValueKind::Store(store) => builder.store(store.value(), store.dest()),
+        ValueKind::GetPtr(gp) => builder.get_ptr(gp.src(), gp.index()),
+        ValueKind::GetElemPtr(gep) => builder.get_elem_ptr(gep.src(), gep.index()),
+        ValueKind::Binary(binary) => builder.binary(binary.op(), binary.lhs(), binary.rhs()),
+        ValueKind::Branch(branch) => builder.branch_with_args(branch.cond(), blocks[&branch.true_bb()], blocks[&branch.false_bb()],
+                                                              branch.true_args().to_vec(), branch.false_args().to_vec()),
+        ValueKind::Jump(jump) => builder.jump_with_args(blocks[&jump.target()], jump.args().to_vec()),
+        ValueKind::Call(call) => builder.call(call.callee(), call.args().to_vec()),
+        kind => panic!("unexpected instruction {:?}", kind),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::optim::mem2reg::mem2reg;
+    use crate::optim::test_utils::{block, block_names, callees, func, program};
+
+    const SOURCE: &str = "int add(int a, int b) { return a + b; } \
+        int abs(int x) { if (x < 0) return -x; return x; } \
+        int fact(int n) { if (n < 2) return 1; return n * fact(n - 1); } \
+        int main() { int x = getint(); putint(add(x, 1) + abs(x)); return fact(x); }";
+
+    #[test]
+    fn small_functions_are_inlined(){
+        let mut program = program(SOURCE);
+        mem2reg(&mut program);
+        inline_functions(&mut program, DEFAULT_THRESHOLD);
+        let main = func(&program, "@main");
+        // 递归函数不内联
+        assert_eq!(callees(&program, main), vec!["@getint", "@putint", "@fact"]);
+        assert_eq!(callees(&program, func(&program, "@fact")), vec!["@fact"]);
+        // abs 有两处返回，返回值成为汇合块的参数
+        let func_data = program.func(main);
+        let names = block_names(func_data);
+        assert!(names.contains(&"%inline2_abs_then_1".to_string()), "{:?}", names);
+        assert_eq!(func_data.dfg().bb(block(func_data, "%inline2_abs_end_abs")).params().len(), 1);
+    }
+
+    #[test]
+    fn zero_threshold_keeps_calls(){
+        let mut program = program(SOURCE);
+        mem2reg(&mut program);
+        inline_functions(&mut program, 0);
+        let main = func(&program, "@main");
+        assert_eq!(callees(&program, main), vec!["@getint", "@add", "@abs", "@putint", "@fact"]);
+        assert_eq!(block_names(program.func(main)), vec!["%entry", "%end_main"]);
+    }
+}
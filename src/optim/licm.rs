@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+use koopa::ir::{BasicBlock, BinaryOp, FunctionData, Program, Value, ValueKind};
+use crate::optim::loops::{Loop, LoopForest};
+
+/// 循环不变量外提：把循环里操作数都在循环外定义的二元运算和地址计算
+/// 移到循环的前置块末尾。内层循环先处理，外提出来的值还可以继续提到更外层。
+/// 没有前置块的循环不做处理。
+pub fn licm(program: &mut Program){
+    for func in program.func_layout().to_vec(){
+        let func_data = program.func_mut(func);
+        if func_data.layout().entry_bb().is_none(){
+            continue;
+        }
+        // 只移动指令，不改变控制流，循环信息一直有效
+        let forest = LoopForest::new(func_data);
+        for l in forest.loops.iter().rev(){
+            if let Some(preheader) = l.preheader{
+                hoist_loop(func_data, l, preheader);
+            }
+        }
+    }
+}
+
+fn hoist_loop(func_data: &mut FunctionData, l: &Loop, preheader: BasicBlock){
+    let blocks: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().filter(|&bb| l.contains(bb)).collect();
+    let params: HashSet<Value> = blocks.iter()
+        .flat_map(|&bb| func_data.dfg().bb(bb).params().iter().copied())
+        .collect();
+    // 按布局顺序扫描，定义在使用之前，直到没有可以外提的指令
+    loop{
+        let mut changed = false;
+        for &bb in &blocks{
+            let insts: Vec<Value> = func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            for inst in insts{
+                if hoistable(func_data, l, &params, inst){
+                    func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                    let insts = func_data.layout_mut().bb_mut(preheader).insts_mut();
+                    let last = *insts.back_key().unwrap();
+                    insts.cursor_mut(last).insert_key_before(inst).unwrap();
+                    changed = true;
+                }
+            }
+        }
+        if !changed{
+            break;
+        }
+    }
+}
+
+/// 没有副作用、不会出错并且操作数都在循环外定义的指令
+fn hoistable(func_data: &FunctionData, l: &Loop, params: &HashSet<Value>, inst: Value) -> bool{
+    let invariant = |v: Value|{
+        if v.is_global(){
+            return true;
+        }
+        let data = func_data.dfg().value(v);
+        match data.kind(){
+            _ if data.kind().is_const() => true,
+            ValueKind::FuncArgRef(_) => true,
+            ValueKind::BlockArgRef(_) => !params.contains(&v),
+            _ => func_data.layout().parent_bb(v).is_some_and(|bb| !l.contains(bb)),
+        }
+    };
+    let kind = func_data.dfg().value(inst).kind();
+    let pure = match kind{
+        // 除数为零时不能提到可能不执行的位置
+        ValueKind::Binary(binary) if matches!(binary.op(), BinaryOp::Div | BinaryOp::Mod) =>
+            matches!(func_data.dfg().value(binary.rhs()).kind(), ValueKind::Integer(i) if i.value() != 0),
+        ValueKind::Binary(_) | ValueKind::GetElemPtr(_) | ValueKind::GetPtr(_) => true,
+        _ => false,
+    };
+    pure && kind.value_uses().all(invariant)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::optim::mem2reg::mem2reg;
+    use crate::optim::test_utils::{block, block_insts, func, program};
+
+    #[test]
+    fn invariant_address_is_hoisted(){
+        let mut program = program("int a[10][10]; int main() { int i = 0; int j = getint(); int s = 0; \
+            while (i < 10) { s = s + a[j][i] + j * 13 + 100 / j; i = i + 1; } return s; }");
+        mem2reg(&mut program);
+        licm(&mut program);
+        let func_data = program.func(func(&program, "@main"));
+        let count = |name: &str, f: fn(&ValueKind) -> bool| block_insts(func_data, block(func_data, name)).into_iter()
+            .filter(|&inst| f(func_data.dfg().value(inst).kind()))
+            .count();
+        let getelemptr = |kind: &ValueKind| matches!(kind, ValueKind::GetElemPtr(_));
+        let mul = |kind: &ValueKind| matches!(kind, ValueKind::Binary(b) if b.op() == BinaryOp::Mul);
+        let div = |kind: &ValueKind| matches!(kind, ValueKind::Binary(b) if b.op() == BinaryOp::Div);
+        // `a[j]` 和 `j * 13` 提到前置块，`a[j][i]` 和可能除零的除法留在循环里
+        assert_eq!((count("%entry", getelemptr), count("%entry", mul)), (1, 1));
+        assert_eq!((count("%while_body_1", getelemptr), count("%while_body_1", mul)), (1, 0));
+        assert_eq!((count("%entry", div), count("%while_body_1", div)), (0, 1));
+    }
+}
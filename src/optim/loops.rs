@@ -0,0 +1,189 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::{BasicBlock, Function, FunctionData, Program};
+use crate::optim::dominance::DominatorTree;
+use crate::optim::utils::{predecessors, successors};
+
+/// 一个自然循环。同一个循环头的多条回边合成一个循环
+#[derive(Debug, Clone)]
+pub struct Loop{
+    pub header: BasicBlock,
+    /// 循环体，包括循环头
+    pub blocks: HashSet<BasicBlock>,
+    /// 回边的起点，按布局顺序
+    pub latches: Vec<BasicBlock>,
+    /// 循环外、有前驱在循环里的块，按布局顺序
+    pub exits: Vec<BasicBlock>,
+    /// 循环外进入循环头的唯一前驱，并且它只跳到循环头
+    pub preheader: Option<BasicBlock>,
+    /// 外层循环在 `LoopForest::loops` 中的下标
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// 最外层循环为 1
+    pub depth: u32,
+}
+impl Loop{
+    pub fn contains(&self, bb: BasicBlock) -> bool{
+        self.blocks.contains(&bb)
+    }
+}
+
+/// 一个函数的循环嵌套森林。外层循环排在内层循环前面
+#[derive(Debug, Clone, Default)]
+pub struct LoopForest{
+    pub loops: Vec<Loop>,
+    /// 每个块所在的最内层循环
+    innermost: HashMap<BasicBlock, usize>,
+}
+impl LoopForest{
+    pub fn new(func_data: &FunctionData) -> LoopForest{
+        let dom = DominatorTree::new(func_data);
+        let preds = predecessors(func_data);
+        let layout: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+        let position: HashMap<BasicBlock, usize> = layout.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+
+        // 回边 `bb -> header`（`header` 支配 `bb`）逆着边能走到的块组成循环体
+        let mut headers: Vec<BasicBlock> = Vec::new();
+        let mut bodies: HashMap<BasicBlock, (HashSet<BasicBlock>, Vec<BasicBlock>)> = HashMap::new();
+        for &bb in &layout{
+            if !dom.is_reachable(bb){
+                continue;
+            }
+            for header in successors(func_data, bb){
+                if !dom.dominates(header, bb){
+                    continue;
+                }
+                let (body, latches) = bodies.entry(header).or_insert_with(||{
+                    headers.push(header);
+                    (HashSet::from([header]), Vec::new())
+                });
+                latches.push(bb);
+                let mut stack = vec![bb];
+                while let Some(x) = stack.pop(){
+                    if body.insert(x){
+                        stack.extend(preds[&x].iter().copied().filter(|&p| dom.is_reachable(p)));
+                    }
+                }
+            }
+        }
+        // 自然循环要么不相交要么嵌套，循环体大的在前就保证了外层在前
+        headers.sort_by_key(|header| (std::cmp::Reverse(bodies[header].0.len()), position[header]));
+
+        let mut loops: Vec<Loop> = Vec::new();
+        for header in headers{
+            let (blocks, latches) = bodies.remove(&header).unwrap();
+            let mut exits: Vec<BasicBlock> = blocks.iter()
+                .flat_map(|&bb| successors(func_data, bb))
+                .filter(|succ| !blocks.contains(succ))
+                .collect();
+            exits.sort_by_key(|bb| position[bb]);
+            exits.dedup();
+            let entering: Vec<BasicBlock> = preds[&header].iter().copied()
+                .filter(|p| !blocks.contains(p) && dom.is_reachable(*p))
+                .collect();
+            let preheader = match entering[..]{
+                [p] if successors(func_data, p).len() == 1 => Some(p),
+                _ => None,
+            };
+            let parent = loops.iter().rposition(|l| l.contains(header));
+            let depth = parent.map_or(1, |p| loops[p].depth + 1);
+            let index = loops.len();
+            if let Some(p) = parent{
+                loops[p].children.push(index);
+            }
+            loops.push(Loop{header, blocks, latches, exits, preheader, parent, children: Vec::new(), depth});
+        }
+
+        let mut innermost = HashMap::new();
+        for (i, l) in loops.iter().enumerate(){
+            for &bb in &l.blocks{
+                innermost.insert(bb, i);
+            }
+        }
+        LoopForest{loops, innermost}
+    }
+
+    /// 块所在的最内层循环
+    pub fn loop_of(&self, bb: BasicBlock) -> Option<&Loop>{
+        self.innermost.get(&bb).map(|&i| &self.loops[i])
+    }
+    /// 块的循环嵌套深度，不在循环里为 0
+    pub fn depth(&self, bb: BasicBlock) -> u32{
+        self.loop_of(bb).map_or(0, |l| l.depth)
+    }
+    /// 最外层的循环
+    pub fn top_level(&self) -> impl Iterator<Item = &Loop>{
+        self.loops.iter().filter(|l| l.parent.is_none())
+    }
+}
+
+pub trait LoopAnalysis{
+    fn loops(&self) -> HashMap<Function, LoopForest>;
+    fn print_loops(&self, info: &HashMap<Function, LoopForest>) -> String;
+}
+impl LoopAnalysis for Program{
+    /// 计算每个有函数体的函数的循环嵌套森林
+    fn loops(&self) -> HashMap<Function, LoopForest>{
+        self.func_layout().iter()
+            .filter(|&&func| self.func(func).layout().entry_bb().is_some())
+            .map(|&func| (func, LoopForest::new(self.func(func))))
+            .collect()
+    }
+    /// 按嵌套关系缩进列出每个循环的循环体、回边、出口和前置块
+    fn print_loops(&self, info: &HashMap<Function, LoopForest>) -> String{
+        let mut s = String::new();
+        for func in self.func_layout(){
+            let (func_data, forest) = match info.get(func){
+                Some(forest) => (self.func(*func), forest),
+                None => continue,
+            };
+            let name = |bb: BasicBlock| func_data.dfg().bb(bb).name().clone().unwrap_or_else(|| format!("{:?}", bb));
+            let names = |bbs: &[BasicBlock]| bbs.iter().map(|&bb| name(bb)).collect::<Vec<_>>().join(", ");
+            s += &format!("fun {}\n", func_data.name());
+            let mut stack: Vec<usize> = (0..forest.loops.len()).filter(|&i| forest.loops[i].parent.is_none()).rev().collect();
+            while let Some(i) = stack.pop(){
+                let l = &forest.loops[i];
+                let blocks: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().filter(|&bb| l.contains(bb)).collect();
+                s += &format!("{}loop {}: depth {}, blocks [{}], latches [{}], exits [{}], preheader {}\n",
+                              "  ".repeat(l.depth as usize), name(l.header), l.depth, names(&blocks), names(&l.latches), names(&l.exits),
+                              l.preheader.map_or("-".to_string(), name));
+                stack.extend(l.children.iter().rev());
+            }
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::optim::test_utils::{block, func, program};
+
+    #[test]
+    fn nested_loops(){
+        let program = program("int main() { int i = 0; int s = 0; while (i < 10) { int j = 0; \
+            while (j < i) { if (j == 5) break; s = s + j; j = j + 1; } i = i + 1; } \
+            while (s > 100) s = s / 2; return s; }");
+        let func_data = program.func(func(&program, "@main"));
+        let bb = |name: &str| block(func_data, name);
+        let forest = LoopForest::new(func_data);
+        assert_eq!(forest.loops.len(), 3);
+        assert_eq!(forest.top_level().count(), 2);
+
+        let outer = forest.loop_of(bb("%while_body_1")).unwrap();
+        assert_eq!(outer.header, bb("%while_entry_1"));
+        assert_eq!(outer.exits, vec![bb("%while_end_1")]);
+        assert_eq!(outer.preheader, Some(bb("%entry")));
+        assert_eq!(outer.children.len(), 1);
+
+        let inner = forest.loop_of(bb("%while_body_2")).unwrap();
+        assert_eq!(inner.header, bb("%while_entry_2"));
+        assert_eq!(inner.depth, 2);
+        assert!(outer.contains(inner.header));
+        // break 跳出的块也是内层循环的出口
+        assert_eq!(inner.exits, vec![bb("%then_3"), bb("%while_end_2")]);
+        assert_eq!(forest.depth(bb("%while_body_2")), 2);
+        assert_eq!(forest.depth(bb("%while_end_2")), 1);
+        assert_eq!(forest.depth(bb("%while_end_1")), 0);
+        assert_eq!(forest.depth(bb("%entry")), 0);
+    }
+}
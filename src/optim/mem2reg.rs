@@ -0,0 +1,250 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, FunctionData, Program, Type, Value, ValueKind};
+use crate::optim::dominance::DominatorTree;
+use crate::optim::utils::{new_block_params, predecessors, remove_inst, replace_uses};
+
+/// 把只通过 load/store 访问的标量 alloc 提升为 SSA 值。
+/// 汇合点处的值用基本块参数表示，前驱在跳转时传入各自的当前值。
+pub fn mem2reg(program: &mut Program){
+    for func in program.func_layout().to_vec(){
+        let func_data = program.func_mut(func);
+        if func_data.layout().entry_bb().is_some(){
+            promote(func_data);
+        }
+    }
+}
+
+/// 类型为 `*i32`，且所有使用都是以它为地址的 load/store。
+/// 保存数组形参的 `*(*i32)` 不提升：后端的 getptr 要求基址来自 load。
+fn promotable(func_data: &FunctionData, alloc: Value) -> bool{
+    let data = func_data.dfg().value(alloc);
+    if *data.ty() != Type::get_pointer(Type::get_i32()){
+        return false;
+    }
+    data.used_by().iter().all(|&user| match func_data.dfg().value(user).kind(){
+        ValueKind::Load(load) => load.src() == alloc,
+        ValueKind::Store(store) => store.dest() == alloc && store.value() != alloc,
+        _ => false,
+    })
+}
+
+enum Visit{
+    Enter(BasicBlock),
+    /// 离开基本块时弹出在其中压入的定义
+    Exit(Vec<usize>),
+}
+
+fn promote(func_data: &mut FunctionData){
+    let allocs: Vec<Value> = func_data.layout().bbs().nodes()
+        .flat_map(|node| node.insts().keys().copied())
+        .filter(|&inst| matches!(func_data.dfg().value(inst).kind(), ValueKind::Alloc(_)))
+        .filter(|&inst| promotable(func_data, inst))
+        .collect();
+    if allocs.is_empty(){
+        return;
+    }
+    let index: HashMap<Value, usize> = allocs.iter().enumerate().map(|(i, &a)| (a, i)).collect();
+    let dom = DominatorTree::new(func_data);
+    let rpo = dom.reverse_postorder();
+    let preds = predecessors(func_data);
+
+    // 每个变量被定值的块，以及在块内先读后写（入口处活跃）的块
+    let mut def_blocks: Vec<HashSet<BasicBlock>> = vec![HashSet::new(); allocs.len()];
+    let mut live_in: Vec<HashSet<BasicBlock>> = vec![HashSet::new(); allocs.len()];
+    for &bb in &rpo{
+        let mut defined = HashSet::new();
+        for &inst in func_data.layout().bbs().node(&bb).unwrap().insts().keys(){
+            match func_data.dfg().value(inst).kind(){
+                ValueKind::Load(load) => if let Some(&i) = index.get(&load.src()){
+                    if !defined.contains(&i){
+                        live_in[i].insert(bb);
+                    }
+                }
+                ValueKind::Store(store) => if let Some(&i) = index.get(&store.dest()){
+                    defined.insert(i);
+                    def_blocks[i].insert(bb);
+                }
+                _ => {}
+            }
+        }
+    }
+    for i in 0..allocs.len(){
+        let mut worklist: Vec<BasicBlock> = live_in[i].iter().copied().collect();
+        while let Some(bb) = worklist.pop(){
+            for &pred in &preds[&bb]{
+                if dom.is_reachable(pred) && !def_blocks[i].contains(&pred) && live_in[i].insert(pred){
+                    worklist.push(pred);
+                }
+            }
+        }
+    }
+
+    // 在定值块的迭代支配边界上、且变量活跃的地方放置块参数
+    let mut phis: HashMap<BasicBlock, Vec<usize>> = HashMap::new();
+    for i in 0..allocs.len(){
+        let mut placed = HashSet::new();
+        let mut worklist: Vec<BasicBlock> = def_blocks[i].iter().copied().collect();
+        while let Some(bb) = worklist.pop(){
+            for df in dom.frontier(bb){
+                if live_in[i].contains(&df) && placed.insert(df){
+                    phis.entry(df).or_default().push(i);
+                    worklist.push(df);
+                }
+            }
+        }
+    }
+    for &bb in &rpo{
+        if let Some(vars) = phis.get_mut(&bb){
+            vars.sort_unstable();
+            add_params(func_data, bb, vars.iter().map(|&i| allocs[i]).collect());
+        }
+    }
+
+    // 沿支配树重命名：load 换成当前值，store 更新当前值。未初始化的变量按 0 处理
+    let zero = func_data.dfg_mut().new_value().integer(0);
+    let mut current: Vec<Vec<Value>> = vec![vec![zero]; allocs.len()];
+    let mut replace: HashMap<Value, Value> = HashMap::new();
+    let mut dead: Vec<Value> = Vec::new();
+    let mut branch_args: Vec<(Value, Vec<Value>, Vec<Value>)> = Vec::new();
+    let args_for = |current: &Vec<Vec<Value>>, target: BasicBlock| -> Vec<Value>{
+        phis.get(&target).map_or(Vec::new(), |vars| vars.iter().map(|&i| *current[i].last().unwrap()).collect())
+    };
+    let mut stack = vec![Visit::Enter(rpo[0])];
+    while let Some(visit) = stack.pop(){
+        let bb = match visit{
+            Visit::Enter(bb) => bb,
+            Visit::Exit(pushed) => {
+                for i in pushed{
+                    current[i].pop();
+                }
+                continue;
+            }
+        };
+        let mut pushed = Vec::new();
+        if let Some(vars) = phis.get(&bb){
+            for (&i, &param) in vars.iter().zip(func_data.dfg().bb(bb).params()){
+                current[i].push(param);
+                pushed.push(i);
+            }
+        }
+        for &inst in func_data.layout().bbs().node(&bb).unwrap().insts().keys(){
+            match func_data.dfg().value(inst).kind(){
+                ValueKind::Load(load) => if let Some(&i) = index.get(&load.src()){
+                    replace.insert(inst, *current[i].last().unwrap());
+                    dead.push(inst);
+                }
+                ValueKind::Store(store) => if let Some(&i) = index.get(&store.dest()){
+                    let value = *replace.get(&store.value()).unwrap_or(&store.value());
+                    current[i].push(value);
+                    pushed.push(i);
+                    dead.push(inst);
+                }
+                ValueKind::Jump(jump) => {
+                    let args = args_for(&current, jump.target());
+                    if !args.is_empty(){
+                        branch_args.push((inst, args, Vec::new()));
+                    }
+                }
+                ValueKind::Branch(branch) => {
+                    let true_args = args_for(&current, branch.true_bb());
+                    let false_args = args_for(&current, branch.false_bb());
+                    if !true_args.is_empty() || !false_args.is_empty(){
+                        branch_args.push((inst, true_args, false_args));
+                    }
+                }
+                _ => {}
+            }
+        }
+        stack.push(Visit::Exit(pushed));
+        for child in dom.children(bb).into_iter().rev(){
+            stack.push(Visit::Enter(child));
+        }
+    }
+    // 不可达块中的访问没有意义，同样读到 0
+    for (&bb, node) in func_data.layout().bbs(){
+        if dom.is_reachable(bb){
+            continue;
+        }
+        for &inst in node.insts().keys(){
+            match func_data.dfg().value(inst).kind(){
+                ValueKind::Load(load) if index.contains_key(&load.src()) => {
+                    replace.insert(inst, zero);
+                    dead.push(inst);
+                }
+                ValueKind::Store(store) if index.contains_key(&store.dest()) => dead.push(inst),
+                _ => {}
+            }
+        }
+    }
+
+    replace_uses(func_data, &replace);
+    for (inst, true_args, false_args) in branch_args{
+        let mut data = func_data.dfg().value(inst).clone();
+        match data.kind_mut(){
+            ValueKind::Jump(jump) => *jump.args_mut() = true_args,
+            ValueKind::Branch(branch) => {
+                *branch.true_args_mut() = true_args;
+                *branch.false_args_mut() = false_args;
+            }
+            _ => unreachable!(),
+        }
+        func_data.dfg_mut().replace_value_with(inst).raw(data);
+    }
+    // 先删 store，load 与 alloc 才会变成无人使用
+    dead.sort_by_key(|&inst| !matches!(func_data.dfg().value(inst).kind(), ValueKind::Store(_)));
+    for inst in dead.into_iter().chain(allocs){
+        remove_inst(func_data, inst);
+    }
+}
+
+/// 给还没有参数的基本块 `bb` 添加 i32 参数，名字取自对应的 alloc
+fn add_params(func_data: &mut FunctionData, bb: BasicBlock, allocs: Vec<Value>){
+    assert!(func_data.dfg().bb(bb).params().is_empty(), "block already has parameters");
+    let params = new_block_params(func_data, vec![Type::get_i32(); allocs.len()]);
+    let dfg = func_data.dfg_mut();
+    for (&param, &alloc) in params.iter().zip(&allocs){
+        if let Some(name) = dfg.value(alloc).name().clone(){
+            dfg.set_value_name(param, Some(format!("%{}", &name[1..])));
+        }
+    }
+    *dfg.bb_mut(bb).params_mut() = params;
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::optim::test_utils::{block, func, insts, program};
+
+    /// 函数里的 alloc、load 和 store 的条数
+    fn memory_insts(func_data: &FunctionData) -> (usize, usize, usize){
+        let kinds: Vec<&ValueKind> = insts(func_data).into_iter().map(|inst| func_data.dfg().value(inst).kind()).collect();
+        let count = |f: fn(&ValueKind) -> bool| kinds.iter().filter(|&&kind| f(kind)).count();
+        (count(|k| matches!(k, ValueKind::Alloc(_))), count(|k| matches!(k, ValueKind::Load(_))),
+         count(|k| matches!(k, ValueKind::Store(_))))
+    }
+
+    #[test]
+    fn loop_variables_become_block_parameters(){
+        let mut program = program("int main() { int i = 0; int s = 0; while (i < 10) { s = s + i; i = i + 1; } return s; }");
+        mem2reg(&mut program);
+        let func_data = program.func(func(&program, "@main"));
+        assert_eq!(memory_insts(func_data), (0, 0, 0));
+        let params: Vec<String> = func_data.dfg().bb(block(func_data, "%while_entry_1")).params().iter()
+            .map(|&p| func_data.dfg().value(p).name().clone().unwrap())
+            .collect();
+        assert_eq!(params, vec!["%i", "%s"]);
+    }
+
+    #[test]
+    fn arrays_stay_in_memory(){
+        let mut program = program("int main() { int a[2] = {1, 2}; int x = getint(); if (x) x = a[x]; return x; }");
+        mem2reg(&mut program);
+        let func_data = program.func(func(&program, "@main"));
+        // 数组的 alloc 和读写留着，x 变成汇合块的参数
+        assert_eq!(memory_insts(func_data), (1, 1, 2));
+        let alloc = insts(func_data)[0];
+        assert_eq!(func_data.dfg().value(alloc).ty().to_string(), "*[i32, 2]");
+        assert_eq!(func_data.dfg().bb(block(func_data, "%end_1")).params().len(), 1);
+    }
+}
@@ -1,4 +1,69 @@
 pub mod cfg;
 pub use cfg::ControlFlowGraph;
 pub mod reg_alloc;
-pub use cfg::check_used;
\ No newline at end of file
+pub mod graph_coloring;
+pub mod split;
+pub use cfg::check_used;
+pub mod utils;
+pub mod dominance;
+pub mod loops;
+pub mod licm;
+pub mod gvn;
+pub mod inline;
+pub mod simplify;
+pub mod mem2reg;
+pub mod out_of_ssa;
+pub mod sccp;
+pub mod dce;
+#[cfg(test)]
+pub mod test_utils;
+
+use koopa::ir::Program;
+
+/// 优化等级，对应命令行的 `-O0/-O1/-O2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel{
+    #[default]
+    O0,
+    O1,
+    O2,
+}
+
+/// 按优化等级依次运行 IR 上的优化遍。`-O2` 内联更大的函数，内联和外提之后再跑一轮标量优化，
+/// 处理它们暴露出来的常量和公共子表达式。`inline_threshold` 为 `None` 时按优化等级取默认值，为 0 时不做内联
+pub fn optimize(program: &mut Program, level: OptLevel, inline_threshold: Option<usize>){
+    if level < OptLevel::O1{
+        return;
+    }
+    let (default_threshold, rounds) = match level{
+        OptLevel::O2 => (inline::O2_THRESHOLD, 2),
+        _ => (inline::DEFAULT_THRESHOLD, 1),
+    };
+    mem2reg::mem2reg(program);
+    inline::inline_functions(program, inline_threshold.unwrap_or(default_threshold));
+    for _ in 0..rounds{
+        sccp::sccp(program);
+        simplify::simplify(program);
+        gvn::gvn(program);
+        licm::licm(program);
+        dce::dce(program);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::{compile, Emit, OptLevel, Options};
+
+    #[test]
+    fn o2_inlines_larger_functions(){
+        // f 的指令数在 -O1 和 -O2 的内联上限之间
+        let body: String = (0..12).map(|i| format!("s = s * {} + getint() % {};", i + 2, i + 3)).collect();
+        let source = format!("int f(int s) {{ {} return s; }}\nint main() {{ return f(getint()); }}", body);
+        let calls = |opt_level| {
+            let options = Options{emit: vec![Emit::KoopaOpt], opt_level, ..Options::default()};
+            compile(&source, &options).unwrap().get(Emit::KoopaOpt).unwrap().contains("call @f(")
+        };
+        assert!(calls(OptLevel::O1));
+        assert!(!calls(OptLevel::O2));
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, FunctionData, Program, Type, Value, ValueKind};
+use crate::optim::utils::replace_uses;
+
+/// 把 SSA 形式的 IR 变回后端能处理的形式，生成汇编前必须调用：
+/// 后端不支持基本块参数，函数形参也只能被 store 到它自己的 alloc 里。
+/// 两者都换成 alloc，后端把标量 alloc 当作寄存器，所以这相当于插入复制。
+pub fn destruct_ssa(program: &mut Program){
+    for func in program.func_layout().to_vec(){
+        let func_data = program.func_mut(func);
+        if func_data.layout().entry_bb().is_some(){
+            lower_block_params(func_data);
+            lower_func_params(func_data);
+        }
+    }
+}
+
+/// 形参在入口先存进 alloc 再读出来，其余使用都换成读出的值
+fn lower_func_params(func_data: &mut FunctionData){
+    let entry = func_data.layout().entry_bb().unwrap();
+    let mut replace: HashMap<Value, Value> = HashMap::new();
+    let mut lowered: Vec<(Value, Value, Value)> = Vec::new();
+    // 后端在 store 处直接读形参所在的 a 寄存器，只有入口处第一次调用之前的 store 读到的还是形参
+    let before_call: HashSet<Value> = func_data.layout().bbs().node(&entry).unwrap().insts().keys().copied()
+        .take_while(|&inst| !matches!(func_data.dfg().value(inst).kind(), ValueKind::Call(_)))
+        .collect();
+    for param in func_data.params().to_vec(){
+        let data = func_data.dfg().value(param);
+        // 存进全局变量的不算，全局变量不在函数的 dfg 里
+        let only_stored = data.used_by().iter().all(|&user| match func_data.dfg().value(user).kind(){
+            ValueKind::Store(store) => store.value() == param && before_call.contains(&user)
+                && func_data.dfg().values().get(&store.dest()).is_some_and(|dest| matches!(dest.kind(), ValueKind::Alloc(_))),
+            _ => false,
+        });
+        if only_stored{
+            continue;
+        }
+        let ty = data.ty().clone();
+        let alloc = func_data.dfg_mut().new_value().alloc(ty);
+        let load = func_data.dfg_mut().new_value().load(alloc);
+        replace.insert(param, load);
+        lowered.push((param, alloc, load));
+    }
+    if lowered.is_empty(){
+        return;
+    }
+    // 先替换原有的使用，新插入的 store 要保留对形参本身的使用
+    replace_uses(func_data, &replace);
+    // 与前端一样，alloc 都放在入口块最前面，形参的 store 紧跟在所有 alloc 之后
+    for &(_, alloc, _) in lowered.iter().rev(){
+        func_data.layout_mut().bb_mut(entry).insts_mut().push_key_front(alloc).unwrap();
+    }
+    let last_alloc = func_data.layout().bbs().node(&entry).unwrap().insts().keys().copied()
+        .take_while(|&inst| matches!(func_data.dfg().value(inst).kind(), ValueKind::Alloc(_)))
+        .last()
+        .unwrap();
+    let mut position = last_alloc;
+    for (param, alloc, load) in lowered{
+        let store = func_data.dfg_mut().new_value().store(param, alloc);
+        let insts = func_data.layout_mut().bb_mut(entry).insts_mut();
+        insts.cursor_mut(position).insert_key_after(store).unwrap();
+        insts.cursor_mut(store).insert_key_after(load).unwrap();
+        position = load;
+    }
+}
+
+/// 每个块参数对应一个 alloc，前驱在跳转前把实参写进去，基本块开头再读出来
+fn lower_block_params(func_data: &mut FunctionData){
+    let blocks: Vec<BasicBlock> = func_data.layout().bbs().keys().copied()
+        .filter(|&bb| !func_data.dfg().bb(bb).params().is_empty())
+        .collect();
+    if blocks.is_empty(){
+        return;
+    }
+    let entry = func_data.layout().entry_bb().unwrap();
+    let mut slots: HashMap<BasicBlock, Vec<Value>> = HashMap::new();
+    for &bb in &blocks{
+        let count = func_data.dfg().bb(bb).params().len();
+        let bb_slots: Vec<Value> = (0..count).map(|_| func_data.dfg_mut().new_value().alloc(Type::get_i32())).collect();
+        for &slot in &bb_slots{
+            func_data.layout_mut().bb_mut(entry).insts_mut().push_key_front(slot).unwrap();
+        }
+        slots.insert(bb, bb_slots);
+    }
+
+    // 前驱：在终结指令前写入实参，然后去掉实参
+    let terminators: Vec<(BasicBlock, Value)> = func_data.layout().bbs().iter()
+        .filter_map(|(&bb, node)| node.insts().back_key().map(|&inst| (bb, inst)))
+        .collect();
+    for (bb, term) in terminators{
+        let mut data = func_data.dfg().value(term).clone();
+        // (写入实参的块, 写在这条指令之前, 目标块, 实参)
+        let edges = match data.kind_mut(){
+            ValueKind::Jump(jump) => vec![(bb, term, jump.target(), std::mem::take(jump.args_mut()))],
+            ValueKind::Branch(branch) => {
+                let true_args = std::mem::take(branch.true_args_mut());
+                let false_args = std::mem::take(branch.false_args_mut());
+                let (true_bb, false_bb) = (branch.true_bb(), branch.false_bb());
+                if true_bb == false_bb && !false_args.is_empty(){
+                    // 两条边去往同一个块，false 边的写入放到新拆出的块里
+                    let (split, jump) = split_edge(func_data, bb, false_bb);
+                    *branch.false_bb_mut() = split;
+                    vec![(bb, term, true_bb, true_args), (split, jump, false_bb, false_args)]
+                } else {
+                    vec![(bb, term, true_bb, true_args), (bb, term, false_bb, false_args)]
+                }
+            }
+            _ => continue,
+        };
+        if edges.iter().all(|(_, _, _, args)| args.is_empty()){
+            continue;
+        }
+        for (at, before, target, args) in edges{
+            for (&slot, arg) in slots.get(&target).map_or(&[][..], |v| v.as_slice()).iter().zip(args){
+                let store = func_data.dfg_mut().new_value().store(arg, slot);
+                func_data.layout_mut().bb_mut(at).insts_mut().cursor_mut(before).insert_key_before(store).unwrap();
+            }
+        }
+        func_data.dfg_mut().replace_value_with(term).raw(data);
+    }
+
+    // 基本块开头读出参数的值
+    let mut replace: HashMap<Value, Value> = HashMap::new();
+    for &bb in &blocks{
+        let params = func_data.dfg().bb(bb).params().to_vec();
+        for (&param, &slot) in params.iter().zip(&slots[&bb]).rev(){
+            let load = func_data.dfg_mut().new_value().load(slot);
+            func_data.layout_mut().bb_mut(bb).insts_mut().push_key_front(load).unwrap();
+            replace.insert(param, load);
+        }
+    }
+    replace_uses(func_data, &replace);
+    for &bb in &blocks{
+        let params = std::mem::take(func_data.dfg_mut().bb_mut(bb).params_mut());
+        for param in params{
+            func_data.dfg_mut().remove_value(param);
+        }
+    }
+}
+
+/// 在 `from` 之后插入一个只包含跳转到 `to` 的块，返回新块和其中的跳转
+fn split_edge(func_data: &mut FunctionData, from: BasicBlock, to: BasicBlock) -> (BasicBlock, Value){
+    let name = func_data.dfg().bb(from).name().clone().map(|name| format!("{}_split", name));
+    let split = func_data.dfg_mut().new_bb().basic_block(name);
+    let jump = func_data.dfg_mut().new_value().jump(to);
+    func_data.layout_mut().bbs_mut().cursor_mut(from).insert_key_after(split).unwrap();
+    func_data.layout_mut().bb_mut(split).insts_mut().push_key_back(jump).unwrap();
+    (split, jump)
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::interpreter::riscv;
+    use crate::{compile, Emit, OptLevel, Options, RegAllocKind};
+
+    fn run(source: &str) -> Vec<Vec<u8>>{
+        RegAllocKind::ALL.iter().map(|&reg_alloc| {
+            let options = Options{emit: vec![Emit::Asm], opt_level: OptLevel::O1, reg_alloc, inline_threshold: Some(0)};
+            let output = compile(source, &options).unwrap();
+            riscv::run(output.get(Emit::Asm).unwrap(), b"", Some(1_000_000)).unwrap().output
+        }).collect()
+    }
+
+    #[test]
+    fn parameters_stored_to_globals_are_lowered(){
+        let source = "int g; void set(int x) { g = x; } int main() { set(3); putint(g); return 0; }";
+        for output in run(source){
+            assert_eq!(output, b"3");
+        }
+    }
+
+    #[test]
+    fn parameters_stored_after_a_call_are_lowered(){
+        // b 作为块参数的实参在 putint 之后才存进 alloc，这时 a1 已经被调用改掉了
+        let source = "int f(int a, int b) { putint(a); if (a > 5) { b = 1; } putint(b); return 0; }\n\
+            int main() { f(0, 7); return 0; }";
+        for output in run(source){
+            assert_eq!(output, b"07");
+        }
+    }
+}
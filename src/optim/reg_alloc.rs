@@ -1,47 +1,200 @@
-use std::collections::{HashMap, VecDeque};
-use std::hash::Hash;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use koopa::ir::{Function, FunctionData, Value, ValueKind, Program};
-use crate::optim::cfg::{Interval, IntervalHandler};
-use lazy_static::lazy_static;
+use crate::optim::cfg::{ActiveAnalysis, ActiveVar, Interval, IntervalHandler};
+use crate::optim::split::Split;
+
+/// 分配结果里的寄存器编号：0-11 是被调用者保存的 s0-s11，12-19 是调用者保存的 a0-a7。
+/// t0-t6 留给后端做临时寄存器
+pub const CALLEE_SAVED: i32 = 12;
+pub const NUM_REGS: i32 = 20;
+
+pub fn reg_name(idx: i32) -> String{
+    if idx < CALLEE_SAVED{
+        format!("s{}", idx)
+    } else {
+        format!("a{}", idx - CALLEE_SAVED)
+    }
+}
+
+pub fn is_callee_saved(idx: i32) -> bool{
+    idx < CALLEE_SAVED
+}
+
+/// 一个函数按调用约定能用哪些寄存器
+pub(crate) struct RegClasses{
+    /// 调用之后仍然活跃的值，调用会破坏 a 寄存器，它们只能放在 s 寄存器里
+    across_call: HashSet<Value>,
+    /// 可用的调用者保存寄存器；形参所在的 a 寄存器在入口处还要读，不参与分配
+    caller_saved: Vec<i32>,
+}
+impl RegClasses{
+    pub(crate) fn new(func_data: &FunctionData, active: &ActiveVar, values: &HashSet<Value>) -> RegClasses{
+        let params = func_data.params().len().min(8) as i32;
+        RegClasses{
+            across_call: live_across_calls(func_data, active, values),
+            caller_saved: (CALLEE_SAVED + params..NUM_REGS).collect(),
+        }
+    }
+    pub(crate) fn across_call(&self, value: Value) -> bool{
+        self.across_call.contains(&value)
+    }
+    /// 按优先顺序列出能用的寄存器。不跨调用的值先用 a 寄存器，
+    /// 这样序言和尾声里不用保存恢复
+    pub(crate) fn registers(&self, across_call: bool) -> Vec<i32>{
+        if across_call{
+            (0..CALLEE_SAVED).collect()
+        } else {
+            self.caller_saved.iter().copied().chain(0..CALLEE_SAVED).collect()
+        }
+    }
+    pub(crate) fn candidates(&self, value: Value) -> Vec<i32>{
+        self.registers(self.across_call(value))
+    }
+}
+
+/// 指令定义和使用的、参与分配的值。写标量 alloc 是对它的重新定义，
+/// alloc 本身不产生代码
+pub(crate) fn def_use(func_data: &FunctionData, inst: Value, values: &HashSet<Value>) -> (Option<Value>, Vec<Value>){
+    let kind = func_data.dfg().value(inst).kind();
+    let (def, uses): (Option<Value>, Vec<Value>) = match kind{
+        ValueKind::Store(store) if values.contains(&store.dest())
+            && matches!(func_data.dfg().value(store.dest()).kind(), ValueKind::Alloc(_)) =>
+            (Some(store.dest()), vec![store.value()]),
+        ValueKind::Alloc(_) => (None, Vec::new()),
+        _ => (Some(inst), kind.value_uses().collect()),
+    };
+    (def.filter(|v| values.contains(v)), uses.into_iter().filter(|v| values.contains(v)).collect())
+}
+
+/// 从基本块出口的活跃集合逐条指令倒推，找出在某条 call 之后仍然活跃的值
+fn live_across_calls(func_data: &FunctionData, active: &ActiveVar, values: &HashSet<Value>) -> HashSet<Value>{
+    let mut across = HashSet::new();
+    for (&bb, node) in func_data.layout().bbs(){
+        let mut live: HashSet<Value> = active.live_out(bb)
+            .map(|set| set.intersection(values).copied().collect())
+            .unwrap_or_default();
+        let insts: Vec<Value> = node.insts().keys().copied().collect();
+        for inst in insts.into_iter().rev(){
+            let (def, uses) = def_use(func_data, inst, values);
+            if let Some(def) = def{
+                live.remove(&def);
+            }
+            if let ValueKind::Call(_) = func_data.dfg().value(inst).kind(){
+                across.extend(live.iter().copied());
+            }
+            live.extend(uses);
+        }
+    }
+    across
+}
+
+/// 寄存器分配算法，对应命令行的 `--regalloc=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RegAllocKind{
+    /// 在合并后的活跃区间上做线性扫描
+    #[default]
+    LinearScan,
+    /// 迭代合并的图着色（见 `graph_coloring`）
+    GraphColoring,
+}
+impl RegAllocKind{
+    pub const ALL: [RegAllocKind; 2] = [RegAllocKind::LinearScan, RegAllocKind::GraphColoring];
+    pub fn name(&self) -> &'static str{
+        match self{
+            RegAllocKind::LinearScan => "linear",
+            RegAllocKind::GraphColoring => "graph",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<RegAllocKind>{
+        RegAllocKind::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
+/// 一个函数的分配结果统计。move 指从标量 alloc 读出、写入标量 alloc 的复制，
+/// 两端分到同一个寄存器时这条复制就被合并掉了
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllocStats{
+    pub function: String,
+    pub values: usize,
+    pub spilled: usize,
+    /// 溢出的值里在循环中拆分出寄存器的个数
+    pub split: usize,
+    pub registers: usize,
+    /// 用到的被调用者保存寄存器，序言里要保存它们
+    pub callee_saved: usize,
+    pub moves: usize,
+    pub coalesced: usize,
+}
+impl AllocStats{
+    pub fn new(func_data: &FunctionData, result: &HashMap<Value, Option<i32>>, splits: &[(Value, Split)]) -> AllocStats{
+        let registers: HashSet<i32> = result.values().flatten().copied().collect();
+        let is_scalar_alloc = |value: Value| result.contains_key(&value)
+            && matches!(func_data.dfg().value(value).kind(), ValueKind::Alloc(_));
+        let mut moves = 0;
+        let mut coalesced = 0;
+        for node in func_data.layout().bbs().nodes(){
+            for &inst in node.insts().keys(){
+                let (dest, src) = match func_data.dfg().value(inst).kind(){
+                    ValueKind::Load(load) if is_scalar_alloc(load.src()) => (inst, load.src()),
+                    ValueKind::Store(store) if is_scalar_alloc(store.dest()) => (store.dest(), store.value()),
+                    _ => continue,
+                };
+                if let (Some(d), Some(s)) = (result.get(&dest), result.get(&src)){
+                    moves += 1;
+                    if d.is_some() && d == s{
+                        coalesced += 1;
+                    }
+                }
+            }
+        }
+        AllocStats{
+            function: func_data.name().to_string(),
+            values: result.len(),
+            spilled: result.values().filter(|reg| reg.is_none()).count(),
+            split: splits.len(),
+            registers: registers.len(),
+            callee_saved: registers.iter().filter(|&&reg| is_callee_saved(reg)).count(),
+            moves,
+            coalesced,
+        }
+    }
+}
+impl fmt::Display for AllocStats{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "fun {}: {} values in {} registers ({} callee-saved), {} spilled ({} split in loops), {} of {} moves coalesced",
+               self.function, self.values, self.registers, self.callee_saved, self.spilled, self.split, self.coalesced, self.moves)
+    }
+}
 
 struct RegAllocator{
-    reg_pool: VecDeque<i32>,
+    reg_pool: HashSet<i32>,
     val_use_reg:  HashMap<Value, i32>,
     reg_store_val: HashMap<i32, Value>
 }
 impl RegAllocator{
     fn new() -> RegAllocator{
-        let mut reg_pool = VecDeque::new();
-        for i in 0..12{
-            reg_pool.push_front(i);
-        }
-        RegAllocator{reg_pool, val_use_reg: HashMap::with_capacity(12), reg_store_val:
-        HashMap::with_capacity(12)}
-    }
-    fn alloc_reg(&mut self, val: Value) -> Option<i32>{
-        if let Some(reg) = self.reg_pool.pop_back(){
-            self.val_use_reg.insert(val, reg);
-            self.reg_store_val.insert(reg, val);
-            Some(reg)
-        } else {
-            None
-        }
+        RegAllocator{reg_pool: (0..NUM_REGS).collect(), val_use_reg: HashMap::with_capacity(NUM_REGS as usize),
+            reg_store_val: HashMap::with_capacity(NUM_REGS as usize)}
+    }
+    /// 从 `candidates` 里按顺序取第一个空闲的寄存器
+    fn alloc_reg(&mut self, val: Value, candidates: &[i32]) -> Option<i32>{
+        let reg = candidates.iter().copied().find(|reg| self.reg_pool.contains(reg))?;
+        self.reg_pool.remove(&reg);
+        self.val_use_reg.insert(val, reg);
+        self.reg_store_val.insert(reg, val);
+        Some(reg)
     }
     /// if reg is invalid, do nothing
     /// if not in val_use_reg means this value is allocated to the stack, just do nothing
-    fn free_reg(&mut self, val: Value, func_data: &FunctionData){
-        if self.val_use_reg.contains_key(&val){
-            let val = self.val_use_reg.remove(&val).unwrap();
-            self.reg_store_val.remove(&val);
-            self.reg_pool.push_back(val);
+    fn free_reg(&mut self, val: Value){
+        if let Some(reg) = self.val_use_reg.remove(&val){
+            self.reg_store_val.remove(&reg);
+            self.reg_pool.insert(reg);
         }
     }
     fn reset(&mut self){
-        let mut reg_pool = VecDeque::new();
-        for i in 0..12{
-            reg_pool.push_front(i);
-        }
-        self.reg_pool = reg_pool;
+        self.reg_pool = (0..NUM_REGS).collect();
         self.reg_store_val.clear();
         self.val_use_reg.clear();
     }
@@ -57,25 +210,62 @@ impl RegAlloc for Program{
     fn reg_alloc(&self, all_interval: HashMap<Function, HashMap<Value, Interval>>) ->
                                                                                  HashMap<Function,
         HashMap<Value, Option<i32>>>{
+        let (active, _) = self.active_analysis();
+        let classes: HashMap<Function, RegClasses> = all_interval.iter().map(|(&func, interval)|{
+            let values: HashSet<Value> = interval.keys().copied().collect();
+            (func, RegClasses::new(self.func(func), &active[&func], &values))
+        }).collect();
         let mut reg_allocator = RegAllocator::new();
-        let mut hanles = IntervalHandler::new(all_interval);
+        let mut hanles = IntervalHandler::new(self, all_interval);
         let mut global_result = HashMap::new();
         for (func, handle) in &mut hanles{
             let mut result = HashMap::new();
-            let mut dequeue = VecDeque::new();
+            let classes = &classes[func];
             reg_allocator.reset();
             for (value, out_of_use) in handle{
                 for val in out_of_use{
-                    reg_allocator.free_reg(val, self.func(func.clone()));
+                    reg_allocator.free_reg(val);
                 }
-                dequeue.push_back((value, reg_allocator.alloc_reg(value)));
+                result.insert(value, reg_allocator.alloc_reg(value, &classes.candidates(value)));
             }
-            for (value, reg_idx) in dequeue{
-                result.insert(value, reg_idx);
-            }
-            global_result.insert(func.clone(), result);
+            global_result.insert(*func, result);
 
         }
         global_result
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests{
+    use crate::{compile, Emit, Options, OptLevel, RegAllocKind};
+
+    #[test]
+    fn only_values_live_across_calls_use_callee_saved_registers(){
+        let source = "int leaf(int a, int b) { int c = a * b; int d = a + b; return c - d; }\n\
+            int main() { int x = getint(); int y = leaf(x, 3); return leaf(y, x) + y; }";
+        for reg_alloc in RegAllocKind::ALL{
+            let options = Options{emit: vec![Emit::RegAlloc, Emit::Asm], opt_level: OptLevel::O1, reg_alloc,
+                                  inline_threshold: Some(0)};
+            let output = compile(source, &options).unwrap();
+            let stats = output.get(Emit::RegAlloc).unwrap();
+            assert!(stats.contains("fun @leaf: ") && stats.lines().next().unwrap().contains("(0 callee-saved)"), "{}", stats);
+            // x 跨过第一次调用，y 跨过第二次调用
+            assert!(stats.lines().nth(1).unwrap().contains("(2 callee-saved)"), "{}", stats);
+            let asm = output.get(Emit::Asm).unwrap();
+            let leaf = &asm[asm.find("leaf:").unwrap()..asm.find("main:").unwrap()];
+            assert!(!leaf.contains("sw s"), "{}", leaf);
+        }
+    }
+
+    #[test]
+    fn dead_definitions_get_a_register_of_their_own(){
+        // 循环里 `id()` 的结果没有用，以前它没有区间，写回时会覆盖别的值的寄存器，循环不会结束
+        let source = "int g[3]; int id() { return 0; }\n\
+            int main() { int v = 0; id(); if (g[v]) {} int i = 0; while (i < 1) { i = 1; v = id(); } return 0; }";
+        for reg_alloc in RegAllocKind::ALL{
+            let options = Options{emit: vec![Emit::Asm], opt_level: OptLevel::O0, reg_alloc, inline_threshold: Some(0)};
+            let output = compile(source, &options).unwrap();
+            let execution = crate::interpreter::riscv::run(output.get(Emit::Asm).unwrap(), b"", Some(100_000));
+            assert_eq!(execution.map(|e| e.exit_code), Ok(0), "{:?}", reg_alloc);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, FunctionData, Program, Value, ValueKind};
+use crate::optim::utils::{edge_args, predecessors, remove_block_params, remove_blocks, remove_inst, replace_uses, successors};
+
+/// 稀疏条件常量传播：折叠操作数为常量的二元运算，条件为常量的分支换成跳转，
+/// 删除不可达的基本块。应在 mem2reg 之后运行，块参数也参与传播。
+pub fn sccp(program: &mut Program){
+    for func in program.func_layout().to_vec(){
+        let func_data = program.func_mut(func);
+        if func_data.layout().entry_bb().is_some(){
+            let solver = Solver::solve(func_data);
+            rewrite(func_data, &solver);
+        }
+    }
+}
+
+/// 按 koopa 的语义计算二元运算，除零时返回 None
+pub fn eval_binary(op: BinaryOp, l: i32, r: i32) -> Option<i32>{
+    if matches!(op, BinaryOp::Div | BinaryOp::Mod) && r == 0{
+        return None;
+    }
+    Some(match op{
+        BinaryOp::NotEq => (l != r) as i32,
+        BinaryOp::Eq => (l == r) as i32,
+        BinaryOp::Gt => (l > r) as i32,
+        BinaryOp::Lt => (l < r) as i32,
+        BinaryOp::Ge => (l >= r) as i32,
+        BinaryOp::Le => (l <= r) as i32,
+        BinaryOp::Add => l.wrapping_add(r),
+        BinaryOp::Sub => l.wrapping_sub(r),
+        BinaryOp::Mul => l.wrapping_mul(r),
+        BinaryOp::Div => l.wrapping_div(r),
+        BinaryOp::Mod => l.wrapping_rem(r),
+        BinaryOp::And => l & r,
+        BinaryOp::Or => l | r,
+        BinaryOp::Xor => l ^ r,
+        BinaryOp::Shl => l.wrapping_shl(r as u32),
+        BinaryOp::Shr => (l as u32).wrapping_shr(r as u32) as i32,
+        BinaryOp::Sar => l.wrapping_shr(r as u32),
+    })
+}
+
+/// 格上的值：还没有算出（Top）、常量、不是常量（Bottom）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lattice{
+    Top,
+    Const(i32),
+    Bottom,
+}
+impl Lattice{
+    fn meet(self, other: Lattice) -> Lattice{
+        match (self, other){
+            (Lattice::Top, x) | (x, Lattice::Top) => x,
+            (Lattice::Const(a), Lattice::Const(b)) if a == b => Lattice::Const(a),
+            _ => Lattice::Bottom,
+        }
+    }
+}
+
+struct Solver{
+    values: HashMap<Value, Lattice>,
+    blocks: HashSet<BasicBlock>,
+    edges: HashSet<(BasicBlock, BasicBlock)>,
+}
+
+impl Solver{
+    fn solve(func_data: &FunctionData) -> Solver{
+        let preds = predecessors(func_data);
+        let mut solver = Solver{values: HashMap::new(), blocks: HashSet::new(), edges: HashSet::new()};
+        let entry = func_data.layout().entry_bb().unwrap();
+        let mut flow_work: Vec<(Option<BasicBlock>, BasicBlock)> = vec![(None, entry)];
+        let mut ssa_work: Vec<Value> = Vec::new();
+        while !flow_work.is_empty() || !ssa_work.is_empty(){
+            while let Some((from, to)) = flow_work.pop(){
+                if let Some(from) = from{
+                    if !solver.edges.insert((from, to)){
+                        continue;
+                    }
+                }
+                if solver.blocks.insert(to){
+                    solver.visit_params(func_data, &preds, to, &mut ssa_work);
+                    for &inst in func_data.layout().bbs().node(&to).unwrap().insts().keys(){
+                        solver.visit_inst(func_data, &preds, inst, &mut flow_work, &mut ssa_work);
+                    }
+                } else {
+                    // 已经执行过的块多了一条入边，只有参数可能改变
+                    solver.visit_params(func_data, &preds, to, &mut ssa_work);
+                }
+            }
+            while let Some(value) = ssa_work.pop(){
+                for &user in func_data.dfg().value(value).used_by(){
+                    let executable = func_data.layout().parent_bb(user).is_some_and(|bb| solver.blocks.contains(&bb));
+                    if executable{
+                        solver.visit_inst(func_data, &preds, user, &mut flow_work, &mut ssa_work);
+                    }
+                }
+            }
+        }
+        solver
+    }
+
+    fn get(&self, func_data: &FunctionData, value: Value) -> Lattice{
+        if let Some(&lattice) = self.values.get(&value){
+            return lattice;
+        }
+        // 全局变量不在函数的数据流图里；还没访问过的指令和块参数是 Top
+        match func_data.dfg().values().get(&value).map(|data| data.kind()){
+            Some(ValueKind::Integer(int)) => Lattice::Const(int.value()),
+            Some(ValueKind::FuncArgRef(_)) | None => Lattice::Bottom,
+            Some(_) => Lattice::Top,
+        }
+    }
+
+    fn set(&mut self, value: Value, lattice: Lattice, ssa_work: &mut Vec<Value>){
+        if self.values.insert(value, lattice) != Some(lattice){
+            ssa_work.push(value);
+        }
+    }
+
+    /// 块参数的值是所有可执行入边上实参的交汇
+    fn visit_params(&mut self, func_data: &FunctionData, preds: &HashMap<BasicBlock, Vec<BasicBlock>>,
+                    bb: BasicBlock, ssa_work: &mut Vec<Value>){
+        let params = func_data.dfg().bb(bb).params();
+        if params.is_empty(){
+            return;
+        }
+        let mut incoming = vec![Lattice::Top; params.len()];
+        for &pred in &preds[&bb]{
+            if !self.edges.contains(&(pred, bb)){
+                continue;
+            }
+            for args in edge_args(func_data, pred, bb){
+                for (lattice, &arg) in incoming.iter_mut().zip(args){
+                    *lattice = lattice.meet(self.get(func_data, arg));
+                }
+            }
+        }
+        for (&param, lattice) in params.iter().zip(incoming){
+            self.set(param, lattice, ssa_work);
+        }
+    }
+
+    fn visit_inst(&mut self, func_data: &FunctionData, preds: &HashMap<BasicBlock, Vec<BasicBlock>>, inst: Value,
+                  flow_work: &mut Vec<(Option<BasicBlock>, BasicBlock)>, ssa_work: &mut Vec<Value>){
+        let bb = func_data.layout().parent_bb(inst).unwrap();
+        match func_data.dfg().value(inst).kind(){
+            ValueKind::Binary(bin) => {
+                let lattice = match (self.get(func_data, bin.lhs()), self.get(func_data, bin.rhs())){
+                    (Lattice::Const(l), Lattice::Const(r)) => eval_binary(bin.op(), l, r).map_or(Lattice::Bottom, Lattice::Const),
+                    (Lattice::Bottom, _) | (_, Lattice::Bottom) => Lattice::Bottom,
+                    _ => Lattice::Top,
+                };
+                self.set(inst, lattice, ssa_work);
+            }
+            ValueKind::Branch(branch) => {
+                let targets = match self.get(func_data, branch.cond()){
+                    Lattice::Top => vec![],
+                    Lattice::Const(0) => vec![branch.false_bb()],
+                    Lattice::Const(_) => vec![branch.true_bb()],
+                    Lattice::Bottom => vec![branch.true_bb(), branch.false_bb()],
+                };
+                self.follow(func_data, preds, bb, targets, flow_work, ssa_work);
+            }
+            ValueKind::Jump(jump) => self.follow(func_data, preds, bb, vec![jump.target()], flow_work, ssa_work),
+            ValueKind::Store(_) | ValueKind::Return(_) => {}
+            _ => self.set(inst, Lattice::Bottom, ssa_work),
+        }
+    }
+
+    /// 标记从 `bb` 出发的可执行边；已经走过的边上实参可能变了，重新计算目标块的参数
+    fn follow(&mut self, func_data: &FunctionData, preds: &HashMap<BasicBlock, Vec<BasicBlock>>, bb: BasicBlock,
+              targets: Vec<BasicBlock>, flow_work: &mut Vec<(Option<BasicBlock>, BasicBlock)>, ssa_work: &mut Vec<Value>){
+        for target in targets{
+            if self.edges.contains(&(bb, target)){
+                self.visit_params(func_data, preds, target, ssa_work);
+            } else {
+                flow_work.push((Some(bb), target));
+            }
+        }
+    }
+
+    fn constant(&self, value: Value) -> Option<i32>{
+        match self.values.get(&value){
+            Some(Lattice::Const(c)) => Some(*c),
+            _ => None,
+        }
+    }
+}
+
+fn rewrite(func_data: &mut FunctionData, solver: &Solver){
+    let layout: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+    let (live, dead): (Vec<BasicBlock>, Vec<BasicBlock>) = layout.into_iter().partition(|bb| solver.blocks.contains(bb));
+
+    // 常量替换成整数，折叠掉的指令和参数随后删除
+    let mut replace: HashMap<Value, Value> = HashMap::new();
+    let mut folded: Vec<Value> = Vec::new();
+    let mut const_params: HashMap<BasicBlock, HashSet<usize>> = HashMap::new();
+    for &bb in &live{
+        let params = func_data.dfg().bb(bb).params().to_vec();
+        for (i, param) in params.into_iter().enumerate(){
+            if let Some(c) = solver.constant(param){
+                replace.insert(param, func_data.dfg_mut().new_value().integer(c));
+                const_params.entry(bb).or_default().insert(i);
+            }
+        }
+        let insts: Vec<Value> = func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for inst in insts{
+            if let Some(c) = solver.constant(inst){
+                replace.insert(inst, func_data.dfg_mut().new_value().integer(c));
+                folded.push(inst);
+            }
+        }
+    }
+    replace_uses(func_data, &replace);
+    for inst in folded{
+        remove_inst(func_data, inst);
+    }
+
+    // 只有一条出边可执行的分支换成跳转
+    for &bb in &live{
+        let term = *func_data.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap();
+        let (target, args) = match func_data.dfg().value(term).kind(){
+            ValueKind::Branch(branch) => {
+                let taken: Vec<BasicBlock> = successors(func_data, bb).into_iter()
+                    .filter(|&succ| solver.edges.contains(&(bb, succ)))
+                    .collect();
+                if taken.len() != 1 || branch.true_bb() == branch.false_bb(){
+                    continue;
+                }
+                if taken[0] == branch.true_bb(){
+                    (branch.true_bb(), branch.true_args().to_vec())
+                } else {
+                    (branch.false_bb(), branch.false_args().to_vec())
+                }
+            }
+            _ => continue,
+        };
+        func_data.dfg_mut().replace_value_with(term).jump_with_args(target, args);
+    }
+
+    remove_blocks(func_data, &dead);
+    remove_block_params(func_data, &const_params);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::optim::mem2reg::mem2reg;
+    use crate::optim::test_utils::{binary_ops, block, block_insts, block_names, callees, func, integer, program};
+
+    #[test]
+    fn constants_flow_through_branches_and_loops(){
+        let mut program = program("int main() { int a = 3; int b = a * 4 + 1; if (b > 10) b = b - 1; else b = getint(); \
+            int i = 0; while (i < b) { i = i + b; } return b - i + 100; }");
+        mem2reg(&mut program);
+        sccp(&mut program);
+        let main = func(&program, "@main");
+        let func_data = program.func(main);
+        // 条件恒为真，else 分支连同其中的调用一起删除
+        assert!(!block_names(func_data).contains(&"%else_1".to_string()));
+        assert!(callees(&program, main).is_empty());
+        assert!(!binary_ops(func_data).contains(&BinaryOp::Mul));
+        // b 折叠成 12，汇合块不再需要参数；循环变量 i 不是常量
+        assert!(func_data.dfg().bb(block(func_data, "%end_1")).params().is_empty());
+        let cond = block(func_data, "%while_entry_2");
+        assert_eq!(func_data.dfg().bb(cond).params().len(), 1);
+        let compare = block_insts(func_data, cond)[0];
+        match func_data.dfg().value(compare).kind(){
+            ValueKind::Binary(binary) => assert_eq!(integer(func_data, binary.rhs()), Some(12)),
+            kind => panic!("{:?}", kind),
+        }
+    }
+
+    #[test]
+    fn unknown_values_are_kept(){
+        let mut program = program("int main() { int x = getint(); int y = 1; if (x) y = 2; return y + 0 / x; }");
+        mem2reg(&mut program);
+        sccp(&mut program);
+        let func_data = program.func(func(&program, "@main"));
+        let entry = func_data.layout().entry_bb().unwrap();
+        let term = *block_insts(func_data, entry).last().unwrap();
+        assert!(matches!(func_data.dfg().value(term).kind(), ValueKind::Branch(_)));
+        assert_eq!(func_data.dfg().bb(block(func_data, "%end_1")).params().len(), 1);
+        // 除数可能为零，不能折叠
+        assert_eq!(binary_ops(func_data), vec![BinaryOp::Div, BinaryOp::Add]);
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BinaryOp, FunctionData, Program, Value, ValueKind};
+use crate::optim::utils::{remove_insts, replace_uses};
+
+/// 算术化简：消去 `x+0`、`x*1`、`x-x` 这类恒等式，乘以 2 的幂或者 2^a±2^b 换成移位和加减，
+/// 除以、模 2 的幂换成移位序列。除以其他常数留给后端用乘法实现。应在 sccp 之后运行。
+pub fn simplify(program: &mut Program){
+    for func in program.func_layout().to_vec(){
+        let func_data = program.func_mut(func);
+        if func_data.layout().entry_bb().is_some(){
+            simplify_function(func_data);
+        }
+    }
+}
+
+fn simplify_function(func_data: &mut FunctionData){
+    let insts: Vec<Value> = func_data.layout().bbs().nodes()
+        .flat_map(|node| node.insts().keys().copied())
+        .collect();
+    let mut replaced: HashMap<Value, Value> = HashMap::new();
+    for inst in insts{
+        let (op, lhs, rhs) = match func_data.dfg().value(inst).kind(){
+            ValueKind::Binary(binary) => (binary.op(), binary.lhs(), binary.rhs()),
+            _ => continue,
+        };
+        let resolve = |v: Value| *replaced.get(&v).unwrap_or(&v);
+        let mut e = Emitter{func_data: &mut *func_data, before: inst};
+        if let Some(value) = reduce(&mut e, op, resolve(lhs), resolve(rhs)){
+            replaced.insert(inst, value);
+        }
+    }
+    let removed: Vec<Value> = replaced.keys().copied().collect();
+    replace_uses(func_data, &replaced);
+    remove_insts(func_data, removed);
+}
+
+/// 在 `before` 之前插入新指令
+struct Emitter<'a>{
+    func_data: &'a mut FunctionData,
+    before: Value,
+}
+impl Emitter<'_>{
+    fn int(&mut self, value: i32) -> Value{
+        self.func_data.dfg_mut().new_value().integer(value)
+    }
+    fn binary(&mut self, op: BinaryOp, lhs: Value, rhs: i32) -> Value{
+        let rhs = self.int(rhs);
+        self.binary_value(op, lhs, rhs)
+    }
+    fn binary_value(&mut self, op: BinaryOp, lhs: Value, rhs: Value) -> Value{
+        let value = self.func_data.dfg_mut().new_value().binary(op, lhs, rhs);
+        let bb = self.func_data.layout().parent_bb(self.before).unwrap();
+        self.func_data.layout_mut().bb_mut(bb).insts_mut().cursor_mut(self.before).insert_key_before(value).unwrap();
+        value
+    }
+    fn neg(&mut self, value: Value) -> Value{
+        let zero = self.int(0);
+        self.binary_value(BinaryOp::Sub, zero, value)
+    }
+    fn constant(&self, value: Value) -> Option<i32>{
+        match self.func_data.dfg().values().get(&value).map(|data| data.kind()){
+            Some(ValueKind::Integer(i)) => Some(i.value()),
+            _ => None,
+        }
+    }
+}
+
+/// 化简后的值，不能化简时返回 None
+fn reduce(e: &mut Emitter, op: BinaryOp, mut lhs: Value, mut rhs: Value) -> Option<Value>{
+    let commutative = matches!(op, BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or
+        | BinaryOp::Xor | BinaryOp::Eq | BinaryOp::NotEq);
+    if commutative && e.constant(lhs).is_some() && e.constant(rhs).is_none(){
+        std::mem::swap(&mut lhs, &mut rhs);
+    }
+    if lhs == rhs && e.constant(lhs).is_none(){
+        return match op{
+            BinaryOp::Sub | BinaryOp::Xor | BinaryOp::NotEq | BinaryOp::Lt | BinaryOp::Gt => Some(e.int(0)),
+            BinaryOp::Eq | BinaryOp::Le | BinaryOp::Ge => Some(e.int(1)),
+            BinaryOp::And | BinaryOp::Or => Some(lhs),
+            _ => None,
+        };
+    }
+    let c = e.constant(rhs)?;
+    if e.constant(lhs).is_some(){
+        return None;
+    }
+    match (op, c){
+        (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Or | BinaryOp::Xor | BinaryOp::Shl | BinaryOp::Shr | BinaryOp::Sar, 0) => Some(lhs),
+        (BinaryOp::Mul | BinaryOp::And, 0) => Some(e.int(0)),
+        (BinaryOp::Mul | BinaryOp::Div, 1) | (BinaryOp::And, -1) => Some(lhs),
+        (BinaryOp::Mul | BinaryOp::Div, -1) => Some(e.neg(lhs)),
+        (BinaryOp::Mod, 1 | -1) => Some(e.int(0)),
+        (BinaryOp::Mul, c) if c > 0 => multiply(e, lhs, c as u32),
+        (BinaryOp::Div, c) if c != i32::MIN && c.unsigned_abs().is_power_of_two() => {
+            let k = c.unsigned_abs().trailing_zeros() as i32;
+            let biased = bias(e, lhs, k);
+            let q = e.binary(BinaryOp::Sar, biased, k);
+            Some(if c < 0 {e.neg(q)} else {q})
+        }
+        // 余数的符号跟被除数，与除数的符号无关
+        (BinaryOp::Mod, c) if c != i32::MIN && c.unsigned_abs().is_power_of_two() => {
+            let k = c.unsigned_abs().trailing_zeros() as i32;
+            let biased = bias(e, lhs, k);
+            let rounded = e.binary(BinaryOp::And, biased, -(1 << k));
+            Some(e.binary_value(BinaryOp::Sub, lhs, rounded))
+        }
+        _ => None,
+    }
+}
+
+/// `x * c`：c 是 2 的幂、2^a+2^b 或 2^a-2^b 时换成移位和加减
+fn multiply(e: &mut Emitter, x: Value, c: u32) -> Option<Value>{
+    let shifted = |e: &mut Emitter, k: u32| if k == 0 {x} else {e.binary(BinaryOp::Shl, x, k as i32)};
+    if c.is_power_of_two(){
+        return Some(shifted(e, c.trailing_zeros()));
+    }
+    let low = c & c.wrapping_neg();
+    if (c - low).is_power_of_two(){
+        let (a, b) = (shifted(e, (c - low).trailing_zeros()), shifted(e, low.trailing_zeros()));
+        return Some(e.binary_value(BinaryOp::Add, a, b));
+    }
+    // c = 2^a - 2^b：加上最低位之后只剩一位
+    match c.checked_add(low){
+        Some(high) if high.is_power_of_two() && high < 1 << 31 => {
+            let (a, b) = (shifted(e, high.trailing_zeros()), shifted(e, low.trailing_zeros()));
+            Some(e.binary_value(BinaryOp::Sub, a, b))
+        }
+        _ => None,
+    }
+}
+
+/// 负数除以 2^k 要向零取整，先加上 2^k-1
+fn bias(e: &mut Emitter, x: Value, k: i32) -> Value{
+    let sign = if k == 1 {x} else {e.binary(BinaryOp::Sar, x, 31)};
+    let adjust = e.binary(BinaryOp::Shr, sign, 32 - k);
+    e.binary_value(BinaryOp::Add, x, adjust)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::optim::mem2reg::mem2reg;
+    use crate::optim::sccp::sccp;
+    use crate::optim::test_utils::{binary_ops, func, insts, integer, program};
+
+    #[test]
+    fn constants_become_shifts(){
+        let mut program = program("int main() { int x = getint(); int y = getint(); \
+            putint(x * 8 + x * 10 + x * 7 + x * 1 + 0); putint(x / 4 + x % 8 + x / -2); putint((x - x) + y * 3 / 5); return 0; }");
+        // sccp 先把 `-2` 折叠成常量
+        mem2reg(&mut program);
+        sccp(&mut program);
+        simplify(&mut program);
+        let func_data = program.func(func(&program, "@main"));
+        let ops = binary_ops(func_data);
+        let count = |op: BinaryOp| ops.iter().filter(|&&o| o == op).count();
+        assert_eq!((count(BinaryOp::Mul), count(BinaryOp::Mod)), (0, 0), "{:?}", ops);
+        // x * 8、x * 10 (两个)、x * 7、y * 3 各需要移位
+        assert_eq!(count(BinaryOp::Shl), 5, "{:?}", ops);
+        // 除以 5 留给后端，`+ 0` 和 `x - x` 都消去了
+        for inst in insts(func_data){
+            if let ValueKind::Binary(binary) = func_data.dfg().value(inst).kind(){
+                let rhs = integer(func_data, binary.rhs());
+                assert_eq!(binary.op() == BinaryOp::Div, rhs == Some(5), "{:?}", ops);
+                assert!(rhs != Some(0) && binary.lhs() != binary.rhs(), "{:?}", ops);
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Value, ValueKind};
+use crate::optim::cfg::{ActiveAnalysis, ActiveVar};
+use crate::optim::loops::LoopForest;
+use crate::optim::reg_alloc::{def_use, RegClasses};
+use crate::optim::utils::{predecessors, successors};
+
+/// 溢出的值在一个循环里分到的寄存器。循环外值仍然在栈上，
+/// 进入循环前读进寄存器，循环里改过的值在出口写回栈上
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Split{
+    pub reg: i32,
+    /// 值在这些基本块里放在 `reg` 中
+    pub blocks: HashSet<BasicBlock>,
+    /// 这些块以 `jump` 进入循环，跳转前把值从栈上读进 `reg`
+    pub reload: Vec<BasicBlock>,
+    /// 这些出口块开头把 `reg` 写回栈上
+    pub spill: Vec<BasicBlock>,
+}
+
+pub trait LiveRangeSplit{
+    /// 在分配结果的基础上，给溢出的值在用到它的循环里找一个空闲的寄存器。
+    /// 每个值最多拆分一次，按决定的顺序返回
+    fn split_live_ranges(&self, alloc: &HashMap<Function, HashMap<Value, Option<i32>>>) ->
+        HashMap<Function, Vec<(Value, Split)>>;
+}
+impl LiveRangeSplit for Program{
+    fn split_live_ranges(&self, alloc: &HashMap<Function, HashMap<Value, Option<i32>>>) ->
+        HashMap<Function, Vec<(Value, Split)>>{
+        let (active, _) = self.active_analysis();
+        let mut result = HashMap::new();
+        for (&func, alloc) in alloc{
+            let func_data = self.func(func);
+            if alloc.values().all(|reg| reg.is_some()) || !active.contains_key(&func){
+                continue;
+            }
+            result.insert(func, split_function(func_data, &active[&func], alloc));
+        }
+        result
+    }
+}
+
+/// 一个基本块里的活跃信息
+#[derive(Default)]
+struct BlockInfo{
+    live_in: HashSet<Value>,
+    /// 在块里活跃、定义或使用过的值，它们的寄存器在这个块里不能挪作他用
+    touched: HashSet<Value>,
+    defs: HashSet<Value>,
+    /// 每个值在块里被定义、使用的次数
+    occurrences: HashMap<Value, usize>,
+    /// 块里有调用或返回，会改写 a 寄存器
+    clobbers_args: bool,
+}
+
+fn block_info(func_data: &FunctionData, active: &ActiveVar, values: &HashSet<Value>) -> HashMap<BasicBlock, BlockInfo>{
+    let mut infos = HashMap::new();
+    for (&bb, node) in func_data.layout().bbs(){
+        let mut info = BlockInfo::default();
+        let mut live: HashSet<Value> = active.live_out(bb)
+            .map(|set| set.intersection(values).copied().collect())
+            .unwrap_or_default();
+        info.touched.extend(live.iter().copied());
+        let insts: Vec<Value> = node.insts().keys().copied().collect();
+        for inst in insts.into_iter().rev(){
+            if matches!(func_data.dfg().value(inst).kind(), ValueKind::Call(_) | ValueKind::Return(_)){
+                info.clobbers_args = true;
+            }
+            let (def, uses) = def_use(func_data, inst, values);
+            for &v in def.iter().chain(&uses){
+                *info.occurrences.entry(v).or_insert(0) += 1;
+                info.touched.insert(v);
+            }
+            if let Some(def) = def{
+                live.remove(&def);
+                info.defs.insert(def);
+            }
+            live.extend(uses);
+        }
+        info.live_in = live;
+        infos.insert(bb, info);
+    }
+    infos
+}
+
+fn split_function(func_data: &FunctionData, active: &ActiveVar, alloc: &HashMap<Value, Option<i32>>) -> Vec<(Value, Split)>{
+    let values: HashSet<Value> = alloc.keys().copied().collect();
+    let classes = RegClasses::new(func_data, active, &values);
+    let infos = block_info(func_data, active, &values);
+    let preds = predecessors(func_data);
+    let entry = func_data.layout().entry_bb().unwrap();
+    // 外层循环在前，拆分的范围越大，读写栈的次数越少
+    let forest = LoopForest::new(func_data);
+    let layout: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+
+    // 寄存器在块里被占用：分配到的值用到它，或者已经拆分出的区间用到它
+    let mut busy: HashMap<BasicBlock, HashSet<i32>> = infos.iter().map(|(&bb, info)|{
+        (bb, info.touched.iter().filter_map(|v| alloc[v]).collect())
+    }).collect();
+    let mut split_busy: HashMap<BasicBlock, HashSet<i32>> = HashMap::new();
+
+    // 循环里用得越多的值越先拆分
+    let weight = |v: Value| -> usize{
+        infos.iter()
+            .map(|(bb, info)| info.occurrences.get(&v).copied().unwrap_or(0) * 10usize.pow(forest.depth(*bb).min(6)))
+            .sum()
+    };
+    let mut spilled: Vec<(Value, usize)> = func_data.layout().bbs().nodes()
+        .flat_map(|node| node.insts().keys().copied())
+        .filter(|v| alloc.get(v) == Some(&None))
+        .map(|v| (v, weight(v)))
+        .collect();
+    spilled.sort_by_key(|&(_, w)| std::cmp::Reverse(w));
+
+    let mut result = Vec::new();
+    for (value, _) in spilled{
+        for l in &forest.loops{
+            let (header, body) = (&l.header, &l.blocks);
+            if *header == entry || !body.iter().any(|bb| infos[bb].occurrences.contains_key(&value)){
+                continue;
+            }
+            let reload: Vec<BasicBlock> = if infos[header].live_in.contains(&value){
+                preds[header].iter().copied().filter(|p| !body.contains(p)).collect()
+            } else {
+                Vec::new()
+            };
+            // 读回的代码放在跳转之前，所以进入循环的边必须来自无条件跳转
+            let jumps_in = reload.iter().all(|&p| successors(func_data, p).len() == 1
+                && matches!(func_data.dfg().value(*func_data.layout().bbs().node(&p).unwrap().insts().back_key().unwrap()).kind(), ValueKind::Jump(_)));
+            if !jumps_in{
+                continue;
+            }
+            let mut spill: Vec<BasicBlock> = Vec::new();
+            if body.iter().any(|bb| infos[bb].defs.contains(&value)){
+                for &bb in &layout{
+                    if !body.contains(&bb){
+                        continue;
+                    }
+                    for succ in successors(func_data, bb){
+                        if !body.contains(&succ) && infos[&succ].live_in.contains(&value) && !spill.contains(&succ){
+                            spill.push(succ);
+                        }
+                    }
+                }
+            }
+            // 写回放在出口块开头，出口块只能从循环里进入
+            if !spill.iter().all(|e| preds[e].iter().all(|p| body.contains(p))){
+                continue;
+            }
+            let across_call = body.iter().any(|bb| infos[bb].clobbers_args);
+            let reg = classes.registers(across_call).into_iter().find(|reg|{
+                body.iter().all(|bb| !busy[bb].contains(reg))
+                    && reload.iter().all(|p| !split_busy.get(p).is_some_and(|set| set.contains(reg)))
+            });
+            let reg = match reg{
+                Some(reg) => reg,
+                None => continue,
+            };
+            for &bb in body.iter().chain(&reload).chain(&spill){
+                busy.get_mut(&bb).unwrap().insert(reg);
+                split_busy.entry(bb).or_default().insert(reg);
+            }
+            result.push((value, Split{reg, blocks: body.clone(), reload, spill}));
+            break;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::{compile, Emit, Options, OptLevel};
+
+    #[test]
+    fn spilled_values_get_registers_inside_loops(){
+        let mut source = String::from("int main() {\n");
+        for i in 0..20{
+            source += &format!("int a{} = getint();\n", i);
+        }
+        source += "int i = 0; int s = 0; while (i < 10) {\n";
+        for i in 0..20{
+            source += &format!("s = s + a{} * (i - {});\n", i, i);
+        }
+        source += "i = i + 1; }\nputint(s";
+        for i in 0..20{
+            source += &format!(" + a{}", i);
+        }
+        source += "); return 0; }";
+        let options = Options{emit: vec![Emit::RegAlloc, Emit::Asm], opt_level: OptLevel::O1, ..Options::default()};
+        let output = compile(&source, &options).unwrap();
+        let stats = output.get(Emit::RegAlloc).unwrap();
+        assert!(!stats.contains("(0 split in loops)"), "{}", stats);
+        // 跳进循环之前从栈上读回
+        let asm = output.get(Emit::Asm).unwrap();
+        let before = &asm[..asm.find("\nwhile_entry_1:").unwrap()];
+        let last = before.lines().rfind(|line| line.starts_with('\t')).unwrap();
+        assert!(last.trim_start().starts_with("lw "), "{}", before);
+    }
+}
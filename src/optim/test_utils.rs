@@ -0,0 +1,61 @@
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Program, Value, ValueKind};
+
+/// 解析源程序并翻译成未经优化的 Koopa IR
+pub fn program(source: &str) -> Program{
+    let ast = crate::sysy::CompUnitParser::new().parse(source).unwrap();
+    crate::front_end::parser::build_program(&ast).program
+}
+
+/// 名为 `name`（带 `@`）的函数
+pub fn func(program: &Program, name: &str) -> Function{
+    *program.func_layout().iter().find(|&&f| program.func(f).name() == name)
+        .unwrap_or_else(|| panic!("no function {}", name))
+}
+
+/// 名为 `name`（带 `%`）的基本块，不要求它还在布局里
+pub fn block(func_data: &FunctionData, name: &str) -> BasicBlock{
+    *func_data.dfg().bbs().iter().find(|(_, data)| data.name().as_deref() == Some(name))
+        .unwrap_or_else(|| panic!("no basic block {}", name)).0
+}
+
+/// 布局中的基本块名字，按布局顺序
+pub fn block_names(func_data: &FunctionData) -> Vec<String>{
+    func_data.layout().bbs().keys()
+        .map(|&bb| func_data.dfg().bb(bb).name().clone().unwrap_or_default())
+        .collect()
+}
+
+/// 基本块里的指令，按布局顺序
+pub fn block_insts(func_data: &FunctionData, bb: BasicBlock) -> Vec<Value>{
+    func_data.layout().bbs().node(&bb).expect("basic block not in layout").insts().keys().copied().collect()
+}
+
+/// 函数里的所有指令，按布局顺序
+pub fn insts(func_data: &FunctionData) -> Vec<Value>{
+    func_data.layout().bbs().nodes().flat_map(|node| node.insts().keys().copied()).collect()
+}
+
+/// 函数里所有二元运算的运算符，按布局顺序
+pub fn binary_ops(func_data: &FunctionData) -> Vec<BinaryOp>{
+    insts(func_data).into_iter().filter_map(|inst| match func_data.dfg().value(inst).kind(){
+        ValueKind::Binary(binary) => Some(binary.op()),
+        _ => None,
+    }).collect()
+}
+
+/// 值是整数常量时给出它的值
+pub fn integer(func_data: &FunctionData, value: Value) -> Option<i32>{
+    match func_data.dfg().values().get(&value).map(|data| data.kind()){
+        Some(ValueKind::Integer(i)) => Some(i.value()),
+        _ => None,
+    }
+}
+
+/// 函数里每条调用指令的被调函数名，按布局顺序
+pub fn callees(program: &Program, func: Function) -> Vec<String>{
+    let func_data = program.func(func);
+    insts(func_data).into_iter().filter_map(|inst| match func_data.dfg().value(inst).kind(){
+        ValueKind::Call(call) => Some(program.func(call.callee()).name().to_string()),
+        _ => None,
+    }).collect()
+}
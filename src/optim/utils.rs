@@ -0,0 +1,240 @@
+use std::collections::{HashMap, HashSet};
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, FunctionData, Type, Value, ValueKind};
+
+/// 基本块最后一条指令（终结指令）的后继，按 true/false 的顺序给出
+pub fn successors(func_data: &FunctionData, bb: BasicBlock) -> Vec<BasicBlock>{
+    let node = func_data.layout().bbs().node(&bb).expect("basic block not in layout");
+    match node.insts().back_key().map(|&inst| func_data.dfg().value(inst).kind()){
+        Some(ValueKind::Jump(jump)) => vec![jump.target()],
+        Some(ValueKind::Branch(branch)) => vec![branch.true_bb(), branch.false_bb()],
+        _ => Vec::new(),
+    }
+}
+
+/// 每个基本块的前驱，顺序与布局中前驱出现的顺序一致；同一条边只记录一次
+pub fn predecessors(func_data: &FunctionData) -> HashMap<BasicBlock, Vec<BasicBlock>>{
+    let mut preds: HashMap<BasicBlock, Vec<BasicBlock>> =
+        func_data.layout().bbs().keys().map(|&bb| (bb, Vec::new())).collect();
+    for &bb in func_data.layout().bbs().keys(){
+        for succ in successors(func_data, bb){
+            let list = preds.entry(succ).or_default();
+            if !list.contains(&bb){
+                list.push(bb);
+            }
+        }
+    }
+    preds
+}
+
+/// `from` 跳转到 `to` 时传入的实参，两条分支去往同一个块时有两组
+pub fn edge_args(func_data: &FunctionData, from: BasicBlock, to: BasicBlock) -> Vec<&[Value]>{
+    let node = func_data.layout().bbs().node(&from).expect("basic block not in layout");
+    match node.insts().back_key().map(|&inst| func_data.dfg().value(inst).kind()){
+        Some(ValueKind::Jump(jump)) if jump.target() == to => vec![jump.args()],
+        Some(ValueKind::Branch(branch)) => {
+            let mut args = Vec::new();
+            if branch.true_bb() == to{
+                args.push(branch.true_args());
+            }
+            if branch.false_bb() == to{
+                args.push(branch.false_args());
+            }
+            args
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// 用 `f` 改写指令的每个操作数（包括跳转参数）
+pub fn map_operands<F: FnMut(Value) -> Value>(kind: &mut ValueKind, mut f: F){
+    let mut apply = |v: &mut Value| *v = f(*v);
+    match kind{
+        ValueKind::Load(load) => apply(load.src_mut()),
+        ValueKind::Store(store) => {
+            apply(store.value_mut());
+            apply(store.dest_mut());
+        }
+        ValueKind::GetPtr(get_ptr) => {
+            apply(get_ptr.src_mut());
+            apply(get_ptr.index_mut());
+        }
+        ValueKind::GetElemPtr(gep) => {
+            apply(gep.src_mut());
+            apply(gep.index_mut());
+        }
+        ValueKind::Binary(bin) => {
+            apply(bin.lhs_mut());
+            apply(bin.rhs_mut());
+        }
+        ValueKind::Branch(branch) => {
+            apply(branch.cond_mut());
+            branch.true_args_mut().iter_mut().for_each(&mut apply);
+            branch.false_args_mut().iter_mut().for_each(&mut apply);
+        }
+        ValueKind::Jump(jump) => jump.args_mut().iter_mut().for_each(apply),
+        ValueKind::Call(call) => call.args_mut().iter_mut().for_each(apply),
+        ValueKind::Return(ret) => ret.value_mut().iter_mut().for_each(apply),
+        _ => {}
+    }
+}
+
+/// 把函数中对 `map` 里各个值的使用替换成对应的新值。
+/// koopa 的 `replace_value_with` 会清空被替换值的 used_by，所以这里按定义先于使用的顺序
+/// 重建函数中的每条指令，让使用关系随着后面的使用者重新建立起来。
+pub fn replace_uses(func_data: &mut FunctionData, map: &HashMap<Value, Value>){
+    if map.is_empty(){
+        return;
+    }
+    for inst in def_use_order(func_data){
+        let mut data = func_data.dfg().value(inst).clone();
+        map_operands(data.kind_mut(), |v| *map.get(&v).unwrap_or(&v));
+        func_data.dfg_mut().replace_value_with(inst).raw(data);
+    }
+}
+
+/// 布局中的指令按操作数先于使用者的顺序排列
+fn def_use_order(func_data: &FunctionData) -> Vec<Value>{
+    let insts: Vec<Value> = func_data.layout().bbs().nodes()
+        .flat_map(|node| node.insts().keys().copied())
+        .collect();
+    let in_layout: HashSet<Value> = insts.iter().copied().collect();
+    let mut visited: HashSet<Value> = HashSet::new();
+    let mut order = Vec::with_capacity(insts.len());
+    for inst in insts{
+        // 显式栈：(指令, 是否已经压入了它的操作数)
+        let mut stack = vec![(inst, false)];
+        while let Some((value, expanded)) = stack.pop(){
+            if expanded{
+                order.push(value);
+                continue;
+            }
+            if !visited.insert(value){
+                continue;
+            }
+            stack.push((value, true));
+            for operand in func_data.dfg().value(value).kind().value_uses(){
+                if in_layout.contains(&operand) && !visited.contains(&operand){
+                    stack.push((operand, false));
+                }
+            }
+        }
+    }
+    order
+}
+
+/// 从布局和数据流图中删除一条已经不再被使用的指令
+pub fn remove_inst(func_data: &mut FunctionData, inst: Value){
+    let bb = func_data.layout().parent_bb(inst).expect("instruction not in layout");
+    func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+    func_data.dfg_mut().remove_value(inst);
+}
+
+/// 创建一组新的基本块参数，还没有挂到任何基本块上。
+/// koopa 只能在创建基本块时生成参数，因此借一个临时块创建后再转移出来。
+pub fn new_block_params(func_data: &mut FunctionData, tys: Vec<Type>) -> Vec<Value>{
+    let dfg = func_data.dfg_mut();
+    let tmp = dfg.new_bb().basic_block_with_params(None, tys);
+    let params = std::mem::take(dfg.bb_mut(tmp).params_mut());
+    dfg.remove_bb(tmp);
+    params
+}
+
+/// 删除 `removed` 中各个块的指定下标的参数，这些参数必须已经不再被使用。
+/// 跳转到这些块的指令同时去掉对应的实参；留下的参数重新创建，保证下标连续。
+pub fn remove_block_params(func_data: &mut FunctionData, removed: &HashMap<BasicBlock, HashSet<usize>>){
+    let removed: HashMap<BasicBlock, &HashSet<usize>> = removed.iter()
+        .filter(|(_, indices)| !indices.is_empty())
+        .map(|(&bb, indices)| (bb, indices))
+        .collect();
+    if removed.is_empty(){
+        return;
+    }
+    let mut replace: HashMap<Value, Value> = HashMap::new();
+    let mut new_params: HashMap<BasicBlock, Vec<Value>> = HashMap::new();
+    for (&bb, indices) in &removed{
+        let kept: Vec<Value> = func_data.dfg().bb(bb).params().iter().enumerate()
+            .filter(|(i, _)| !indices.contains(i))
+            .map(|(_, &param)| param)
+            .collect();
+        let tys = kept.iter().map(|&param| func_data.dfg().value(param).ty().clone()).collect();
+        let params = new_block_params(func_data, tys);
+        for (&old, &new) in kept.iter().zip(&params){
+            let name = func_data.dfg().value(old).name().clone();
+            func_data.dfg_mut().set_value_name(new, name);
+            replace.insert(old, new);
+        }
+        new_params.insert(bb, params);
+    }
+    replace_uses(func_data, &replace);
+
+    let keep = |args: &mut Vec<Value>, target: BasicBlock|{
+        if let Some(indices) = removed.get(&target){
+            let mut i = 0;
+            args.retain(|_|{
+                i += 1;
+                !indices.contains(&(i - 1))
+            });
+        }
+    };
+    let terminators: Vec<Value> = func_data.layout().bbs().nodes()
+        .filter_map(|node| node.insts().back_key().copied())
+        .collect();
+    for term in terminators{
+        let mut data = func_data.dfg().value(term).clone();
+        match data.kind_mut(){
+            ValueKind::Jump(jump) if removed.contains_key(&jump.target()) => {
+                let target = jump.target();
+                keep(jump.args_mut(), target);
+            }
+            ValueKind::Branch(branch) if removed.contains_key(&branch.true_bb()) || removed.contains_key(&branch.false_bb()) => {
+                let (true_bb, false_bb) = (branch.true_bb(), branch.false_bb());
+                keep(branch.true_args_mut(), true_bb);
+                keep(branch.false_args_mut(), false_bb);
+            }
+            _ => continue,
+        }
+        func_data.dfg_mut().replace_value_with(term).raw(data);
+    }
+
+    for (bb, params) in new_params{
+        let old = std::mem::replace(func_data.dfg_mut().bb_mut(bb).params_mut(), params);
+        for param in old{
+            func_data.dfg_mut().remove_value(param);
+        }
+    }
+}
+
+/// 删除一组基本块以及其中的全部指令。块中的值只能被这组块中的指令使用，
+/// 其他块也不能再跳转到这些块。
+pub fn remove_blocks(func_data: &mut FunctionData, blocks: &[BasicBlock]){
+    let mut insts: Vec<Value> = Vec::new();
+    for bb in blocks{
+        insts.extend(func_data.layout().bbs().node(bb).expect("basic block not in layout").insts().keys().copied());
+    }
+    remove_insts(func_data, insts);
+    for bb in blocks{
+        func_data.layout_mut().bbs_mut().remove(bb);
+        func_data.dfg_mut().remove_bb(*bb);
+    }
+}
+
+/// 删除一组指令，它们只能被这组指令使用。使用者先于被使用的值删除
+pub fn remove_insts(func_data: &mut FunctionData, mut insts: Vec<Value>){
+    for &inst in &insts{
+        let bb = func_data.layout().parent_bb(inst).expect("instruction not in layout");
+        func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+    }
+    while !insts.is_empty(){
+        let before = insts.len();
+        insts.retain(|&inst|{
+            if func_data.dfg().value(inst).used_by().is_empty(){
+                func_data.dfg_mut().remove_value(inst);
+                false
+            } else {
+                true
+            }
+        });
+        assert!(insts.len() < before, "removed instructions are still used elsewhere");
+    }
+}
@@ -0,0 +1,213 @@
+// 快照测试：`tests/golden` 下的每个 `name.sy` 旁边放着各阶段的期望结果
+//   name.koopa  前端生成的 Koopa IR
+//   name.S      -O0 生成的汇编
+//   name.out    程序的标准输出
+//   name.exit   `main` 的返回值
+//   name.in     可选，程序的标准输入
+// 各优化等级下解释执行 IR、模拟执行汇编的结果都要和 `name.out`、`name.exit` 一致。
+//
+//   cargo test --test golden -- [--bless] [FILTER...]
+//
+// `--bless` 用当前的输出更新快照（运行结果以 -O0 解释执行 IR 为准），
+// FILTER 只运行名字里包含其中某个串的用例。
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use sysy_compiler::interpreter::{ir, riscv, Execution};
+use sysy_compiler::{compile, compile_to_koopa, Emit, OptLevel, Options, RegAllocKind};
+
+/// 每次运行最多执行的指令条数，防止死循环的程序卡住测试
+const MAX_STEPS: u64 = 100_000_000;
+
+struct Case{
+    name: String,
+    source: PathBuf,
+}
+impl Case{
+    fn snapshot(&self, extension: &str) -> PathBuf{
+        self.source.with_extension(extension)
+    }
+}
+
+#[derive(Default)]
+struct Report{
+    /// 与快照不一致的地方
+    failures: Vec<String>,
+    /// 更新过的快照
+    blessed: Vec<PathBuf>,
+}
+
+fn main(){
+    let mut bless = false;
+    let mut filters = Vec::new();
+    for arg in std::env::args().skip(1){
+        match arg.as_str(){
+            "--bless" => bless = true,
+            // `cargo test` 传给所有测试的参数
+            _ if arg.starts_with('-') => {}
+            _ => filters.push(arg),
+        }
+    }
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut cases: Vec<Case> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "sy"))
+        .map(|source| Case{name: source.file_stem().unwrap().to_string_lossy().into_owned(), source})
+        .filter(|case| filters.is_empty() || filters.iter().any(|f| case.name.contains(f.as_str())))
+        .collect();
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let start = Instant::now();
+    let next = AtomicUsize::new(0);
+    let reports = Mutex::new(Vec::new());
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(cases.len().max(1));
+    std::thread::scope(|scope|{
+        for _ in 0..threads{
+            scope.spawn(||{
+                while let Some(case) = cases.get(next.fetch_add(1, Ordering::Relaxed)){
+                    let report = check(case, bless);
+                    reports.lock().unwrap().push((case.name.clone(), report));
+                }
+            });
+        }
+    });
+    let mut reports = reports.into_inner().unwrap();
+    reports.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut failed = 0;
+    let mut blessed = 0;
+    for (name, report) in &reports{
+        for path in &report.blessed{
+            println!("blessed {}", path.display());
+        }
+        blessed += !report.blessed.is_empty() as usize;
+        if report.failures.is_empty(){
+            println!("ok      {}", name);
+        } else {
+            failed += 1;
+            println!("FAILED  {}", name);
+            for failure in &report.failures{
+                for line in failure.lines(){
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
+    println!("\ngolden: {} passed, {} failed, {} blessed, {} cases in {:.2}s",
+             reports.len() - failed, failed, blessed, reports.len(), start.elapsed().as_secs_f64());
+    if failed > 0{
+        if !bless{
+            println!("run `cargo test --test golden -- --bless` to accept the new output");
+        }
+        std::process::exit(1);
+    }
+}
+
+fn check(case: &Case, bless: bool) -> Report{
+    let mut report = Report::default();
+    let source = fs::read_to_string(&case.source).unwrap();
+    let input = fs::read(case.snapshot("in")).unwrap_or_default();
+
+    let options = Options{emit: vec![Emit::Koopa, Emit::Asm], ..Options::default()};
+    let output = match compile(&source, &options){
+        Ok(output) => output,
+        Err(diagnostics) => {
+            report.failures.push(format!("compilation failed with {} diagnostics", diagnostics.list.len()));
+            return report;
+        }
+    };
+    compare(case, "koopa", output.get(Emit::Koopa).unwrap(), bless, &mut report);
+    compare(case, "S", output.get(Emit::Asm).unwrap(), bless, &mut report);
+
+    // 所有优化等级和寄存器分配方式下运行的结果都应该一样
+    let mut runs: Vec<(String, Result<Execution, String>)> = Vec::new();
+    for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2]{
+        let options = Options{opt_level, ..Options::default()};
+        let execution = compile_to_koopa(&source, &options).map_err(|_| "compilation failed".to_string())
+            .and_then(|(program, _)| ir::run(&program, &input, Some(MAX_STEPS)).map_err(|e| e.to_string()));
+        runs.push((format!("koopa {:?}", opt_level), execution));
+        for reg_alloc in RegAllocKind::ALL{
+            let options = Options{emit: vec![Emit::Asm], opt_level, reg_alloc, ..Options::default()};
+            let execution = compile(&source, &options).map_err(|_| "compilation failed".to_string())
+                .and_then(|output| riscv::run(output.get(Emit::Asm).unwrap(), &input, Some(MAX_STEPS))
+                    .map_err(|e| e.to_string()));
+            runs.push((format!("riscv {:?} --regalloc={}", opt_level, reg_alloc.name()), execution));
+        }
+    }
+    let mut expected = None;
+    for (stage, execution) in runs{
+        let execution = match execution{
+            Ok(execution) => execution,
+            Err(e) => {
+                report.failures.push(format!("{}: {}", stage, e));
+                continue;
+            }
+        };
+        let stdout = String::from_utf8_lossy(&execution.output).into_owned();
+        let exit = format!("{}\n", execution.exit_code);
+        match &expected{
+            // 第一个成功的运行和快照比较，之后的运行和它比较
+            None => {
+                let failures = report.failures.len();
+                compare(case, "out", &stdout, bless, &mut report);
+                compare(case, "exit", &exit, bless, &mut report);
+                for failure in &mut report.failures[failures..]{
+                    *failure = format!("{}: {}", stage, failure);
+                }
+                expected = Some((stdout, exit, stage));
+            }
+            Some((expected_stdout, expected_exit, first)) => {
+                if stdout != *expected_stdout{
+                    report.failures.push(format!("{}: stdout differs from {}\n{}", stage, first, diff(expected_stdout, &stdout)));
+                }
+                if exit != *expected_exit{
+                    report.failures.push(format!("{}: returned {} but {} returned {}", stage, exit.trim(), first,
+                                                 expected_exit.trim()));
+                }
+            }
+        }
+    }
+    report
+}
+
+/// 把 `actual` 和快照比较，`bless` 时直接写入快照
+fn compare(case: &Case, extension: &str, actual: &str, bless: bool, report: &mut Report){
+    let path = case.snapshot(extension);
+    let expected = fs::read_to_string(&path).ok();
+    if expected.as_deref() == Some(actual){
+        return;
+    }
+    if bless{
+        fs::write(&path, actual).unwrap();
+        report.blessed.push(path);
+        return;
+    }
+    match expected{
+        None => report.failures.push(format!("missing snapshot {}", path.display())),
+        Some(expected) => report.failures.push(format!("{} differs\n{}", path.display(), diff(&expected, actual))),
+    }
+}
+
+/// 从第一处不同开始给出几行对比
+fn diff(expected: &str, actual: &str) -> String{
+    const CONTEXT: usize = 2;
+    const SHOWN: usize = 6;
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let first = expected.iter().zip(&actual).take_while(|(e, a)| e == a).count();
+    let last = expected[first..].iter().rev().zip(actual[first..].iter().rev()).take_while(|(e, a)| e == a).count();
+    let mut text = format!("first difference at line {}\n", first + 1);
+    for line in &expected[first.saturating_sub(CONTEXT)..first]{
+        text += &format!("  {}\n", line);
+    }
+    for line in expected[first..expected.len() - last].iter().take(SHOWN){
+        text += &format!("- {}\n", line);
+    }
+    for line in actual[first..actual.len() - last].iter().take(SHOWN){
+        text += &format!("+ {}\n", line);
+    }
+    text
+}